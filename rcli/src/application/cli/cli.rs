@@ -1,5 +1,11 @@
 use crate::application::cli::commands;
+use crate::application::cli::commands::kill::RunMode;
+use crate::utils::matcher::Matcher;
+use crate::utils::process::KillOptions;
+use crate::utils::signal::{self, Signal};
+use crate::utils::socket::SocketFilter;
 use clap::{Parser, Subcommand};
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "rcli")]
@@ -19,7 +25,60 @@ pub enum Commands {
     Inquire {},
 
     #[command(about = "kill progress by port or program name")]
-    Kill { port_or_program_name: String },
+    Kill {
+        #[arg(
+            required_unless_present_any = ["exact", "cmd", "user", "ppid"],
+            help = "comma separated ports (5500), port ranges (3000-3010) or names to match, any of them"
+        )]
+        port_or_program_name: Option<String>,
+        #[arg(long, help = "match programs whose name is exactly this")]
+        exact: Option<String>,
+        #[arg(
+            long,
+            help = "match programs whose full command line matches this regex"
+        )]
+        cmd: Option<String>,
+        #[arg(long, help = "match programs owned by this user name or uid")]
+        user: Option<String>,
+        #[arg(long, help = "match programs whose parent pid is this")]
+        ppid: Option<u32>,
+        #[arg(
+            long,
+            help = "match programs meeting any condition instead of all of them"
+        )]
+        any: bool,
+        #[arg(long, help = "only match tcp sockets when killing by port")]
+        tcp: bool,
+        #[arg(long, help = "only match udp sockets when killing by port")]
+        udp: bool,
+        #[arg(long, help = "only match listening sockets when killing by port")]
+        listening: bool,
+        #[arg(
+            short,
+            long,
+            default_value = "TERM",
+            value_parser = str::parse::<Signal>,
+            help = "signal to send: TERM, INT, HUP, QUIT, KILL, USR1, USR2, ABRT, ALRM"
+        )]
+        signal: Signal,
+        #[arg(
+            long,
+            value_parser = signal::parse_duration,
+            help = "send SIGKILL if the program is still running after this long, e.g. 5s or 500ms; ignored for HUP, USR1, USR2 and ALRM"
+        )]
+        grace: Option<Duration>,
+        #[arg(short, long, help = "kill all matched programs without prompt")]
+        yes: bool,
+        #[arg(long, conflicts_with = "yes", help = "only list matched programs")]
+        dry_run: bool,
+        #[arg(long, help = "print matched programs and kill results as json")]
+        json: bool,
+        #[arg(
+            long,
+            help = "also kill child processes of matched programs, children first"
+        )]
+        tree: bool,
+    },
 }
 
 pub fn dispatch_command() {
@@ -33,9 +92,81 @@ pub fn dispatch_command() {
         }
         Commands::Kill {
             port_or_program_name,
+            exact,
+            cmd,
+            user,
+            ppid,
+            any,
+            tcp,
+            udp,
+            listening,
+            signal,
+            grace,
+            yes,
+            dry_run,
+            json,
+            tree,
         } => {
-            let name_or_ports: Vec<&str> = port_or_program_name.split(',').collect();
-            commands::kill::run(name_or_ports);
+            let matcher = match kill_matcher(port_or_program_name, exact, cmd, user, ppid, any) {
+                Ok(matcher) => matcher,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+            let filter = SocketFilter {
+                tcp,
+                udp,
+                listening,
+            };
+            let mode = RunMode { yes, dry_run, json };
+            // 没匹配到或者有进程没 kill 掉时退出码为 1，方便脚本判断
+            if !commands::kill::run(
+                matcher,
+                filter,
+                KillOptions {
+                    signal,
+                    grace,
+                    tree,
+                },
+                mode,
+            ) {
+                std::process::exit(1);
+            }
         }
     }
 }
+
+// 各个条件默认都要满足，--any 时满足一个就行
+fn kill_matcher(
+    port_or_program_name: Option<String>,
+    exact: Option<String>,
+    cmd: Option<String>,
+    user: Option<String>,
+    ppid: Option<u32>,
+    any: bool,
+) -> Result<Matcher, String> {
+    let mut matchers = vec![];
+    if let Some(port_or_program_name) = port_or_program_name {
+        matchers.push(Matcher::from_terms(
+            port_or_program_name.split(',').collect(),
+        ));
+    }
+    if let Some(exact) = exact {
+        matchers.push(Matcher::ExactName(exact));
+    }
+    if let Some(cmd) = cmd {
+        matchers.push(Matcher::cmdline(&cmd)?);
+    }
+    if let Some(user) = user {
+        matchers.push(Matcher::user(&user)?);
+    }
+    if let Some(ppid) = ppid {
+        matchers.push(Matcher::Parent(ppid));
+    }
+    Ok(if any {
+        Matcher::Any(matchers)
+    } else {
+        Matcher::All(matchers)
+    })
+}
@@ -1,60 +1,230 @@
+use crate::utils::matcher::Matcher;
 use crate::utils::process;
-use crate::utils::process::Program;
+use crate::utils::process::{KillOptions, KillOutcome, KillReport, Program};
+use crate::utils::socket::SocketFilter;
 use crate::utils::text;
 use inquire;
 use inquire::list_option::ListOption;
+use serde_json::{Value, json};
 
-pub fn run(port_or_names: Vec<&str>) {
-    let matched_programs = process::fetch_all_matched_program(port_or_names);
+// 非交互的用法：--yes 全部 kill，--dry-run 只列出来，--json 输出给脚本用
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunMode {
+    pub yes: bool,
+    pub dry_run: bool,
+    pub json: bool,
+}
 
-    if matched_programs.len() > 0 {
-        // 多选要kill的进程
-        let programs_options: Vec<_> = matched_programs
-            .iter()
-            .enumerate()
-            .map(|(i, x)| {
-                format!(
-                    "{}. [{}] - ({}) - {}",
-                    text::pad_left(&(i + 1).to_string(), 3, '0'),
-                    text::pad_left(&*x.pid.to_string(), 5, '_'),
-                    text::pad_left(&*x.port.to_string(), 5, '_'),
-                    x.name.clone(),
-                )
-            })
-            .collect();
+// 返回是否成功：没有匹配到进程，或者有进程没 kill 掉都算失败
+pub fn run(matcher: Matcher, filter: SocketFilter, options: KillOptions, mode: RunMode) -> bool {
+    let matched_programs = process::fetch_all_matched_program(&matcher, &filter, options.tree);
+
+    if matched_programs.is_empty() {
+        if mode.json {
+            println!("{}", to_json(&matched_programs, &[], &options, mode));
+        } else {
+            println!("No match program found");
+        }
+        return false;
+    }
 
-        let formatted_output = |selected: &[ListOption<&String>]| {
-            let mut output_str = String::from("\n\nYou Selected:\n");
-            let options_str = selected
+    if mode.dry_run {
+        if mode.json {
+            println!("{}", to_json(&matched_programs, &[], &options, mode));
+        } else {
+            matched_programs
                 .iter()
-                .map(|opt| format!("* {}", opt.value)) // 获取每个选项的 `item` 字段
-                .collect::<Vec<String>>()
-                .join("\n");
-            output_str.push_str(&options_str);
+                .enumerate()
+                .for_each(|(i, program)| println!("{}", format_program(i, program)));
+        }
+        return true;
+    }
 
-            output_str
-        };
+    let choose_programs = if mode.yes {
+        matched_programs.clone()
+    } else {
+        match choose(&matched_programs) {
+            // 选中一个进程时，它的子孙也一起 kill
+            Some(indexes) if options.tree => process::with_descendants(&matched_programs, &indexes),
+            Some(indexes) => indexes
+                .into_iter()
+                .map(|index| matched_programs[index].clone())
+                .collect(),
+            None => return false,
+        }
+    };
 
-        let user_checked_kill_programs = inquire::MultiSelect::new(
-            "please choose what program you want to kill",
-            programs_options,
-        )
-        .with_page_size(100)
-        .with_formatter(&formatted_output)
-        .prompt()
-        .unwrap();
+    let reports = process::kill_programs(choose_programs, !mode.json, &options);
+    if mode.json {
+        println!("{}", to_json(&matched_programs, &reports, &options, mode));
+    }
+    reports.iter().all(KillReport::success)
+}
+
+// --tree 时子进程按层级缩进
+fn format_program(i: usize, program: &Program) -> String {
+    let branch = match program.depth {
+        0 => String::new(),
+        depth => format!("{}└─ ", "   ".repeat(depth - 1)),
+    };
+    format!(
+        "{}. [{}] - ({}) - {branch}{}",
+        text::pad_left(&(i + 1).to_string(), 3, '0'),
+        text::pad_left(&program.pid.to_string(), 5, '_'),
+        text::pad_left(&program.socket_label(), 5, '_'),
+        program.name.clone(),
+    )
+}
+
+// 多选要kill的进程，返回选中的下标，取消选择时返回 None
+fn choose(matched_programs: &[Program]) -> Option<Vec<usize>> {
+    let programs_options: Vec<_> = matched_programs
+        .iter()
+        .enumerate()
+        .map(|(i, x)| format_program(i, x))
+        .collect();
 
-        let choose_programs: Vec<Program> = user_checked_kill_programs
+    let formatted_output = |selected: &[ListOption<&String>]| {
+        let mut output_str = String::from("\n\nYou Selected:\n");
+        let options_str = selected
+            .iter()
+            .map(|opt| format!("* {}", opt.value)) // 获取每个选项的 `item` 字段
+            .collect::<Vec<String>>()
+            .join("\n");
+        output_str.push_str(&options_str);
+
+        output_str
+    };
+
+    let user_checked_kill_programs = match inquire::MultiSelect::new(
+        "please choose what program you want to kill",
+        programs_options,
+    )
+    .with_page_size(100)
+    .with_formatter(&formatted_output)
+    .prompt()
+    {
+        Ok(checked) => checked,
+        Err(err) => {
+            println!("{err}, use --yes to kill without prompt");
+            return None;
+        }
+    };
+
+    Some(
+        user_checked_kill_programs
             .iter()
             .filter_map(|choose| {
                 let index_str = choose.split(".").next()?;
                 let index: usize = index_str.parse().ok()?;
-                matched_programs.get(index - 1).cloned()
+                index
+                    .checked_sub(1)
+                    .filter(|&index| index < matched_programs.len())
             })
-            .collect();
+            .collect(),
+    )
+}
 
-        process::kill_programs(choose_programs, true);
-    } else {
-        println!("No match program found");
-    }
+fn program_json(program: &Program) -> Value {
+    json!({
+        "pid": program.pid,
+        "name": program.name,
+        "port": program.port,
+        "protocol": program.protocol.map(|protocol| protocol.to_string()),
+        "state": program.state.map(|state| state.to_string()),
+        "depth": program.depth,
+    })
+}
+
+fn to_json(
+    matched: &[Program],
+    reports: &[KillReport],
+    options: &KillOptions,
+    mode: RunMode,
+) -> Value {
+    let results: Vec<Value> = reports
+        .iter()
+        .map(|report| {
+            json!({
+                "pid": report.program.pid,
+                "name": report.program.name,
+                "success": report.success(),
+                "escalated": report.result == Ok(KillOutcome::Escalated),
+                "already_exited": report.result == Ok(KillOutcome::AlreadyExited),
+                "error": report.result.as_ref().err(),
+            })
+        })
+        .collect();
+    json!({
+        "dry_run": mode.dry_run,
+        "signal": options.signal.to_string(),
+        "matched": matched.iter().map(program_json).collect::<Vec<Value>>(),
+        "results": results,
+    })
+}
+
+#[test]
+fn test_to_json() {
+    let program = Program {
+        name: "node".to_string(),
+        pid: 42,
+        port: 3000,
+        protocol: Some(crate::utils::socket::Protocol::Tcp),
+        state: Some(crate::utils::socket::SocketState::Listen),
+        depth: 0,
+    };
+    let reports = [KillReport {
+        program: program.clone(),
+        result: Err("Operation not permitted".to_string()),
+    }];
+    let value = to_json(
+        &[program],
+        &reports,
+        &KillOptions::default(),
+        RunMode::default(),
+    );
+    assert_eq!(value["signal"], "SIGTERM");
+    assert_eq!(value["matched"][0]["protocol"], "tcp");
+    assert_eq!(value["matched"][0]["state"], "LISTEN");
+    assert_eq!(value["results"][0]["success"], false);
+    assert_eq!(value["results"][0]["error"], "Operation not permitted");
+}
+
+#[test]
+fn test_run_without_prompt() {
+    let mut child = std::process::Command::new("sleep")
+        .arg("30.25")
+        .spawn()
+        .unwrap();
+    let matcher = || {
+        Matcher::All(vec![
+            // 别的测试也会起 sleep，用参数区分
+            Matcher::cmdline(r"^sleep 30\.25$").unwrap(),
+            Matcher::Parent(std::process::id()),
+        ])
+    };
+    let dry_run = RunMode {
+        dry_run: true,
+        json: true,
+        ..RunMode::default()
+    };
+    let yes = RunMode {
+        yes: true,
+        json: true,
+        ..RunMode::default()
+    };
+    let run_with = |mode| {
+        run(
+            matcher(),
+            SocketFilter::default(),
+            KillOptions::default(),
+            mode,
+        )
+    };
+
+    assert!(run_with(dry_run));
+    assert!(run_with(yes));
+    child.wait().unwrap();
+    // 已经 kill 掉了，什么都匹配不到，返回失败
+    assert!(!run_with(dry_run));
 }
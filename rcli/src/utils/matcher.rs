@@ -0,0 +1,158 @@
+use regex::Regex;
+use std::ops::RangeInclusive;
+use sysinfo::{Pid, Process, Uid, Users};
+
+use crate::utils::text;
+
+// 找要 kill 的进程的条件，All 要全部满足，Any 满足一个就行
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    // 名称包含
+    Name(String),
+    // 名称完全相同
+    ExactName(String),
+    // 对完整的命令行做正则匹配
+    Cmdline(Regex),
+    User(Uid),
+    Parent(u32),
+    // 持有的本地端口在范围内
+    Ports(RangeInclusive<u16>),
+    All(Vec<Matcher>),
+    Any(Vec<Matcher>),
+}
+
+#[test]
+fn test_parse_term() {
+    assert!(matches!(parse_term("5500"), Matcher::Ports(ports) if ports == (5500..=5500)));
+    assert!(matches!(parse_term("3000-3010"), Matcher::Ports(ports) if ports == (3000..=3010)));
+    assert!(matches!(parse_term("3010-3000"), Matcher::Name(_)));
+    assert!(matches!(parse_term("Google Chrome"), Matcher::Name(name) if name == "Google Chrome"));
+    assert!(matches!(parse_term("x-1"), Matcher::Name(_)));
+}
+
+// 端口（5500）、端口范围（3000-3010），其余的按名称包含匹配
+pub fn parse_term(term: &str) -> Matcher {
+    if text::is_valid_port(term) {
+        let port = term.parse::<u16>().unwrap();
+        return Matcher::Ports(port..=port);
+    }
+    let range = term.split_once('-').and_then(|(start, end)| {
+        if !text::is_valid_port(start) || !text::is_valid_port(end) {
+            return None;
+        }
+        let (start, end) = (start.parse::<u16>().ok()?, end.parse::<u16>().ok()?);
+        (start <= end).then_some(start..=end)
+    });
+    match range {
+        Some(ports) => Matcher::Ports(ports),
+        None => Matcher::Name(term.to_string()),
+    }
+}
+
+impl Matcher {
+    // 逗号分隔的多个端口或名称，满足一个就行
+    pub fn from_terms(terms: Vec<&str>) -> Matcher {
+        Matcher::Any(
+            terms
+                .into_iter()
+                .map(str::trim)
+                .filter(|term| !term.is_empty())
+                .map(parse_term)
+                .collect(),
+        )
+    }
+
+    pub fn cmdline(pattern: &str) -> Result<Matcher, String> {
+        Regex::new(pattern)
+            .map(Matcher::Cmdline)
+            .map_err(|err| format!("invalid command line regex: {err}"))
+    }
+
+    // 用户名或者 uid
+    pub fn user(name_or_uid: &str) -> Result<Matcher, String> {
+        let users = Users::new_with_refreshed_list();
+        users
+            .iter()
+            .find(|user| user.name() == name_or_uid)
+            .map(|user| user.id().clone())
+            .or_else(|| name_or_uid.parse::<Uid>().ok())
+            .map(Matcher::User)
+            .ok_or_else(|| format!("unknown user {name_or_uid}"))
+    }
+
+    // 用到的所有端口范围，按端口匹配前先查一次 socket
+    pub fn port_ranges(&self) -> Vec<RangeInclusive<u16>> {
+        match self {
+            Matcher::Ports(ports) => vec![ports.clone()],
+            Matcher::All(matchers) | Matcher::Any(matchers) => {
+                matchers.iter().flat_map(Matcher::port_ranges).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    // ports 是这个进程持有的、落在 port_ranges 里的本地端口
+    pub fn matches(&self, process: &Process, ports: &[u16]) -> bool {
+        match self {
+            Matcher::Name(name) => process.name().to_string_lossy().contains(name.as_str()),
+            Matcher::ExactName(name) => process.name().to_string_lossy() == name.as_str(),
+            Matcher::Cmdline(regex) => regex.is_match(&command_line(process)),
+            Matcher::User(uid) => process.user_id() == Some(uid),
+            Matcher::Parent(ppid) => process.parent() == Some(Pid::from_u32(*ppid)),
+            Matcher::Ports(range) => ports.iter().any(|port| range.contains(port)),
+            Matcher::All(matchers) => matchers
+                .iter()
+                .all(|matcher| matcher.matches(process, ports)),
+            Matcher::Any(matchers) => matchers
+                .iter()
+                .any(|matcher| matcher.matches(process, ports)),
+        }
+    }
+}
+
+pub fn command_line(process: &Process) -> String {
+    process
+        .cmd()
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// 不会被列出来的进程：rcli 自己和 init
+pub fn is_protected(pid: u32) -> bool {
+    pid == 1 || pid == std::process::id()
+}
+
+#[test]
+fn test_matches_process() {
+    let mut child = std::process::Command::new("sleep")
+        .arg("30.5")
+        .spawn()
+        .unwrap();
+    let system = sysinfo::System::new_all();
+    let process = system.process(Pid::from_u32(child.id())).unwrap();
+    let me = system.process(Pid::from_u32(std::process::id())).unwrap();
+
+    assert!(Matcher::ExactName("sleep".to_string()).matches(process, &[]));
+    assert!(!Matcher::ExactName("slee".to_string()).matches(process, &[]));
+    assert!(
+        Matcher::cmdline(r"^sleep 30\.5$")
+            .unwrap()
+            .matches(process, &[])
+    );
+    assert!(Matcher::Parent(std::process::id()).matches(process, &[]));
+    assert!(Matcher::User(me.user_id().unwrap().clone()).matches(process, &[]));
+
+    let both = Matcher::All(vec![
+        Matcher::Name("sleep".to_string()),
+        parse_term("3000-3010"),
+    ]);
+    assert!(!both.matches(process, &[]));
+    assert!(both.matches(process, &[3005]));
+    assert!(Matcher::from_terms(vec!["nothing", "sleep"]).matches(process, &[]));
+    assert!(is_protected(std::process::id()) && is_protected(1));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -1,4 +1,7 @@
 pub mod text;
 pub mod process;
 pub mod regex;
+pub mod signal;
+pub mod socket;
+pub mod matcher;
 
@@ -1,10 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+#[cfg(target_os = "macos")]
 use std::process::Command;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use sysinfo::{Pid, Process, System};
 
-use crate::utils::text;
+use crate::utils::matcher::{self, Matcher};
+use crate::utils::signal::{self, Signal};
+use crate::utils::socket::{self, Protocol, SocketFilter, SocketState};
+
+// 没有指定宽限期时，发完信号等这么久确认进程退出
+const DEFAULT_VERIFY_WAIT: Duration = Duration::from_secs(3);
+// 升级成 SIGKILL 之后等这么久
+const KILL_WAIT: Duration = Duration::from_secs(2);
 
 pub fn with_ctrl_c_handler<F: FnOnce()>(main_logic: F, exit_message: Option<&str>) {
     let running = Arc::new(AtomicBool::new(true));
@@ -26,6 +36,211 @@ pub struct Program {
     pub name: String,
     pub pid: u32,
     pub port: u32,
+    // 按端口找到的程序才有协议和状态
+    pub protocol: Option<Protocol>,
+    pub state: Option<SocketState>,
+    // --tree 时在进程树里的层级，匹配到的进程是 0，子进程是 1，依次往下
+    pub depth: usize,
+}
+
+impl Program {
+    // 端口、协议和状态，例如 3000/tcp LISTEN，按名称找到的只有端口 0
+    pub fn socket_label(&self) -> String {
+        match (self.protocol, self.state) {
+            (Some(protocol), Some(state)) => format!("{}/{protocol} {state}", self.port),
+            _ => self.port.to_string(),
+        }
+    }
+}
+
+fn process_name(pid: u32, all_process: &HashMap<Pid, Process>) -> String {
+    all_process
+        .get(&Pid::from_u32(pid))
+        .map(|process| process.name().to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn test_get_process_info_by_port() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let system = System::new_all();
+
+    let ports = [port..=port];
+    let programs = get_process_info_by_port(&ports, &SocketFilter::default(), system.processes());
+    println!("test_get_process_info_by_port result is {:#?}", programs);
+    assert!(programs.iter().any(|program| {
+        program.pid == std::process::id()
+            && program.protocol == Some(Protocol::Tcp)
+            && program.state == Some(SocketState::Listen)
+    }));
+
+    let udp_only = SocketFilter {
+        udp: true,
+        ..SocketFilter::default()
+    };
+    assert!(get_process_info_by_port(&ports, &udp_only, system.processes()).is_empty());
+}
+
+// 本地端口在 ports 范围里的 socket 所属的进程，同一进程同样端口、协议和状态的只算一次；
+// 只看本地端口，连到这个端口的客户端不算
+#[cfg(target_os = "linux")]
+fn get_process_info_by_port(
+    ports: &[RangeInclusive<u16>],
+    filter: &SocketFilter,
+    all_process: &HashMap<Pid, Process>,
+) -> Vec<Program> {
+    let mut match_programs: Vec<Program> = vec![];
+    let sockets = match socket::sockets() {
+        Ok(sockets) => sockets,
+        Err(err) => {
+            eprintln!("Failed to read /proc/net: {err}");
+            return match_programs;
+        }
+    };
+    let matched_sockets: Vec<_> = sockets
+        .into_iter()
+        .filter(|socket| {
+            ports
+                .iter()
+                .any(|range| range.contains(&socket.local.port()))
+                && filter.matches(socket)
+        })
+        .collect();
+    if matched_sockets.is_empty() {
+        return match_programs;
+    }
+
+    let owners = socket::socket_owners();
+    let mut hidden = false;
+    for socket in matched_sockets {
+        let Some(pids) = owners.get(&socket.inode) else {
+            hidden |= socket.inode != 0;
+            continue;
+        };
+        let port = u32::from(socket.local.port());
+        for &pid in pids {
+            let exists = match_programs.iter().any(|program| {
+                program.pid == pid
+                    && program.port == port
+                    && program.protocol == Some(socket.protocol)
+                    && program.state == Some(socket.state)
+            });
+            if !exists {
+                match_programs.push(Program {
+                    name: process_name(pid, all_process),
+                    pid,
+                    port,
+                    protocol: Some(socket.protocol),
+                    state: Some(socket.state),
+                    depth: 0,
+                });
+            }
+        }
+    }
+    if hidden {
+        eprintln!("⚠️ Some matched sockets belong to other users' processes, try again with sudo");
+    }
+    match_programs
+}
+
+// macOS 没有 /proc，用 lsof 查
+#[cfg(target_os = "macos")]
+fn get_process_info_by_port(
+    ports: &[RangeInclusive<u16>],
+    filter: &SocketFilter,
+    _all_process: &HashMap<Pid, Process>,
+) -> Vec<Program> {
+    let mut match_programs: Vec<Program> = vec![];
+    for range in ports {
+        let output = match Command::new("lsof")
+            .args([
+                "-n",
+                "-P",
+                "-i",
+                &format!(":{}-{}", range.start(), range.end()),
+            ])
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) => {
+                eprintln!("Failed to execute lsof: {err}");
+                return match_programs;
+            }
+        };
+        collect_lsof_programs(&output.stdout, range, filter, &mut match_programs);
+    }
+    match_programs
+}
+
+#[cfg(target_os = "macos")]
+fn collect_lsof_programs(
+    stdout: &[u8],
+    range: &RangeInclusive<u16>,
+    filter: &SocketFilter,
+    match_programs: &mut Vec<Program>,
+) {
+    // COMMAND PID USER FD TYPE DEVICE SIZE/OFF NODE NAME，tcp 的 NAME 后面带 (LISTEN) 这样的状态
+    let output_str = String::from_utf8_lossy(stdout);
+    for line in output_str.lines().skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let (Some(command), Some(Ok(pid)), Some(node), Some(name)) = (
+            columns.first(),
+            columns.get(1).map(|pid| pid.parse::<u32>()),
+            columns.get(7),
+            columns.get(8),
+        ) else {
+            continue;
+        };
+        let protocol = if node.eq_ignore_ascii_case("udp") {
+            Protocol::Udp
+        } else {
+            Protocol::Tcp
+        };
+        // 只要本地端口匹配的：NAME 是 local->remote 或者 local
+        let Some(port) = name
+            .split("->")
+            .next()
+            .and_then(|local| local.rsplit_once(':'))
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .filter(|port| range.contains(port))
+        else {
+            continue;
+        };
+        let state = match (protocol, columns.get(9).copied()) {
+            (_, Some("(LISTEN)")) => SocketState::Listen,
+            (_, Some("(ESTABLISHED)")) => SocketState::Established,
+            (Protocol::Udp, _) if !name.contains("->") => SocketState::Listen,
+            (Protocol::Udp, _) => SocketState::Established,
+            (Protocol::Tcp, _) => SocketState::Other(0),
+        };
+        let socket = socket::Socket {
+            protocol,
+            local: std::net::SocketAddr::from(([0, 0, 0, 0], port)),
+            remote: std::net::SocketAddr::from(([0, 0, 0, 0], 0)),
+            state,
+            uid: 0,
+            inode: 0,
+        };
+        let port = u32::from(port);
+        let exists = match_programs.iter().any(|program| {
+            program.pid == pid
+                && program.port == port
+                && program.protocol == Some(protocol)
+                && program.state == Some(state)
+        });
+        if filter.matches(&socket) && !exists {
+            match_programs.push(Program {
+                name: command.to_string(),
+                pid,
+                port,
+                protocol: Some(protocol),
+                state: Some(state),
+                depth: 0,
+            })
+        }
+    }
 }
 
 // #[cfg(target_os = "windows")]
@@ -59,183 +274,474 @@ pub struct Program {
 //     None
 // }
 
-// #[cfg(target_os = "linux")]
-// fn get_process_info_by_port(port: u32) -> Vec<Program> {
-//     let mut match_programs: Vec<Program> = vec![];
-//
-//     let output = Command::new("ss")
-//         .arg("-tuln")
-//         .output()
-//         .expect("Failed to execute ss");
-//
-//     if !output.status.success() {
-//         eprintln!("Failed to execute ss");
-//         return match_programs;
-//     }
-//
-//     let mut match_program_pids: Vec<&str> = vec![];
-//     let output_str = String::from_utf8_lossy(&output.stdout);
-//
-//     for line in output_str.lines() {
-//         if line.contains(&format!(":{} ", port)) {
-//             let columns: Vec<&str> = line.split_whitespace().collect();
-//             // 解析 PID 并获取程序名
-//             if let Some(pid_str) = columns.get(5) {
-//                 if let Ok(pid) = pid_str.parse::<u32>() {
-//                     match_programs.push(Program {
-//                         pid,
-//                         port,
-//                         name: columns.get(1).unwrap_or(&"").to_string(),
-//                     })
-//                 }
-//             }
-//         }
-//     }
-//
-//     match_programs
-// }
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn get_process_info_by_port(
+    _ports: &[RangeInclusive<u16>],
+    _filter: &SocketFilter,
+    _all_process: &HashMap<Pid, Process>,
+) -> Vec<Program> {
+    eprintln!("Finding programs by port is not supported on this platform");
+    vec![]
+}
 
-#[test]
-fn test_get_process_info_by_port() {
-    let process_info = get_process_info_by_port(5500);
-    println!(
-        "test_get_process_info_by_port result is {:#?}",
-        process_info
-    );
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KillOptions {
+    pub signal: Signal,
+    // 设置后，过了宽限期进程还在就发 SIGKILL
+    pub grace: Option<Duration>,
+    // 连同子孙进程一起 kill
+    pub tree: bool,
 }
 
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-fn get_process_info_by_port(port: u32) -> Vec<Program> {
-    let mut match_programs: Vec<Program> = vec![];
-    let mut match_program_pids: Vec<&str> = vec![];
+impl Default for KillOptions {
+    fn default() -> Self {
+        KillOptions {
+            signal: Signal::Term,
+            grace: None,
+            tree: false,
+        }
+    }
+}
 
-    let output = Command::new("lsof")
-        .arg("-i")
-        .arg(format!(":{port}"))
-        .output()
-        .expect("Failed to execute lsof");
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillOutcome {
+    // 收到信号后退出了
+    Exited,
+    // 宽限期内没退出，发了 SIGKILL 才退出
+    Escalated,
+    // --tree 时子进程没了之后，父进程可能自己先退出了
+    AlreadyExited,
+    // HUP、USR1 这类不要求退出的信号，发出去就算成功
+    Signaled,
+}
 
-    if !output.status.success() {
-        eprintln!("lsof output result is empty");
-        return match_programs;
-    }
+#[test]
+fn test_kill_progress_by_pid() {
+    use std::process::Command;
 
-    let output_str = String::from_utf8_lossy(&output.stdout);
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    assert_eq!(
+        kill_progress_by_pid(child.id(), &KillOptions::default()),
+        Ok(KillOutcome::Exited)
+    );
+    child.wait().unwrap();
+    assert!(kill_progress_by_pid(child.id(), &KillOptions::default()).is_err());
 
-    for line in output_str.lines() {
-        let columns: Vec<&str> = line.split_whitespace().collect();
-        let command = columns[0];
-        let pid = columns[1];
+    // 忽略 SIGTERM 的进程，过了宽限期升级成 SIGKILL
+    let mut child = Command::new("sh")
+        .args(["-c", "trap '' TERM; while :; do sleep 0.1; done"])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    let options = KillOptions {
+        signal: Signal::Term,
+        grace: Some(Duration::from_millis(300)),
+        ..KillOptions::default()
+    };
+    assert_eq!(
+        kill_progress_by_pid(child.id(), &options),
+        Ok(KillOutcome::Escalated)
+    );
+    child.wait().unwrap();
 
-        if command.ne("COMMAND") {
-            if !match_program_pids.contains(&pid) {
-                match_program_pids.push(pid);
-                match_programs.push(Program {
-                    name: command.to_string(),
-                    pid: pid.parse::<u32>().unwrap(),
-                    port,
-                })
-            }
+    // 不要求退出的信号不等退出，也不升级成 SIGKILL
+    let mut child = Command::new("sh")
+        .args(["-c", "trap '' USR1; while :; do sleep 0.1; done"])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    let options = KillOptions {
+        signal: Signal::Usr1,
+        grace: Some(Duration::from_millis(300)),
+        ..KillOptions::default()
+    };
+    assert_eq!(
+        kill_progress_by_pid(child.id(), &options),
+        Ok(KillOutcome::Signaled)
+    );
+    assert!(signal::is_alive(child.id()));
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+// 发信号；要求退出的信号还要确认进程已经退出
+fn kill_progress_by_pid(pid: u32, options: &KillOptions) -> Result<KillOutcome, String> {
+    match signal::send(pid, options.signal) {
+        Ok(()) => {}
+        Err(err) if options.tree && err.raw_os_error() == Some(libc::ESRCH) => {
+            return Ok(KillOutcome::AlreadyExited);
         }
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            return Err(format!("{err}, try again with sudo"));
+        }
+        Err(err) => return Err(err.to_string()),
+    }
+    if !options.signal.terminates() {
+        return Ok(KillOutcome::Signaled);
     }
 
-    match_programs
+    let wait = options.grace.unwrap_or(DEFAULT_VERIFY_WAIT);
+    if signal::wait_exit(pid, wait) {
+        return Ok(KillOutcome::Exited);
+    }
+    if options.signal == Signal::Kill {
+        return Err(format!(
+            "still running {:.1}s after {}",
+            wait.as_secs_f64(),
+            options.signal
+        ));
+    }
+    if options.grace.is_none() {
+        return Err(format!(
+            "still running {:.1}s after {}, use --grace to escalate to SIGKILL",
+            wait.as_secs_f64(),
+            options.signal
+        ));
+    }
+
+    match signal::send(pid, Signal::Kill) {
+        Ok(()) => {}
+        // 等待超时之后、升级之前刚好退出了
+        Err(err) if err.raw_os_error() == Some(libc::ESRCH) => return Ok(KillOutcome::Exited),
+        Err(err) => return Err(err.to_string()),
+    }
+    if signal::wait_exit(pid, KILL_WAIT) {
+        Ok(KillOutcome::Escalated)
+    } else {
+        Err(format!("still running after {}", Signal::Kill))
+    }
 }
 
-fn kill_progress_by_pid(pid: u32) -> Result<String, String> {
-    let output = Command::new("kill")
-        .arg(pid.to_string())
-        .output()
-        .expect("failed to execute process");
+// 一个程序的 kill 结果
+#[derive(Debug, Clone)]
+pub struct KillReport {
+    pub program: Program,
+    pub result: Result<KillOutcome, String>,
+}
 
-    if !output.status.success() {
-        Err(format!(
-            "Failed to kill pid {}: {}",
-            pid,
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    } else {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+impl KillReport {
+    pub fn success(&self) -> bool {
+        self.result.is_ok()
     }
 }
 
-pub fn kill_programs(programs: Vec<Program>, with_kill_log: bool) {
+// 依次 kill，with_kill_log 为 false 时不输出，只返回结果；
+// 从进程树最底层开始 kill，同一个进程有多个 socket 时只 kill 一次
+pub fn kill_programs(
+    mut programs: Vec<Program>,
+    with_kill_log: bool,
+    options: &KillOptions,
+) -> Vec<KillReport> {
+    programs.sort_by_key(|program| std::cmp::Reverse(program.depth));
+    let mut killed = HashSet::new();
+    programs.retain(|program| killed.insert(program.pid));
     programs
         .into_iter()
-        .for_each(|program| match kill_progress_by_pid(program.pid) {
-            Ok(_) => {
-                if with_kill_log {
-                    println!(
-                        "✅ Kill program is {}, pid is {}, port is {} success",
-                        program.name, program.pid, program.port
-                    );
+        .map(|program| {
+            let result = kill_progress_by_pid(program.pid, options);
+            if with_kill_log {
+                match &result {
+                    Ok(outcome) => {
+                        let how = match outcome {
+                            KillOutcome::Exited => options.signal.to_string(),
+                            KillOutcome::Escalated => {
+                                format!("{} (escalated to {})", options.signal, Signal::Kill)
+                            }
+                            KillOutcome::AlreadyExited => "exiting on its own".to_string(),
+                            KillOutcome::Signaled => {
+                                format!("{} (not waiting for exit)", options.signal)
+                            }
+                        };
+                        println!(
+                            "✅ Kill program is {}, pid is {}, port is {} success by {how}",
+                            program.name,
+                            program.pid,
+                            program.socket_label()
+                        );
+                    }
+                    Err(err) => println!(
+                        "❌ Kill program {}, pid is {} failed: {}",
+                        program.name, program.pid, err
+                    ),
                 }
             }
-            Err(err) => print!("❌ Kill program {} failed: {}", program.name, err),
+            KillReport { program, result }
         })
+        .collect()
 }
 
+#[cfg(target_os = "linux")]
 #[test]
-fn test_get_matched_programs_by_name_or_port() {
-    // 获取系统的所有运行的process
-    let mut system = System::new_all();
-    system.refresh_all();
+fn test_get_matched_programs() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    // 只在这个子进程里把监听的 socket 复制到 fd 3 上让它继承，按端口就能找到子进程；
+    // 不能在测试进程里去掉 CLOEXEC，否则其他测试同时启动的子进程也会继承
+    const INHERITED_FD: libc::c_int = 3;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new("sleep");
+    command.arg("30.5");
+    // SAFETY: pre_exec 里只调用 async-signal-safe 的 dup2 和 fcntl
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 到同一个 fd 时不会清掉 CLOEXEC，要自己清
+            let result = if fd == INHERITED_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, INHERITED_FD)
+            };
+            if result == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    };
+    let mut child = command.spawn().unwrap();
+    drop(listener);
+    let system = System::new_all();
     let all_process = system.processes();
 
-    let query_names_and_ports = vec!["Google Chrome", "5500"];
-    let matched_programs = get_matched_programs_by_name_or_port(query_names_and_ports, all_process);
-    println!("{:?}", matched_programs);
+    let by_port = Matcher::from_terms(vec![&port]);
+    let matched_programs = get_matched_programs(&by_port, &SocketFilter::default(), all_process);
+    let program = matched_programs
+        .iter()
+        .find(|program| program.pid == child.id())
+        .unwrap();
+    assert_eq!(program.name, "sleep");
+    assert_eq!(program.socket_label(), format!("{port}/tcp LISTEN"));
+    // 自己也持有过这个 socket，但不会被列出来
+    assert!(
+        matched_programs
+            .iter()
+            .all(|program| program.pid != std::process::id())
+    );
+
+    let by_name = Matcher::All(vec![
+        Matcher::from_terms(vec!["sleep"]),
+        Matcher::Parent(std::process::id()),
+    ]);
+    let matched_programs = get_matched_programs(&by_name, &SocketFilter::default(), all_process);
+    assert!(
+        matched_programs
+            .iter()
+            .any(|program| program.pid == child.id())
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
 }
 
-// 获取所有匹配端口或名称的程序
-fn get_matched_programs_by_name_or_port(
-    name_or_ports: Vec<&str>,
+// 获取所有满足 matcher 的程序，按端口匹配到的每个 socket 一条，其余的每个进程一条；
+// rcli 自己和 pid 1 不列出来
+fn get_matched_programs(
+    matcher: &Matcher,
+    filter: &SocketFilter,
     all_process: &HashMap<Pid, Process>,
 ) -> Vec<Program> {
-    let mut system = System::new_all();
-    system.refresh_all();
-    let mut matched_programs: Vec<Program> = vec![];
+    let port_ranges = matcher.port_ranges();
+    let port_programs = if port_ranges.is_empty() {
+        vec![]
+    } else {
+        get_process_info_by_port(&port_ranges, filter, all_process)
+    };
 
-    name_or_ports.into_iter().for_each(|name_or_port| {
-        if text::is_valid_port(name_or_port) {
-            let port = name_or_port.parse::<u16>().unwrap();
-            let match_port_programs = get_process_info_by_port(port.into());
-            match_port_programs
-                .into_iter()
-                .for_each(|match_port_program| {
-                    matched_programs.push(match_port_program);
-                })
-        } else {
-            all_process.iter().for_each(|(pid, process)| {
-                let process_name = process.name().to_string_lossy();
-                if process_name.contains(name_or_port) {
-                    matched_programs.push(Program {
-                        name: process_name.to_string().clone(),
-                        pid: pid.as_u32(),
-                        port: 0,
-                    })
-                }
+    let mut matched_programs: Vec<Program> = vec![];
+    all_process.iter().for_each(|(pid, process)| {
+        let pid = pid.as_u32();
+        // 线程也会被列成进程，跳过
+        if matcher::is_protected(pid) || process.thread_kind().is_some() {
+            return;
+        }
+        let sockets: Vec<&Program> = port_programs
+            .iter()
+            .filter(|program| program.pid == pid)
+            .collect();
+        let ports: Vec<u16> = sockets
+            .iter()
+            .filter_map(|program| u16::try_from(program.port).ok())
+            .collect();
+        if !matcher.matches(process, &ports) {
+            return;
+        }
+        if sockets.is_empty() {
+            matched_programs.push(Program {
+                name: process.name().to_string_lossy().to_string(),
+                pid,
+                port: 0,
+                protocol: None,
+                state: None,
+                depth: 0,
             })
+        } else {
+            matched_programs.extend(sockets.into_iter().cloned());
         }
     });
+    matched_programs.sort_by_key(|program| (program.pid, program.port));
 
     matched_programs
 }
 
-pub fn fetch_all_matched_program(name_or_ports: Vec<&str>) -> Vec<Program> {
+#[test]
+fn test_expand_tree() {
+    use std::process::Command;
+
+    // sh -> sleep，sh 要等 sleep 结束才退出
+    let mut child = Command::new("sh")
+        .args(["-c", "sleep 30.75; true"])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    let system = System::new_all();
+    let matcher = Matcher::All(vec![
+        Matcher::ExactName("sh".to_string()),
+        Matcher::Parent(std::process::id()),
+    ]);
+    let programs = get_matched_programs(&matcher, &SocketFilter::default(), system.processes());
+    let tree = expand_tree(programs, system.processes());
+    let root = tree
+        .iter()
+        .position(|program| program.pid == child.id())
+        .unwrap();
+    assert_eq!(tree[root].depth, 0);
+    assert_eq!(
+        (tree[root + 1].name.as_str(), tree[root + 1].depth),
+        ("sleep", 1)
+    );
+
+    let tree_options = KillOptions {
+        tree: true,
+        ..KillOptions::default()
+    };
+    let reports = kill_programs(tree[root..root + 2].to_vec(), false, &tree_options);
+    assert_eq!(reports[0].program.name, "sleep");
+    assert!(reports.iter().all(KillReport::success));
+    child.wait().unwrap();
+}
+
+// 把匹配到的程序的子孙进程都加进来，按进程树先序排列；
+// 匹配到的程序本身是别的匹配程序的子孙时，放在祖先下面
+pub fn expand_tree(programs: Vec<Program>, all_process: &HashMap<Pid, Process>) -> Vec<Program> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    all_process.iter().for_each(|(pid, process)| {
+        if let Some(parent) = process.parent()
+            && process.thread_kind().is_none()
+        {
+            children
+                .entry(parent.as_u32())
+                .or_default()
+                .push(pid.as_u32());
+        }
+    });
+    children.values_mut().for_each(|pids| pids.sort());
+
+    let matched: HashSet<u32> = programs.iter().map(|program| program.pid).collect();
+    // 往上找祖先，有匹配到的就不是根
+    let is_root = |pid: u32| {
+        let mut visited = HashSet::new();
+        let mut current = all_process
+            .get(&Pid::from_u32(pid))
+            .and_then(Process::parent);
+        while let Some(parent) = current {
+            if matched.contains(&parent.as_u32()) {
+                return false;
+            }
+            if !visited.insert(parent) {
+                break;
+            }
+            current = all_process.get(&parent).and_then(Process::parent);
+        }
+        true
+    };
+
+    let mut tree = vec![];
+    let mut visited = HashSet::new();
+    let mut stack: Vec<(u32, usize)> = vec![];
+    for root in programs.iter().map(|program| program.pid) {
+        if !is_root(root) || visited.contains(&root) {
+            continue;
+        }
+        stack.push((root, 0));
+        while let Some((pid, depth)) = stack.pop() {
+            if !visited.insert(pid) || matcher::is_protected(pid) {
+                continue;
+            }
+            let own: Vec<Program> = programs
+                .iter()
+                .filter(|program| program.pid == pid)
+                .map(|program| Program {
+                    depth,
+                    ..program.clone()
+                })
+                .collect();
+            if own.is_empty() {
+                tree.push(Program {
+                    name: process_name(pid, all_process),
+                    pid,
+                    port: 0,
+                    protocol: None,
+                    state: None,
+                    depth,
+                });
+            } else {
+                tree.extend(own);
+            }
+            if let Some(pids) = children.get(&pid) {
+                stack.extend(pids.iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+    }
+    tree
+}
+
+// 选中的程序连同它在 tree 里的子孙（紧跟在后面、层级更深的那些）
+pub fn with_descendants(tree: &[Program], selected: &[usize]) -> Vec<Program> {
+    let mut indexes: Vec<usize> = vec![];
+    for &index in selected {
+        let Some(program) = tree.get(index) else {
+            continue;
+        };
+        let end = tree[index + 1..]
+            .iter()
+            .position(|descendant| descendant.depth <= program.depth)
+            .map_or(tree.len(), |offset| index + 1 + offset);
+        indexes.extend(index..end);
+    }
+    indexes.sort();
+    indexes.dedup();
+    indexes
+        .into_iter()
+        .map(|index| tree[index].clone())
+        .collect()
+}
+
+// filter 只作用于按端口的匹配，tree 为 true 时把子孙进程也列出来
+pub fn fetch_all_matched_program(
+    matcher: &Matcher,
+    filter: &SocketFilter,
+    tree: bool,
+) -> Vec<Program> {
     // 获取系统的所有运行的process
     let mut system = System::new_all();
     system.refresh_all();
     let all_process = system.processes();
 
     // 获取匹配的程序
-    let matched_programs = get_matched_programs_by_name_or_port(name_or_ports, all_process);
-    matched_programs
+    let programs = get_matched_programs(matcher, filter, all_process);
+    if tree {
+        expand_tree(programs, all_process)
+    } else {
+        programs
+    }
 }
 
 // 找到匹配的程序，并kill
 #[allow(dead_code)]
 pub fn fetch_match_program_and_kill(name_or_ports: Vec<&str>) {
-    let matched_programs = fetch_all_matched_program(name_or_ports);
-    kill_programs(matched_programs, true);
+    let matcher = Matcher::from_terms(name_or_ports);
+    let matched_programs = fetch_all_matched_program(&matcher, &SocketFilter::default(), false);
+    kill_programs(matched_programs, true, &KillOptions::default());
 }
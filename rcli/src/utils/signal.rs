@@ -0,0 +1,196 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 直接调用 kill(2) 发信号，不再启动外部的 kill 命令
+
+// 检查进程是否退出的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Term,
+    Int,
+    Hup,
+    Quit,
+    Kill,
+    Usr1,
+    Usr2,
+    Abrt,
+    Alrm,
+}
+
+const SIGNALS: [Signal; 9] = [
+    Signal::Term,
+    Signal::Int,
+    Signal::Hup,
+    Signal::Quit,
+    Signal::Kill,
+    Signal::Usr1,
+    Signal::Usr2,
+    Signal::Abrt,
+    Signal::Alrm,
+];
+
+impl Signal {
+    pub fn number(self) -> i32 {
+        match self {
+            Signal::Term => libc::SIGTERM,
+            Signal::Int => libc::SIGINT,
+            Signal::Hup => libc::SIGHUP,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+            Signal::Abrt => libc::SIGABRT,
+            Signal::Alrm => libc::SIGALRM,
+        }
+    }
+
+    // 是不是要求进程退出的信号；HUP、USR1 这类通常是让进程重新加载配置之类的，发完不等退出
+    pub fn terminates(self) -> bool {
+        matches!(
+            self,
+            Signal::Term | Signal::Int | Signal::Quit | Signal::Kill | Signal::Abrt
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Term => "TERM",
+            Signal::Int => "INT",
+            Signal::Hup => "HUP",
+            Signal::Quit => "QUIT",
+            Signal::Kill => "KILL",
+            Signal::Usr1 => "USR1",
+            Signal::Usr2 => "USR2",
+            Signal::Abrt => "ABRT",
+            Signal::Alrm => "ALRM",
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIG{}", self.name())
+    }
+}
+
+// 支持 TERM、SIGTERM、term 和信号编号 15 这几种写法
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Signal, String> {
+        let upper = value.trim().to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        SIGNALS
+            .into_iter()
+            .find(|signal| signal.name() == name || signal.number().to_string() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = SIGNALS.iter().map(|signal| signal.name()).collect();
+                format!(
+                    "unknown signal {value}, expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[test]
+fn test_parse_signal() {
+    assert_eq!("TERM".parse::<Signal>(), Ok(Signal::Term));
+    assert_eq!("sigkill".parse::<Signal>(), Ok(Signal::Kill));
+    assert_eq!("1".parse::<Signal>(), Ok(Signal::Hup));
+    assert!("STOP".parse::<Signal>().is_err());
+    assert_eq!(Signal::Int.to_string(), "SIGINT");
+    assert!(Signal::Term.terminates() && !Signal::Hup.terminates());
+}
+
+// 5、5s、500ms、1m 这几种写法，不带单位是秒
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration {value}"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("invalid duration unit in {value}, use ms, s or m")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration {value}"))
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert!(parse_duration("-1").is_err());
+    assert!(parse_duration("5h").is_err());
+}
+
+pub fn send(pid: u32, signal: Signal) -> io::Result<()> {
+    let pid = libc::pid_t::try_from(pid)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid pid"))?;
+    // pid 0 和负数会发给整个进程组，不允许
+    if pid <= 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid pid"));
+    }
+    // SAFETY: kill 只读取两个整数参数
+    if unsafe { libc::kill(pid, signal.number()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// 进程是否还在；已经退出但还没被父进程回收的僵尸进程算不在
+pub fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: 信号 0 只检查进程是否存在、有没有权限，不会真的发信号
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    exists && !is_zombie(pid)
+}
+
+#[cfg(target_os = "linux")]
+fn is_zombie(pid: libc::pid_t) -> bool {
+    // /proc/<pid>/stat 形如 "123 (name) Z ..."，name 里可能有空格和括号，从最后一个 ) 往后找
+    std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .ok()
+        .and_then(|stat| {
+            let (_, rest) = stat.rsplit_once(')')?;
+            rest.split_whitespace().next().map(|state| state == "Z")
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_zombie(_pid: libc::pid_t) -> bool {
+    false
+}
+
+// 等进程退出，最多等 timeout，返回是否已经退出
+pub fn wait_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !is_alive(pid) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
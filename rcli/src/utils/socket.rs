@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// 从 /proc/net/{tcp,tcp6,udp,udp6} 读取 socket，再通过 /proc/<pid>/fd 找到所属进程，
+// 不依赖 lsof、ss 等外部命令
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+// udp 没有连接状态，只绑定了地址的算 Listen，connect 过的算 Established
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocketState {
+    Listen,
+    Established,
+    // 其余的 tcp 状态，值是内核里的编号
+    Other(u8),
+}
+
+impl SocketState {
+    fn from_code(protocol: Protocol, code: u8) -> SocketState {
+        match (protocol, code) {
+            (_, 0x01) => SocketState::Established,
+            (Protocol::Tcp, 0x0A) | (Protocol::Udp, 0x07) => SocketState::Listen,
+            (_, code) => SocketState::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for SocketState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SocketState::Listen => "LISTEN",
+            SocketState::Established => "ESTABLISHED",
+            SocketState::Other(0x02) => "SYN_SENT",
+            SocketState::Other(0x03) => "SYN_RECV",
+            SocketState::Other(0x04) => "FIN_WAIT1",
+            SocketState::Other(0x05) => "FIN_WAIT2",
+            SocketState::Other(0x06) => "TIME_WAIT",
+            SocketState::Other(0x07) => "CLOSE",
+            SocketState::Other(0x08) => "CLOSE_WAIT",
+            SocketState::Other(0x09) => "LAST_ACK",
+            SocketState::Other(0x0B) => "CLOSING",
+            SocketState::Other(_) => "UNKNOWN",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socket {
+    pub protocol: Protocol,
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub state: SocketState,
+    pub uid: u32,
+    // 0 表示已经没有进程持有，比如 TIME_WAIT
+    pub inode: u64,
+}
+
+// 按协议和状态过滤，tcp 和 udp 都没指定时两种都要
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketFilter {
+    pub tcp: bool,
+    pub udp: bool,
+    pub listening: bool,
+}
+
+impl SocketFilter {
+    pub fn matches(&self, socket: &Socket) -> bool {
+        let protocol = match socket.protocol {
+            Protocol::Tcp => self.tcp || !self.udp,
+            Protocol::Udp => self.udp || !self.tcp,
+        };
+        protocol && (!self.listening || socket.state == SocketState::Listen)
+    }
+}
+
+// 0100007F:1F90 -> 127.0.0.1:8080；地址是按 4 字节一组、本机字节序打印的
+fn parse_address(value: &str) -> Option<SocketAddr> {
+    let (ip, port) = value.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words = (0..ip.len() / 8)
+        .map(|i| u32::from_str_radix(ip.get(i * 8..i * 8 + 8)?, 16).ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[test]
+fn test_parse_proc_net() {
+    let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 12345 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 12346 1 0000000000000000 20 4 30 10 -1
+";
+    let sockets = parse_proc_net(tcp, Protocol::Tcp);
+    assert_eq!(sockets.len(), 2);
+    assert_eq!(sockets[0].local, "127.0.0.1:8080".parse().unwrap());
+    assert_eq!(sockets[0].state, SocketState::Listen);
+    assert_eq!((sockets[0].uid, sockets[0].inode), (1000, 12345));
+    assert_eq!(sockets[1].remote, "127.0.0.1:54321".parse().unwrap());
+    assert_eq!(sockets[1].state, SocketState::Established);
+
+    let udp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  100: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 999 2 0000000000000000 0
+";
+    let sockets = parse_proc_net(udp6, Protocol::Udp);
+    assert_eq!(sockets[0].local, "[::1]:53".parse().unwrap());
+    assert_eq!(sockets[0].state, SocketState::Listen);
+    assert_eq!(sockets[0].state.to_string(), "LISTEN");
+}
+
+// 解析 /proc/net/tcp 这类文件的内容，第一行是表头，格式不对的行跳过
+pub fn parse_proc_net(contents: &str, protocol: Protocol) -> Vec<Socket> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            Some(Socket {
+                protocol,
+                local: parse_address(columns.get(1)?)?,
+                remote: parse_address(columns.get(2)?)?,
+                state: SocketState::from_code(
+                    protocol,
+                    u8::from_str_radix(columns.get(3)?, 16).ok()?,
+                ),
+                uid: columns.get(7)?.parse().ok()?,
+                inode: columns.get(9)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+// 本机所有 tcp/udp socket；没开 ipv6 时 tcp6/udp6 不存在，直接跳过
+pub fn sockets() -> io::Result<Vec<Socket>> {
+    let mut sockets = vec![];
+    for (file, protocol) in [
+        ("/proc/net/tcp", Protocol::Tcp),
+        ("/proc/net/tcp6", Protocol::Tcp),
+        ("/proc/net/udp", Protocol::Udp),
+        ("/proc/net/udp6", Protocol::Udp),
+    ] {
+        match fs::read_to_string(file) {
+            Ok(contents) => sockets.extend(parse_proc_net(&contents, protocol)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(sockets)
+}
+
+// socket inode -> 持有它的进程，fork 之后同一个 socket 可能被多个进程持有；
+// 别的用户的进程没权限读 fd，不在结果里
+pub fn socket_owners() -> HashMap<u64, Vec<u32>> {
+    let mut owners: HashMap<u64, Vec<u32>> = HashMap::new();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return owners;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|target| target.strip_prefix("socket:["))
+                .and_then(|target| target.strip_suffix(']'))
+                .and_then(|inode| inode.parse::<u64>().ok());
+            if let Some(inode) = inode {
+                let pids = owners.entry(inode).or_default();
+                if !pids.contains(&pid) {
+                    pids.push(pid);
+                }
+            }
+        }
+    }
+    owners
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_find_own_sockets() {
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = std::net::TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+    let (_server, _) = tcp.accept().unwrap();
+
+    let sockets = sockets().unwrap();
+    let owners = socket_owners();
+    let find = |port: u16, protocol: Protocol, state: SocketState| {
+        sockets.iter().find(|socket| {
+            socket.local.port() == port && socket.protocol == protocol && socket.state == state
+        })
+    };
+    let pid = std::process::id();
+
+    let tcp_port = tcp.local_addr().unwrap().port();
+    let listen = find(tcp_port, Protocol::Tcp, SocketState::Listen).unwrap();
+    assert!(owners[&listen.inode].contains(&pid));
+    assert!(find(tcp_port, Protocol::Tcp, SocketState::Established).is_some());
+    let outgoing = client.local_addr().unwrap().port();
+    assert!(find(outgoing, Protocol::Tcp, SocketState::Established).is_some());
+
+    let bound = find(
+        udp.local_addr().unwrap().port(),
+        Protocol::Udp,
+        SocketState::Listen,
+    )
+    .unwrap();
+    assert!(owners[&bound.inode].contains(&pid));
+
+    let only_udp = SocketFilter {
+        udp: true,
+        ..SocketFilter::default()
+    };
+    assert!(!only_udp.matches(listen) && only_udp.matches(bound));
+    let listening = SocketFilter {
+        listening: true,
+        ..SocketFilter::default()
+    };
+    assert!(listening.matches(listen) && listening.matches(bound));
+}
@@ -0,0 +1,38 @@
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use std::sync::mpsc;
+use web::thread_pool::{Scheduler, ThreadPool};
+
+// 一批很短的任务，主要比较取任务时的锁竞争
+fn run_batch(pool: &ThreadPool, jobs: usize) {
+    let (tx, rx) = mpsc::channel();
+    for i in 0..jobs {
+        let tx = tx.clone();
+        pool.execute(move || {
+            tx.send(black_box(i).wrapping_mul(31)).unwrap();
+        })
+        .unwrap();
+    }
+    drop(tx);
+    assert_eq!(rx.iter().count(), jobs);
+}
+
+fn scheduler_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scheduler");
+    for scheduler in [Scheduler::SharedQueue, Scheduler::WorkStealing] {
+        let pool = ThreadPool::builder()
+            .core_size(8)
+            .scheduler(scheduler)
+            .build();
+        group.bench_with_input(
+            BenchmarkId::new(format!("{scheduler:?}"), 10_000),
+            &10_000,
+            |b, &jobs| b.iter(|| run_batch(&pool, jobs)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scheduler_benchmark);
+criterion_main!(benches);
+
+// 运行cargo bench --bench thread_pool_benchmark
@@ -0,0 +1,103 @@
+use crate::http::Request;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::info;
+
+// 访问日志事件的 target，logger 按它把访问日志单独写到 access 文件里
+pub const ACCESS_TARGET: &str = "web::access";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // Apache Combined Log Format，末尾追加处理耗时（毫秒）
+    Combined,
+    // 每行一个 JSON 对象
+    Json,
+}
+
+// 一条访问记录
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessEntry {
+    pub time: DateTime<Local>,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration_ms: f64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessEntry {
+    // bytes 是实际写到连接上的响应体字节数，duration 从开始读请求算到响应写完
+    pub fn new(request: &Request, status: u16, bytes: usize, duration: Duration) -> AccessEntry {
+        AccessEntry {
+            time: Local::now(),
+            remote_addr: request.remote_addr,
+            method: request.method.clone(),
+            path: match &request.query {
+                Some(query) => format!("{}?{query}", request.path),
+                None => request.path.clone(),
+            },
+            version: request.version.clone(),
+            status,
+            bytes,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            referer: request.header("Referer").map(str::to_string),
+            user_agent: request.header("User-Agent").map(str::to_string),
+        }
+    }
+
+    // 127.0.0.1 - - [10/Oct/2000:13:55:36 +0800] "GET / HTTP/1.1" 200 2326 "-" "curl/8.0" 0.412
+    pub fn combined(&self) -> String {
+        let remote_addr = self
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+        // 请求里的引号会破坏格式，转义掉
+        let quote = |value: Option<&str>| match value {
+            Some(value) => value.replace('\\', "\\\\").replace('"', "\\\""),
+            None => "-".to_string(),
+        };
+        let bytes = match self.bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        // 服务端在读到请求行之前就回了错误响应时，请求行记成 -
+        let request_line = match self.method.as_str() {
+            "" => "-".to_string(),
+            method => format!(
+                "{} {} {}",
+                quote(Some(method)),
+                quote(Some(&self.path)),
+                quote(Some(&self.version))
+            ),
+        };
+        format!(
+            "{remote_addr} - - [{}] \"{request_line}\" {} {bytes} \"{}\" \"{}\" {:.3}",
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.status,
+            quote(self.referer.as_deref()),
+            quote(self.user_agent.as_deref()),
+            self.duration_ms,
+        )
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Combined => self.combined(),
+            LogFormat::Json => self.json(),
+        }
+    }
+}
+
+pub fn log_access(entry: &AccessEntry, format: LogFormat) {
+    info!(target: ACCESS_TARGET, "{}", entry.format(format));
+}
@@ -0,0 +1,553 @@
+use crate::http::{
+    BodyLength, BodyReceiver, BodyStream, ChunkedDecoder, LAST_CHUNK, MAX_HEAD_SIZE, ParseError,
+    Request, Response, encode_chunk,
+};
+use crate::server::{
+    ConnectionPermit, Limiter, Limits, error_response, parse_error_response, payload_too_large,
+    too_many_connections,
+};
+use crate::thread_pool::ThreadPool as BlockingPool;
+use futures::channel::oneshot;
+use futures::executor::{ThreadPool, block_on};
+use futures::future::{self, BoxFuture, Either};
+use futures::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use futures::{FutureExt, StreamExt};
+use futures_rustls::TlsAcceptor;
+use rustls::ServerConfig;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+mod net;
+mod reactor;
+
+pub use net::{AsyncTcpListener, AsyncTcpStream};
+pub use reactor::{Sleep, sleep};
+
+// 阻塞线程池最多扩到这么多线程
+const MAX_BLOCKING_THREADS: usize = 64;
+// 流式响应体每次在阻塞线程池上读这么多
+const STREAM_CHUNK: usize = 16 * 1024;
+
+// 一个异步服务的线程池：executor 跑连接和后台任务，blocking 跑会阻塞的调用
+struct Runtime {
+    executor: OnceLock<ThreadPool>,
+    blocking: BlockingPool,
+}
+
+thread_local! {
+    // 当前线程所在的异步服务，spawn 和 spawn_blocking 把任务放到它的线程池上
+    static RUNTIME: RefCell<Option<Arc<Runtime>>> = const { RefCell::new(None) };
+}
+
+fn runtime() -> Option<Arc<Runtime>> {
+    RUNTIME.with(|runtime| runtime.borrow().clone())
+}
+
+// 在当前异步服务的线程池上运行一个后台任务，比如 handler 返回响应以后继续推送的事件；
+// 不在异步服务的线程上调用时另起一个线程运行。任务 panic 只结束这个任务，不会带走线程
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    let future = AssertUnwindSafe(future).catch_unwind().map(|result| {
+        if result.is_err() {
+            warn!("background task panicked");
+        }
+    });
+    match runtime().and_then(|runtime| runtime.executor.get().cloned()) {
+        Some(pool) => pool.spawn_ok(future),
+        None => {
+            thread::spawn(move || block_on(future));
+        }
+    }
+}
+
+// 把会阻塞的调用（读文件、和上游通信）放到阻塞线程池上运行，等待期间不占用 executor 的线程；
+// 不在异步服务的线程上调用时另起一个线程运行。f panic 时在等待的任务里接着 panic
+pub async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let job = move || {
+        let _ = sender.send(f());
+    };
+    match runtime() {
+        Some(runtime) => {
+            if let Err(err) = runtime.blocking.execute(job) {
+                panic!("spawn blocking job failed: {err}");
+            }
+        }
+        None => {
+            thread::spawn(job);
+        }
+    }
+    // f panic 时 sender 没有发送就被丢掉
+    receiver.await.expect("blocking job panicked")
+}
+
+// 异步模式下的处理函数，返回的 future 在等待期间不占用线程
+pub trait AsyncHandler: Send + Sync + 'static {
+    fn handle(&self, request: Request) -> BoxFuture<'static, Response>;
+}
+
+impl<F, Fut> AsyncHandler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn handle(&self, request: Request) -> BoxFuture<'static, Response> {
+        Box::pin(self(request))
+    }
+}
+
+// 在 futures::executor::ThreadPool 上运行的异步服务：accept 循环跑在当前线程，
+// 每个连接是线程池上的一个任务，socket 就绪和定时器由 reactor 线程统一唤醒
+pub fn run(
+    listener: TcpListener,
+    handler: Arc<dyn AsyncHandler>,
+    threads: usize,
+) -> io::Result<()> {
+    run_with_limits(listener, handler, threads, Limiter::default())
+}
+
+pub fn run_with_limits(
+    listener: TcpListener,
+    handler: Arc<dyn AsyncHandler>,
+    threads: usize,
+    limiter: Limiter,
+) -> io::Result<()> {
+    start(listener, handler, threads, limiter, None)
+}
+
+// 同 run_with_limits，每个连接先在线程池上异步完成 TLS 握手
+pub fn run_tls(
+    listener: TcpListener,
+    handler: Arc<dyn AsyncHandler>,
+    threads: usize,
+    limiter: Limiter,
+    tls: Arc<ServerConfig>,
+) -> io::Result<()> {
+    start(
+        listener,
+        handler,
+        threads,
+        limiter,
+        Some(TlsAcceptor::from(tls)),
+    )
+}
+
+fn start(
+    listener: TcpListener,
+    handler: Arc<dyn AsyncHandler>,
+    threads: usize,
+    limiter: Limiter,
+    tls: Option<TlsAcceptor>,
+) -> io::Result<()> {
+    let runtime = Arc::new(Runtime {
+        executor: OnceLock::new(),
+        blocking: BlockingPool::builder()
+            .core_size(threads.clamp(1, MAX_BLOCKING_THREADS))
+            .max_size(MAX_BLOCKING_THREADS)
+            .thread_name("web-async-blocking")
+            .build(),
+    });
+    let shared = Arc::clone(&runtime);
+    let pool = ThreadPool::builder()
+        .pool_size(threads)
+        .name_prefix("web-async-")
+        .after_start(move |_| RUNTIME.set(Some(Arc::clone(&shared))))
+        .create()?;
+    let _ = runtime.executor.set(pool.clone());
+    let listener = AsyncTcpListener::from_std(listener)?;
+
+    block_on(async {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => pool.spawn_ok(accept(
+                    stream,
+                    Arc::clone(&handler),
+                    limiter.clone(),
+                    tls.clone(),
+                )),
+                Err(err) => warn!("accept failed: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn accept(
+    stream: AsyncTcpStream,
+    handler: Arc<dyn AsyncHandler>,
+    limiter: Limiter,
+    tls: Option<TlsAcceptor>,
+) {
+    let remote_addr = stream.peer_addr();
+    debug!(%remote_addr, "accepted connection");
+    let permit = limiter.acquire(remote_addr.ip());
+
+    let Some(acceptor) = tls else {
+        return serve_connection(stream, remote_addr, false, handler, &limiter, permit).await;
+    };
+    // 握手也受请求头期限约束，防止只建连接不握手
+    match timeout(limiter.limits.header_timeout, acceptor.accept(stream)).await {
+        Ok(stream) => serve_connection(stream, remote_addr, true, handler, &limiter, permit).await,
+        Err(err) => debug!(%remote_addr, "tls handshake failed: {err}"),
+    }
+}
+
+async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    remote_addr: SocketAddr,
+    secure: bool,
+    handler: Arc<dyn AsyncHandler>,
+    limiter: &Limiter,
+    permit: Option<ConnectionPermit>,
+) {
+    let limits = &limiter.limits;
+    let connected = Instant::now();
+    if permit.is_none() {
+        let mut writer = Counted::new(stream);
+        write_error(
+            &mut writer,
+            too_many_connections(),
+            limiter,
+            remote_addr,
+            connected,
+        )
+        .await;
+        let _ = writer.close().await;
+        return;
+    }
+    let (reader, writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = Counted::new(writer);
+    let mut first = true;
+
+    loop {
+        // 新连接从建立起就按请求头期限计时；长连接先等下一个请求，空闲超时直接关闭
+        if !first {
+            match timeout(limits.keep_alive_timeout, reader.fill_buf()).await {
+                Ok([]) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        first = false;
+        let started = Instant::now();
+
+        let mut request = match read_limited(&mut reader, limits).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(response) => {
+                write_error(&mut writer, response, limiter, remote_addr, started).await;
+                break;
+            }
+        };
+        request.remote_addr = Some(remote_addr);
+        request.secure = secure;
+
+        let mut keep_alive = request.keep_alive();
+        // 请求要交给 handler，先留一份不带请求体的用来记日志
+        let logged = request.without_body();
+        // handler panic 时回 500 并关闭连接，和其他模式一样；也不会带走 executor 的线程
+        let handled = AssertUnwindSafe(async { handler.handle(request).await })
+            .catch_unwind()
+            .await;
+        let mut response = match handled {
+            Ok(response) => response,
+            Err(_) => {
+                warn!(path = %logged.path, "handler panicked");
+                keep_alive = false;
+                Response::text(500, "Internal Server Error")
+            }
+        };
+        if !keep_alive {
+            response.set_header("Connection", "close");
+        }
+        let before = writer.written;
+        let stream = response.stream.clone();
+        let written = match (stream.as_ref().and_then(BodyStream::take_channel), stream) {
+            (Some(body), _) => {
+                response.remove_header("Content-Length");
+                write_channel(&mut writer, &response, body, limits).await
+            }
+            (None, Some(stream)) if !response.no_body => match stream.take() {
+                Some(body) => write_stream(&mut writer, &response, body, limits).await,
+                None => Err(io::Error::other("response body stream already consumed")),
+            },
+            (None, _) => {
+                let mut bytes = response.head_bytes();
+                if !response.no_body {
+                    bytes.extend_from_slice(&response.body);
+                }
+                timeout(limits.write_timeout, writer.write_all(&bytes)).await
+            }
+        };
+        // 去掉响应头以后实际写出的部分，chunked 编码的分块格式也算在内
+        let bytes = (writer.written - before).saturating_sub(response.head_bytes().len());
+        limiter.log_access(&logged, response.status, bytes, started);
+        if written.is_err() || !keep_alive {
+            break;
+        }
+    }
+    let _ = writer.close().await;
+}
+
+// 写出服务端自己回的错误响应，也记一条访问日志，没读到请求行时请求行记成 -
+async fn write_error<W: AsyncWrite + Unpin>(
+    writer: &mut Counted<W>,
+    response: Response,
+    limiter: &Limiter,
+    remote_addr: SocketAddr,
+    started: Instant,
+) {
+    let before = writer.written;
+    let head = response.head_bytes().len();
+    let status = response.status;
+    let bytes = response.to_bytes();
+    let _ = timeout(limiter.limits.write_timeout, writer.write_all(&bytes)).await;
+    let request = Request {
+        remote_addr: Some(remote_addr),
+        ..Request::default()
+    };
+    let written = (writer.written - before).saturating_sub(head);
+    limiter.log_access(&request, status, written, started);
+}
+
+// 推送式响应体：先发响应头，之后每收到一块数据就按 chunked 编码发出去，等待期间不占用线程
+async fn write_channel<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &Response,
+    mut body: BodyReceiver,
+    limits: &Limits,
+) -> io::Result<()> {
+    timeout(
+        limits.write_timeout,
+        writer.write_all(&response.head_bytes()),
+    )
+    .await?;
+    timeout(limits.write_timeout, writer.flush()).await?;
+    while let Some(data) = body.recv().await {
+        timeout(limits.write_timeout, writer.write_all(&encode_chunk(&data))).await?;
+        timeout(limits.write_timeout, writer.flush()).await?;
+    }
+    timeout(limits.write_timeout, writer.write_all(LAST_CHUNK)).await?;
+    timeout(limits.write_timeout, writer.flush()).await
+}
+
+// 普通的流式响应体（文件、代理的上游响应）：reader 是阻塞的，每次在阻塞线程池上读一块，
+// 读到了再异步写出去，不在 executor 上阻塞，也不把整个响应体读进内存。
+// 有 Content-Length 时按长度原样发送，否则用 chunked 编码，和 Response::write_to 一样
+async fn write_stream<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &Response,
+    mut body: Box<dyn Read + Send>,
+    limits: &Limits,
+) -> io::Result<()> {
+    let mut remaining = match response.header("Content-Length") {
+        Some(length) => Some(
+            length
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| ParseError::BadContentLength)?,
+        ),
+        None => None,
+    };
+    timeout(
+        limits.write_timeout,
+        writer.write_all(&response.head_bytes()),
+    )
+    .await?;
+    let mut buf = vec![0; STREAM_CHUNK];
+    while remaining != Some(0) {
+        let max = remaining.map_or(buf.len(), |remaining| buf.len().min(remaining as usize));
+        let (read, returned_body, returned_buf) = spawn_blocking(move || {
+            let read = loop {
+                match body.read(&mut buf[..max]) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    read => break read,
+                }
+            };
+            (read, body, buf)
+        })
+        .await;
+        (body, buf) = (returned_body, returned_buf);
+        let read = read?;
+        if read == 0 {
+            if remaining.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "response body truncated",
+                ));
+            }
+            break;
+        }
+        match &mut remaining {
+            Some(remaining) => {
+                *remaining -= read as u64;
+                timeout(limits.write_timeout, writer.write_all(&buf[..read])).await?;
+            }
+            None => {
+                timeout(
+                    limits.write_timeout,
+                    writer.write_all(&encode_chunk(&buf[..read])),
+                )
+                .await?;
+            }
+        }
+        timeout(limits.write_timeout, writer.flush()).await?;
+    }
+    if remaining.is_none() {
+        timeout(limits.write_timeout, writer.write_all(LAST_CHUNK)).await?;
+    }
+    timeout(limits.write_timeout, writer.flush()).await
+}
+
+// 数写到连接上的字节数，访问日志按实际发出去的量记
+struct Counted<W> {
+    inner: W,
+    written: usize,
+}
+
+impl<W> Counted<W> {
+    fn new(inner: W) -> Counted<W> {
+        Counted { inner, written: 0 }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Counted<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+// 按 Limits 读一个请求，出错时返回应该回给客户端的错误响应
+async fn read_limited<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Option<Request>, Response> {
+    let mut request = match timeout(limits.header_timeout, read_head(reader)).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(error_response(&err)),
+    };
+
+    let length = request.body_length().map_err(parse_error_response)?;
+    if let BodyLength::Fixed(length) = length
+        && length > limits.max_body_size
+    {
+        return Err(payload_too_large());
+    }
+    // 每次读的间隔不超过 read_timeout，整个请求体不超过 body_timeout
+    let read_body = async {
+        match length {
+            BodyLength::Fixed(length) => {
+                let mut body = vec![0; length];
+                let mut filled = 0;
+                while filled < length {
+                    match timeout(limits.read_timeout, reader.read(&mut body[filled..])).await? {
+                        0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        read => filled += read,
+                    }
+                }
+                Ok(body)
+            }
+            BodyLength::Chunked => {
+                let mut decoder = ChunkedDecoder::new(limits.max_body_size);
+                while !decoder.is_done() {
+                    let buf = timeout(limits.read_timeout, reader.fill_buf()).await?;
+                    if buf.is_empty() {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let used = decoder.decode(buf)?;
+                    reader.consume_unpin(used);
+                }
+                Ok(decoder.into_body())
+            }
+        }
+    };
+    request.body = timeout(limits.body_timeout, read_body)
+        .await
+        .map_err(|err| error_response(&err))?;
+    Ok(Some(request))
+}
+
+// 给 IO future 加上超时，超时返回 TimedOut
+pub async fn timeout<T>(
+    duration: Duration,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match future::select(Box::pin(future), sleep(duration)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(io::ErrorKind::TimedOut.into()),
+    }
+}
+
+// Request::read_from 的异步版本
+pub async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Request>> {
+    let Some(mut request) = read_head(reader).await? else {
+        return Ok(None);
+    };
+    let length = request.content_length()?;
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).await?;
+    Ok(Some(request))
+}
+
+// Request::read_head 的异步版本
+pub async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut head = Vec::new();
+    loop {
+        let limit = (MAX_HEAD_SIZE + 1).saturating_sub(head.len()) as u64;
+        let read = (&mut *reader)
+            .take(limit)
+            .read_until(b'\n', &mut head)
+            .await?;
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(ParseError::HeadTooLarge.into());
+        }
+        if read == 0 {
+            if head.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if head == b"\r\n" {
+            head.clear();
+            continue;
+        }
+        if !head.ends_with(b"\r\n\r\n") {
+            continue;
+        }
+        if let Some((request, _)) = Request::parse_head(&head)? {
+            return Ok(Some(request));
+        }
+    }
+}
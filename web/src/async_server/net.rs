@@ -0,0 +1,133 @@
+use super::reactor::{Interests, Reactor};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::{self, Stream};
+use mio::Token;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+// 非阻塞 IO 的通用套路：先试一次，WouldBlock 时登记 waker 再试一次，
+// 第二次仍然 WouldBlock 才返回 Pending，避免登记前到达的就绪事件被漏掉
+fn poll_io<T>(
+    interests: &Mutex<Interests>,
+    waker: &Waker,
+    writing: bool,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> Poll<io::Result<T>> {
+    let mut registered = false;
+    loop {
+        match op() {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if registered {
+                    return Poll::Pending;
+                }
+                let mut interests = interests.lock().unwrap_or_else(PoisonError::into_inner);
+                if writing {
+                    interests.set_writer(waker);
+                } else {
+                    interests.set_reader(waker);
+                }
+                registered = true;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return Poll::Ready(result),
+        }
+    }
+}
+
+pub struct AsyncTcpListener {
+    listener: mio::net::TcpListener,
+    token: Token,
+    interests: Arc<Mutex<Interests>>,
+}
+
+impl AsyncTcpListener {
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<AsyncTcpListener> {
+        listener.set_nonblocking(true)?;
+        let mut listener = mio::net::TcpListener::from_std(listener);
+        let (token, interests) = Reactor::get().register(&mut listener)?;
+        Ok(AsyncTcpListener {
+            listener,
+            token,
+            interests,
+        })
+    }
+
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<AsyncTcpStream>> {
+        poll_io(&self.interests, cx.waker(), false, || {
+            self.listener.accept()
+        })
+        .map(|result| result.and_then(|(stream, addr)| AsyncTcpStream::new(stream, addr)))
+    }
+
+    pub fn incoming(&self) -> impl Stream<Item = io::Result<AsyncTcpStream>> + '_ {
+        stream::poll_fn(move |cx| self.poll_accept(cx).map(Some))
+    }
+}
+
+impl Drop for AsyncTcpListener {
+    fn drop(&mut self) {
+        Reactor::get().deregister(&mut self.listener, self.token);
+    }
+}
+
+pub struct AsyncTcpStream {
+    stream: mio::net::TcpStream,
+    addr: SocketAddr,
+    token: Token,
+    interests: Arc<Mutex<Interests>>,
+}
+
+impl AsyncTcpStream {
+    fn new(mut stream: mio::net::TcpStream, addr: SocketAddr) -> io::Result<AsyncTcpStream> {
+        let (token, interests) = Reactor::get().register(&mut stream)?;
+        Ok(AsyncTcpStream {
+            stream,
+            addr,
+            token,
+            interests,
+        })
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        poll_io(&this.interests, cx.waker(), false, || this.stream.read(buf))
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        poll_io(&this.interests, cx.waker(), true, || this.stream.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().stream.flush())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.stream.shutdown(Shutdown::Write))
+    }
+}
+
+impl Drop for AsyncTcpStream {
+    fn drop(&mut self) {
+        Reactor::get().deregister(&mut self.stream, self.token);
+    }
+}
@@ -0,0 +1,213 @@
+use mio::event::Source;
+use mio::{Events, Interest, Poll, Registry, Token};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll as TaskPoll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAKE: Token = Token(usize::MAX);
+
+// 某个 socket 上等待读、写的任务
+#[derive(Default)]
+pub(super) struct Interests {
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+struct Inner {
+    next_token: usize,
+    sources: HashMap<Token, Arc<Mutex<Interests>>>,
+    // (到期时间, 序号) -> 等待的任务
+    timers: BTreeMap<(Instant, u64), Waker>,
+    next_timer: u64,
+}
+
+// 全局唯一的 IO 反应器：一个后台线程跑 mio Poll，
+// socket 就绪或定时器到期时唤醒对应的 future，future 本身在 futures 的线程池上执行
+pub(super) struct Reactor {
+    registry: Registry,
+    waker: mio::Waker,
+    inner: Mutex<Inner>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Reactor {
+    pub(super) fn get() -> &'static Reactor {
+        static REACTOR: OnceLock<&'static Reactor> = OnceLock::new();
+        REACTOR.get_or_init(|| {
+            let poll = Poll::new().expect("failed to create poll");
+            let reactor: &'static Reactor = Box::leak(Box::new(Reactor {
+                registry: poll
+                    .registry()
+                    .try_clone()
+                    .expect("failed to clone registry"),
+                waker: mio::Waker::new(poll.registry(), WAKE).expect("failed to create waker"),
+                inner: Mutex::new(Inner {
+                    next_token: 0,
+                    sources: HashMap::new(),
+                    timers: BTreeMap::new(),
+                    next_timer: 0,
+                }),
+            }));
+            thread::Builder::new()
+                .name(String::from("web-reactor"))
+                .spawn(move || reactor.run(poll))
+                .expect("failed to spawn reactor thread");
+            reactor
+        })
+    }
+
+    fn run(&self, mut poll: Poll) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            let timeout = self.fire_timers();
+            if let Err(err) = poll.poll(&mut events, timeout) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                panic!("reactor poll failed: {err}");
+            }
+
+            for event in events.iter() {
+                if event.token() == WAKE {
+                    continue;
+                }
+                let Some(interests) = lock(&self.inner).sources.get(&event.token()).cloned() else {
+                    continue;
+                };
+                let mut interests = lock(&interests);
+                let closed = event.is_error() || event.is_read_closed() || event.is_write_closed();
+                if (event.is_readable() || closed)
+                    && let Some(reader) = interests.reader.take()
+                {
+                    reader.wake();
+                }
+                if (event.is_writable() || closed)
+                    && let Some(writer) = interests.writer.take()
+                {
+                    writer.wake();
+                }
+            }
+        }
+    }
+
+    // 唤醒到期的定时器，返回距离下一个定时器到期还有多久
+    fn fire_timers(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let mut inner = lock(&self.inner);
+        let pending = inner.timers.split_off(&(now, u64::MAX));
+        let expired = std::mem::replace(&mut inner.timers, pending);
+        let next = inner
+            .timers
+            .keys()
+            .next()
+            .map(|(deadline, _)| deadline.saturating_duration_since(now));
+        drop(inner);
+
+        expired.into_values().for_each(Waker::wake);
+        next
+    }
+
+    pub(super) fn register(
+        &self,
+        source: &mut impl Source,
+    ) -> io::Result<(Token, Arc<Mutex<Interests>>)> {
+        let interests = Arc::new(Mutex::new(Interests::default()));
+        let mut inner = lock(&self.inner);
+        let token = Token(inner.next_token);
+        inner.next_token = (inner.next_token + 1) % WAKE.0;
+        self.registry
+            .register(source, token, Interest::READABLE | Interest::WRITABLE)?;
+        inner.sources.insert(token, Arc::clone(&interests));
+        Ok((token, interests))
+    }
+
+    pub(super) fn deregister(&self, source: &mut impl Source, token: Token) {
+        lock(&self.inner).sources.remove(&token);
+        let _ = self.registry.deregister(source);
+    }
+
+    fn add_timer(&self, deadline: Instant, waker: Waker) -> u64 {
+        let mut inner = lock(&self.inner);
+        let id = inner.next_timer;
+        inner.next_timer += 1;
+        let earliest = inner
+            .timers
+            .keys()
+            .next()
+            .is_none_or(|(first, _)| deadline < *first);
+        inner.timers.insert((deadline, id), waker);
+        drop(inner);
+
+        // 新定时器比现有的都早，让反应器重新计算 poll 的超时
+        if earliest {
+            let _ = self.waker.wake();
+        }
+        id
+    }
+
+    fn remove_timer(&self, deadline: Instant, id: u64) {
+        lock(&self.inner).timers.remove(&(deadline, id));
+    }
+}
+
+impl Interests {
+    pub(super) fn set_reader(&mut self, waker: &Waker) {
+        self.reader = Some(waker.clone());
+    }
+
+    pub(super) fn set_writer(&mut self, waker: &Waker) {
+        self.writer = Some(waker.clone());
+    }
+}
+
+// 不占用线程的异步定时器
+pub struct Sleep {
+    deadline: Instant,
+    timer: Option<u64>,
+}
+
+impl Sleep {
+    pub fn new(duration: Duration) -> Sleep {
+        Sleep {
+            deadline: Instant::now() + duration,
+            timer: None,
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<()> {
+        if Instant::now() >= self.deadline {
+            return TaskPoll::Ready(());
+        }
+
+        let reactor = Reactor::get();
+        if let Some(id) = self.timer.take() {
+            reactor.remove_timer(self.deadline, id);
+        }
+        self.timer = Some(reactor.add_timer(self.deadline, cx.waker().clone()));
+        TaskPoll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer {
+            Reactor::get().remove_timer(self.deadline, id);
+        }
+    }
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::new(duration)
+}
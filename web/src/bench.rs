@@ -0,0 +1,383 @@
+use crate::http::ChunkedReader;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+// 压测目标，只支持 http://host[:port][/path]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Target {
+    pub fn parse(url: &str) -> io::Result<Target> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// urls are supported"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse().map_err(|_| invalid("invalid port in url"))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host in url"));
+        }
+        Ok(Target {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn host_header(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{port}", self.host),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub target: Target,
+    // 并发连接数
+    pub concurrency: usize,
+    // 总请求数，设置了 duration 时不生效
+    pub requests: usize,
+    pub duration: Option<Duration>,
+    // true 时每个连接上连续发请求，false 时每个请求新建一个连接
+    pub keep_alive: bool,
+    pub timeout: Duration,
+}
+
+impl BenchConfig {
+    pub fn new(target: Target) -> BenchConfig {
+        BenchConfig {
+            target,
+            concurrency: 10,
+            requests: 1000,
+            duration: None,
+            keep_alive: true,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+// 毫秒
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Latency {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Latency {
+    // samples 已排好序
+    fn from_sorted(samples: &[Duration]) -> Latency {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+            millis(samples[rank.clamp(1, samples.len()) - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        Latency {
+            min: millis(samples[0]),
+            mean: millis(total) / samples.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: millis(samples[samples.len() - 1]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub concurrency: usize,
+    pub keep_alive: bool,
+    // 得到响应的请求数，不论状态码
+    pub requests: usize,
+    // 连接失败、超时、响应格式错误的请求数
+    pub errors: usize,
+    // 新建的连接数
+    pub connections: usize,
+    pub elapsed_secs: f64,
+    pub throughput: f64,
+    pub bytes: u64,
+    pub statuses: BTreeMap<u16, usize>,
+    pub latency_ms: Latency,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.keep_alive {
+            "keep-alive"
+        } else {
+            "one-shot"
+        };
+        writeln!(f, "concurrency:  {} ({mode})", self.concurrency)?;
+        writeln!(
+            f,
+            "requests:     {} ({} errors)",
+            self.requests, self.errors
+        )?;
+        writeln!(f, "connections:  {}", self.connections)?;
+        writeln!(f, "elapsed:      {:.3}s", self.elapsed_secs)?;
+        writeln!(f, "throughput:   {:.1} req/s", self.throughput)?;
+        writeln!(f, "transferred:  {} bytes", self.bytes)?;
+        let statuses: Vec<_> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}={count}"))
+            .collect();
+        writeln!(f, "status codes: {}", statuses.join(" "))?;
+        let latency = &self.latency_ms;
+        write!(
+            f,
+            "latency (ms): min {:.3}  mean {:.3}  p50 {:.3}  p90 {:.3}  p99 {:.3}  max {:.3}",
+            latency.min, latency.mean, latency.p50, latency.p90, latency.p99, latency.max
+        )
+    }
+}
+
+// 每个压测线程自己的统计，结束后汇总
+#[derive(Default)]
+struct WorkerStats {
+    latencies: Vec<Duration>,
+    errors: usize,
+    connections: usize,
+    bytes: u64,
+    statuses: BTreeMap<u16, usize>,
+}
+
+// 剩余的请求配额，按请求数压测时所有线程共享
+struct Budget {
+    remaining: Option<AtomicUsize>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    fn take(&self) -> bool {
+        if let Some(deadline) = self.deadline {
+            return Instant::now() < deadline;
+        }
+        match &self.remaining {
+            Some(remaining) => remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok(),
+            None => false,
+        }
+    }
+}
+
+pub fn run(config: &BenchConfig) -> io::Result<BenchReport> {
+    let addr = (config.target.host.as_str(), config.target.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+    let connection = if config.keep_alive {
+        "keep-alive"
+    } else {
+        "close"
+    };
+    let request = Arc::new(format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: web-bench\r\nConnection: {connection}\r\n\r\n",
+        config.target.path,
+        config.target.host_header()
+    ));
+    let budget = Arc::new(Budget {
+        remaining: config
+            .duration
+            .is_none()
+            .then(|| AtomicUsize::new(config.requests)),
+        deadline: config.duration.map(|duration| Instant::now() + duration),
+    });
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..config.concurrency.max(1))
+        .map(|id| {
+            let request = Arc::clone(&request);
+            let budget = Arc::clone(&budget);
+            let config = config.clone();
+            thread::Builder::new()
+                .name(format!("web-bench-{id}"))
+                .spawn(move || drive(addr, &request, &budget, &config))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let mut total = WorkerStats::default();
+    for worker in workers {
+        let stats = worker
+            .join()
+            .map_err(|_| io::Error::other("bench worker panicked"))?;
+        total.latencies.extend(stats.latencies);
+        total.errors += stats.errors;
+        total.connections += stats.connections;
+        total.bytes += stats.bytes;
+        for (status, count) in stats.statuses {
+            *total.statuses.entry(status).or_default() += count;
+        }
+    }
+    let elapsed = started.elapsed();
+
+    total.latencies.sort_unstable();
+    let requests = total.latencies.len();
+    Ok(BenchReport {
+        concurrency: config.concurrency.max(1),
+        keep_alive: config.keep_alive,
+        requests,
+        errors: total.errors,
+        connections: total.connections,
+        elapsed_secs: elapsed.as_secs_f64(),
+        throughput: requests as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        bytes: total.bytes,
+        statuses: total.statuses,
+        latency_ms: Latency::from_sorted(&total.latencies),
+    })
+}
+
+// 一个压测线程：拿到配额就发一个请求，连接断了或者是 one-shot 模式就重新连
+fn drive(
+    addr: std::net::SocketAddr,
+    request: &str,
+    budget: &Budget,
+    config: &BenchConfig,
+) -> WorkerStats {
+    let mut stats = WorkerStats::default();
+    let mut connection: Option<BufReader<TcpStream>> = None;
+    while budget.take() {
+        let started = Instant::now();
+        let reader = match connection.take() {
+            Some(reader) => Ok(reader),
+            None => connect(addr, config.timeout).inspect(|_| stats.connections += 1),
+        };
+        let result = reader.and_then(|mut reader| {
+            reader.get_mut().write_all(request.as_bytes())?;
+            let response = read_response(&mut reader)?;
+            Ok((reader, response))
+        });
+        match result {
+            Ok((reader, response)) => {
+                stats.latencies.push(started.elapsed());
+                stats.bytes += response.bytes;
+                *stats.statuses.entry(response.status).or_default() += 1;
+                if config.keep_alive && response.keep_alive {
+                    connection = Some(reader);
+                }
+            }
+            Err(_) => stats.errors += 1,
+        }
+    }
+    stats
+}
+
+fn connect(addr: std::net::SocketAddr, timeout: Duration) -> io::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(BufReader::new(stream))
+}
+
+struct Received {
+    status: u16,
+    // 响应头加响应体的字节数
+    bytes: u64,
+    keep_alive: bool,
+}
+
+// 读一个响应，响应体按 chunked 或 Content-Length 读，都没有时读到连接关闭
+fn read_response<R: BufRead>(reader: &mut R) -> io::Result<Received> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed response");
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    let mut bytes = line.len() as u64;
+    let mut parts = line.split_whitespace();
+    let version = parts.next().ok_or_else(invalid)?.to_string();
+    let status: u16 = parts
+        .next()
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut keep_alive = version == "HTTP/1.1";
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        bytes += line.len() as u64;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<u64>().map_err(|_| invalid())?);
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        } else if name.eq_ignore_ascii_case("Connection") {
+            keep_alive = value.eq_ignore_ascii_case("keep-alive");
+        }
+    }
+
+    // 1xx、204、304 没有响应体
+    let no_body = (100..200).contains(&status) || status == 204 || status == 304;
+    if no_body {
+        content_length = Some(0);
+    }
+    bytes += match content_length {
+        _ if chunked && !no_body => {
+            io::copy(&mut ChunkedReader::new(&mut *reader), &mut io::sink())?
+        }
+        Some(length) => io::copy(&mut reader.take(length), &mut io::sink()).and_then(|read| {
+            if read == length {
+                Ok(read)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated body",
+                ))
+            }
+        })?,
+        None => {
+            keep_alive = false;
+            io::copy(reader, &mut io::sink())?
+        }
+    };
+    Ok(Received {
+        status,
+        bytes,
+        keep_alive,
+    })
+}
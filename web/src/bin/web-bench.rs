@@ -0,0 +1,76 @@
+use clap::{Parser, ValueEnum};
+use std::process;
+use std::time::Duration;
+use web::bench::{self, BenchConfig, Target};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ConnectionMode {
+    // 每个并发连接上连续发请求
+    KeepAlive,
+    // 每个请求新建连接
+    OneShot,
+}
+
+#[derive(Parser)]
+#[command(name = "web-bench")]
+#[command(version = "1.0.0")]
+#[command(about = "http load generator for the web server", long_about = None)]
+struct Cli {
+    #[arg(help = "target url, e.g. http://127.0.0.1:7878/")]
+    url: String,
+
+    #[arg(short, long, default_value_t = 10, help = "concurrent connections")]
+    concurrency: usize,
+
+    #[arg(short = 'n', long, default_value_t = 1000, help = "total requests")]
+    requests: usize,
+
+    #[arg(
+        short,
+        long,
+        help = "run for this many seconds instead of a fixed number of requests"
+    )]
+    duration: Option<u64>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value = "keep-alive",
+        help = "connection reuse"
+    )]
+    mode: ConnectionMode,
+
+    #[arg(short, long, default_value_t = 10, help = "socket timeout in seconds")]
+    timeout: u64,
+
+    #[arg(long, help = "print the report as json")]
+    json: bool,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let target = match Target::parse(&cli.url) {
+        Ok(target) => target,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(2);
+        }
+    };
+
+    let mut config = BenchConfig::new(target);
+    config.concurrency = cli.concurrency;
+    config.requests = cli.requests;
+    config.duration = cli.duration.map(Duration::from_secs);
+    config.keep_alive = matches!(cli.mode, ConnectionMode::KeepAlive);
+    config.timeout = Duration::from_secs(cli.timeout);
+
+    match bench::run(&config) {
+        Ok(report) if cli.json => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        Ok(report) => println!("{report}"),
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::async_server::AsyncHandler;
+use crate::http::{Handler, Request, Response};
+use flate2::write::GzEncoder;
+use futures::future::BoxFuture;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+// 服务端支持的内容编码，客户端给的权重相同时按这里的顺序优先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+
+    fn matches(self, coding: &str) -> bool {
+        coding == self.as_str() || (self == Encoding::Gzip && coding == "x-gzip")
+    }
+}
+
+// 按 Accept-Encoding 里的权重（q 值）从 supported 里选一个编码，
+// 权重相同优先压缩，都不可接受或者没带这个头时返回 Identity
+pub fn negotiate(accept_encoding: Option<&str>, supported: &[Encoding]) -> Encoding {
+    let Some(accept_encoding) = accept_encoding else {
+        return Encoding::Identity;
+    };
+
+    let mut weights = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        let mut quality = Some(1.0);
+        for param in params {
+            if let Some((name, value)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q")
+            {
+                quality = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q));
+            }
+        }
+        // q 值不合法的项直接忽略
+        if let Some(quality) = quality {
+            weights.push((coding, quality));
+        }
+    }
+
+    // 没有单独列出的编码用 * 的权重
+    let weight = |encoding: Encoding| {
+        let explicit = weights.iter().find(|(name, _)| encoding.matches(name));
+        let any = weights.iter().find(|(name, _)| name == "*");
+        explicit.or(any).map(|&(_, quality)| quality)
+    };
+
+    let identity = weight(Encoding::Identity).unwrap_or(1.0);
+    let mut best = (Encoding::Identity, identity);
+    for &encoding in supported {
+        if encoding == Encoding::Identity {
+            continue;
+        }
+        let quality = weight(encoding).unwrap_or(0.0);
+        if quality > 0.0
+            && (quality > best.1 || (quality == best.1 && best.0 == Encoding::Identity))
+        {
+            best = (encoding, quality);
+        }
+    }
+    best.0
+}
+
+// 值得压缩的文本类型，图片、视频这类本身已经压缩过的不再压缩
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+pub fn encode(encoding: Encoding, data: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Brotli => {
+            let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            writer.write_all(data)?;
+            Ok(writer.into_inner())
+        }
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Identity => Ok(data.to_vec()),
+    }
+}
+
+// 响应压缩的配置
+#[derive(Debug, Clone)]
+pub struct Compression {
+    // 小于这个大小的响应不压缩，压缩省下的字节抵不上开销
+    pub min_size: usize,
+    pub encodings: Vec<Encoding>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            encodings: vec![Encoding::Brotli, Encoding::Gzip],
+        }
+    }
+}
+
+impl Compression {
+    // 按请求的 Accept-Encoding 压缩响应；可以压缩的类型都会带上 Vary: Accept-Encoding，
+    // 方便缓存区分不同编码的版本
+    pub fn apply(&self, accept_encoding: Option<&str>, response: &mut Response) {
+        // 流式响应体边读边发，不压缩
+        if matches!(response.status, 100..=199 | 204 | 304)
+            || response.is_streaming()
+            || response.header("Content-Encoding").is_some()
+            || !response.header("Content-Type").is_some_and(is_compressible)
+            || response
+                .header("Cache-Control")
+                .is_some_and(|value| value.to_ascii_lowercase().contains("no-transform"))
+        {
+            return;
+        }
+        response.add_vary("Accept-Encoding");
+        if response.body.len() < self.min_size {
+            return;
+        }
+
+        let encoding = negotiate(accept_encoding, &self.encodings);
+        if encoding == Encoding::Identity {
+            return;
+        }
+        // 压缩失败或者没变小就原样发送
+        if let Ok(body) = encode(encoding, &response.body)
+            && body.len() < response.body.len()
+        {
+            response.body = body.into();
+            response.set_header("Content-Encoding", encoding.as_str());
+        }
+    }
+}
+
+// 给 handler 的响应加上压缩
+pub fn compress(handler: impl Handler, compression: Compression) -> impl Handler {
+    Compressed {
+        handler,
+        compression,
+    }
+}
+
+struct Compressed<H> {
+    handler: H,
+    compression: Compression,
+}
+
+impl<H: Handler> Handler for Compressed<H> {
+    fn handle(&self, request: &Request) -> Response {
+        let mut response = self.handler.handle(request);
+        self.compression
+            .apply(request.header("Accept-Encoding"), &mut response);
+        response
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.handler.streams_body(request)
+    }
+}
+
+// compress 的异步版本
+pub fn compress_async(handler: impl AsyncHandler, compression: Compression) -> impl AsyncHandler {
+    let compression = Arc::new(compression);
+    move |request: Request| -> BoxFuture<'static, Response> {
+        let accept_encoding = request.header("Accept-Encoding").map(str::to_string);
+        let response = handler.handle(request);
+        let compression = Arc::clone(&compression);
+        Box::pin(async move {
+            let mut response = response.await;
+            compression.apply(accept_encoding.as_deref(), &mut response);
+            response
+        })
+    }
+}
@@ -0,0 +1,609 @@
+use crate::access_log::LogFormat;
+use crate::async_server::AsyncHandler;
+use crate::file_cache::FileCache;
+use crate::http::Handler;
+use crate::middleware::{BasicAuth, Chain, Cors, RequestId, Timing, scoped};
+use crate::proxy::Proxy;
+use crate::server::Limits;
+use crate::tls::TlsConfigBuilder;
+use crate::vhost::VirtualHosts;
+use crate::{site, site_async};
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{error, fmt, fs, io};
+
+// 没有用 --config 指定时，当前目录下有这个文件就读它
+pub const DEFAULT_CONFIG_FILE: &str = "web.toml";
+
+// 分发模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    // 单线程逐个处理连接
+    Single,
+    // 每个连接一个线程
+    #[serde(alias = "thread-per-conn")]
+    #[value(alias = "thread-per-conn")]
+    Threads,
+    // 有界线程池，队列满时回 503
+    #[default]
+    Pool,
+    // mio 事件循环收发，handler 在工作线程池上运行
+    EventLoop,
+    // futures 线程池 + mio reactor
+    Async,
+}
+
+// 配置文件里的超时以秒为单位
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub read_timeout_secs: u64,
+    pub write_timeout_secs: u64,
+    pub header_timeout_secs: u64,
+    pub body_timeout_secs: u64,
+    pub keep_alive_timeout_secs: u64,
+    pub max_body_size: usize,
+    // 0 表示不限制
+    pub max_connections_per_ip: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        LimitsConfig {
+            read_timeout_secs: limits.read_timeout.as_secs(),
+            write_timeout_secs: limits.write_timeout.as_secs(),
+            header_timeout_secs: limits.header_timeout.as_secs(),
+            body_timeout_secs: limits.body_timeout.as_secs(),
+            keep_alive_timeout_secs: limits.keep_alive_timeout.as_secs(),
+            max_body_size: limits.max_body_size,
+            max_connections_per_ip: limits.max_connections_per_ip,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn to_limits(&self) -> Limits {
+        Limits {
+            read_timeout: Duration::from_secs(self.read_timeout_secs),
+            write_timeout: Duration::from_secs(self.write_timeout_secs),
+            header_timeout: Duration::from_secs(self.header_timeout_secs),
+            body_timeout: Duration::from_secs(self.body_timeout_secs),
+            keep_alive_timeout: Duration::from_secs(self.keep_alive_timeout_secs),
+            max_body_size: self.max_body_size,
+            max_connections_per_ip: self.max_connections_per_ip,
+        }
+    }
+}
+
+// 证书和私钥都是 PEM 文件；cert、key 是默认证书，sni 按客户端请求的主机名另外选证书
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    #[serde(default = "default_tls_address")]
+    pub address: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub sni: Vec<SniCert>,
+}
+
+// 一个主机名的证书，主机名可以写成 *.example.com 通配一级子域名
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCert {
+    pub host: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsSettings {
+    pub fn builder(&self) -> TlsConfigBuilder {
+        self.sni.iter().fold(
+            TlsConfigBuilder::new().cert(&self.cert, &self.key),
+            |builder, sni| builder.sni_cert(&sni.host, &sni.cert, &sni.key),
+        )
+    }
+}
+
+fn default_tls_address() -> String {
+    String::from("0.0.0.0:7443")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // 为空或者包含 * 时允许任意来源
+    pub allow_origins: Vec<String>,
+    pub allow_methods: Vec<String>,
+    // 为空时预检请求要求的头都允许
+    pub allow_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allow_origins: Vec::new(),
+            allow_methods: vec!["GET".into(), "HEAD".into(), "POST".into()],
+            allow_headers: Vec::new(),
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthConfig {
+    #[serde(default = "default_realm")]
+    pub realm: String,
+    // 只保护这个路径前缀下的请求
+    #[serde(default = "default_auth_path")]
+    pub path: String,
+    // 用户名 -> 密码
+    pub users: HashMap<String, String>,
+}
+
+fn default_realm() -> String {
+    String::from("web")
+}
+
+fn default_auth_path() -> String {
+    String::from("/")
+}
+
+// [[proxy]]：把 prefix 下的请求转发到 upstreams
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub prefix: String,
+    pub upstreams: Vec<String>,
+    #[serde(default)]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub preserve_host: bool,
+    // 连续失败几次后摘除
+    #[serde(default = "default_max_fails")]
+    pub max_fails: usize,
+    #[serde(default = "default_fail_timeout_secs")]
+    pub fail_timeout_secs: u64,
+    #[serde(default = "default_proxy_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_max_fails() -> usize {
+    1
+}
+
+fn default_fail_timeout_secs() -> u64 {
+    10
+}
+
+fn default_proxy_timeout_secs() -> u64 {
+    30
+}
+
+impl ProxyConfig {
+    pub fn to_proxy(&self) -> Proxy {
+        Proxy::new(&self.prefix, &self.upstreams)
+            .strip_prefix(self.strip_prefix)
+            .preserve_host(self.preserve_host)
+            .max_fails(self.max_fails)
+            .fail_timeout(Duration::from_secs(self.fail_timeout_secs))
+            .timeout(Duration::from_secs(self.timeout_secs))
+    }
+}
+
+fn with_proxies<H>(chain: Chain<H>, proxies: &[ProxyConfig]) -> Chain<H> {
+    proxies
+        .iter()
+        .fold(chain, |chain, proxy| chain.with(proxy.to_proxy()))
+}
+
+// [[vhost]]：names 里的主机名（可以是 *.example.com）用自己的文档根目录和反向代理
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VhostConfig {
+    pub names: Vec<String>,
+    pub doc_root: PathBuf,
+    #[serde(default)]
+    pub proxy: Vec<ProxyConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiddlewareConfig {
+    pub request_id: bool,
+    pub timing: bool,
+    // 配置了才启用
+    pub cors: Option<CorsConfig>,
+    pub basic_auth: Option<BasicAuthConfig>,
+}
+
+impl Default for MiddlewareConfig {
+    fn default() -> Self {
+        MiddlewareConfig {
+            request_id: true,
+            timing: true,
+            cors: None,
+            basic_auth: None,
+        }
+    }
+}
+
+impl MiddlewareConfig {
+    // 按配置给路由套上中间件，从外到内依次是 request id、耗时、CORS、认证，
+    // CORS 在认证外面，预检请求不需要带凭据
+    pub fn chain<H>(&self, handler: H) -> Chain<H> {
+        let mut chain = Chain::new(handler);
+        if self.request_id {
+            chain = chain.with(RequestId::new());
+        }
+        if self.timing {
+            chain = chain.with(Timing);
+        }
+        if let Some(cors) = &self.cors {
+            let mut middleware = Cors::new()
+                .allow_methods(cors.allow_methods.clone())
+                .allow_headers(cors.allow_headers.clone())
+                .allow_credentials(cors.allow_credentials);
+            for origin in &cors.allow_origins {
+                middleware = middleware.allow_origin(origin);
+            }
+            if let Some(secs) = cors.max_age_secs {
+                middleware = middleware.max_age(Duration::from_secs(secs));
+            }
+            chain = chain.with(middleware);
+        }
+        if let Some(auth) = &self.basic_auth {
+            let mut middleware = BasicAuth::new(&auth.realm);
+            for (name, password) in &auth.users {
+                middleware = middleware.user(name, password);
+            }
+            chain = chain.with(scoped(&auth.path, middleware));
+        }
+        chain
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: String,
+    pub mode: Mode,
+    // pool 模式下是核心线程数，event-loop、async 模式下是工作线程数
+    pub workers: usize,
+    pub max_workers: usize,
+    pub queue_capacity: usize,
+    pub doc_root: PathBuf,
+    // 静态文件缓存的大小上限（字节），0 表示不缓存
+    pub file_cache_bytes: usize,
+    pub log_dir: PathBuf,
+    pub access_log_format: LogFormat,
+    pub limits: LimitsConfig,
+    pub middleware: MiddlewareConfig,
+    // 对所有主机生效
+    pub proxy: Vec<ProxyConfig>,
+    pub vhost: Vec<VhostConfig>,
+    // 配置了才另外提供 HTTPS
+    pub tls: Option<TlsSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: String::from("0.0.0.0:7878"),
+            mode: Mode::default(),
+            workers: 4,
+            max_workers: 8,
+            queue_capacity: 16,
+            doc_root: PathBuf::from(crate::DOC_ROOT),
+            file_cache_bytes: 16 * 1024 * 1024,
+            log_dir: PathBuf::from("logs"),
+            access_log_format: LogFormat::Combined,
+            limits: LimitsConfig::default(),
+            middleware: MiddlewareConfig::default(),
+            proxy: Vec::new(),
+            vhost: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "invalid config {}: {err}", path.display()),
+            ConfigError::Invalid(message) => write!(f, "invalid config: {message}"),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl Config {
+    // 路由外面套上中间件，反向代理在最里层，认证对代理的路径同样生效
+    pub fn chain<H>(&self, handler: H) -> Chain<H> {
+        with_proxies(self.middleware.chain(handler), &self.proxy)
+    }
+
+    // 各个虚拟主机的站点，没有匹配的主机交给顶层 doc_root 的默认站点
+    pub fn virtual_hosts(&self, cache: Option<&Arc<FileCache>>) -> VirtualHosts<dyn Handler> {
+        let site = |doc_root: &PathBuf| site(doc_root.clone(), cache.cloned());
+        let default: Arc<dyn Handler> = Arc::new(site(&self.doc_root));
+        self.vhost
+            .iter()
+            .fold(VirtualHosts::new(default), |hosts, vhost| {
+                let site: Arc<dyn Handler> = Arc::new(with_proxies(
+                    Chain::new(site(&vhost.doc_root)),
+                    &vhost.proxy,
+                ));
+                vhost
+                    .names
+                    .iter()
+                    .fold(hosts, |hosts, name| hosts.host(name, Arc::clone(&site)))
+            })
+    }
+
+    // virtual_hosts 的异步版本
+    pub fn virtual_hosts_async(
+        &self,
+        cache: Option<&Arc<FileCache>>,
+    ) -> VirtualHosts<dyn AsyncHandler> {
+        let site = |doc_root: &PathBuf| site_async(doc_root.clone(), cache.cloned());
+        let default: Arc<dyn AsyncHandler> = Arc::new(site(&self.doc_root));
+        self.vhost
+            .iter()
+            .fold(VirtualHosts::new(default), |hosts, vhost| {
+                let site: Arc<dyn AsyncHandler> = Arc::new(with_proxies(
+                    Chain::new(site(&vhost.doc_root)),
+                    &vhost.proxy,
+                ));
+                vhost
+                    .names
+                    .iter()
+                    .fold(hosts, |hosts, name| hosts.host(name, Arc::clone(&site)))
+            })
+    }
+
+    pub fn file_cache(&self) -> Option<Arc<FileCache>> {
+        (self.file_cache_bytes > 0).then(|| Arc::new(FileCache::new(self.file_cache_bytes)))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Config::from_toml(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.workers == 0 {
+            return Err(ConfigError::Invalid(
+                "workers must be greater than 0".into(),
+            ));
+        }
+        // 只有 pool 模式用到 max_workers
+        if self.mode == Mode::Pool && self.max_workers < self.workers {
+            return Err(ConfigError::Invalid(format!(
+                "max_workers ({}) must not be less than workers ({})",
+                self.max_workers, self.workers
+            )));
+        }
+        if let Some(vhost) = self.vhost.iter().find(|vhost| vhost.names.is_empty()) {
+            return Err(ConfigError::Invalid(format!(
+                "vhost {} has no names",
+                vhost.doc_root.display()
+            )));
+        }
+        let proxies = self.vhost.iter().flat_map(|vhost| &vhost.proxy);
+        if let Some(proxy) = self
+            .proxy
+            .iter()
+            .chain(proxies)
+            .find(|proxy| proxy.upstreams.is_empty())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "proxy {} has no upstreams",
+                proxy.prefix
+            )));
+        }
+        if self.queue_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "queue_capacity must be greater than 0".into(),
+            ));
+        }
+        // socket 超时不能设为 0
+        let limits = &self.limits;
+        let timeouts = [
+            ("read_timeout_secs", limits.read_timeout_secs),
+            ("write_timeout_secs", limits.write_timeout_secs),
+            ("header_timeout_secs", limits.header_timeout_secs),
+            ("body_timeout_secs", limits.body_timeout_secs),
+            ("keep_alive_timeout_secs", limits.keep_alive_timeout_secs),
+        ];
+        if let Some((name, _)) = timeouts.iter().find(|(_, secs)| *secs == 0) {
+            return Err(ConfigError::Invalid(format!(
+                "limits.{name} must be greater than 0"
+            )));
+        }
+        if let Some(tls) = &self.tls {
+            validate_sni(&tls.sni)?;
+        }
+        Ok(())
+    }
+}
+
+// 主机名不能为空、不能带端口，通配只能是开头的 *.，同一个主机名只能配置一次
+fn validate_sni(sni: &[SniCert]) -> Result<(), ConfigError> {
+    let mut hosts = HashSet::new();
+    for cert in sni {
+        let host = cert.host.to_ascii_lowercase();
+        let name = host.strip_prefix("*.").unwrap_or(&host);
+        if name.is_empty() || name.contains(['*', ':', '/']) || name.split('.').any(str::is_empty) {
+            return Err(ConfigError::Invalid(format!(
+                "tls.sni host {:?} is not a valid host name",
+                cert.host
+            )));
+        }
+        if !hosts.insert(host) {
+            return Err(ConfigError::Invalid(format!(
+                "tls.sni host {} is configured more than once",
+                cert.host
+            )));
+        }
+    }
+    Ok(())
+}
+
+// 命令行参数，指定了的会覆盖配置文件里的值
+#[derive(Debug, Default, Parser)]
+#[command(name = "web")]
+#[command(version = "1.0.0")]
+#[command(about = "a small http server", long_about = None)]
+pub struct Cli {
+    #[arg(short, long, help = "config file, defaults to ./web.toml if it exists")]
+    pub config: Option<PathBuf>,
+
+    #[arg(short, long, help = "listen address, e.g. 0.0.0.0:7878")]
+    pub address: Option<String>,
+
+    #[arg(short, long, value_enum, help = "dispatch mode")]
+    pub mode: Option<Mode>,
+
+    #[arg(short, long, help = "worker threads")]
+    pub workers: Option<usize>,
+
+    #[arg(long, help = "max worker threads of the pool")]
+    pub max_workers: Option<usize>,
+
+    #[arg(long, help = "queue capacity of the pool")]
+    pub queue_capacity: Option<usize>,
+
+    #[arg(short, long, help = "document root")]
+    pub doc_root: Option<PathBuf>,
+
+    #[arg(long, help = "static file cache size in bytes, 0 to disable")]
+    pub file_cache_bytes: Option<usize>,
+
+    #[arg(long, help = "directory of access logs")]
+    pub log_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum, help = "access log format")]
+    pub access_log_format: Option<LogFormat>,
+
+    #[arg(long, help = "max request body size in bytes")]
+    pub max_body_size: Option<usize>,
+
+    #[arg(
+        long,
+        help = "max concurrent connections per client ip, 0 for unlimited"
+    )]
+    pub max_connections_per_ip: Option<usize>,
+
+    #[arg(long, help = "seconds to wait for request headers")]
+    pub header_timeout: Option<u64>,
+
+    #[arg(long, help = "seconds to receive a request body after its headers")]
+    pub body_timeout: Option<u64>,
+
+    #[arg(long, help = "seconds to keep an idle connection")]
+    pub keep_alive_timeout: Option<u64>,
+
+    #[arg(long, help = "socket read timeout in seconds")]
+    pub read_timeout: Option<u64>,
+
+    #[arg(long, help = "socket write timeout in seconds")]
+    pub write_timeout: Option<u64>,
+
+    #[arg(long, requires = "tls_key", help = "certificate (PEM) to serve https")]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(long, requires = "tls_cert", help = "private key (PEM) to serve https")]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(long, help = "https listen address, e.g. 0.0.0.0:7443")]
+    pub tls_address: Option<String>,
+}
+
+impl Cli {
+    // 读配置文件（没有就用默认值），再用命令行参数覆盖
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        set(&mut config.address, &self.address);
+        set(&mut config.mode, &self.mode);
+        set(&mut config.workers, &self.workers);
+        set(&mut config.max_workers, &self.max_workers);
+        set(&mut config.queue_capacity, &self.queue_capacity);
+        set(&mut config.doc_root, &self.doc_root);
+        set(&mut config.file_cache_bytes, &self.file_cache_bytes);
+        set(&mut config.log_dir, &self.log_dir);
+        set(&mut config.access_log_format, &self.access_log_format);
+
+        let limits = &mut config.limits;
+        set(&mut limits.max_body_size, &self.max_body_size);
+        set(
+            &mut limits.max_connections_per_ip,
+            &self.max_connections_per_ip,
+        );
+        set(&mut limits.header_timeout_secs, &self.header_timeout);
+        set(&mut limits.body_timeout_secs, &self.body_timeout);
+        set(
+            &mut limits.keep_alive_timeout_secs,
+            &self.keep_alive_timeout,
+        );
+        set(&mut limits.read_timeout_secs, &self.read_timeout);
+        set(&mut limits.write_timeout_secs, &self.write_timeout);
+
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            let address = config.tls.as_ref().map(|tls| tls.address.clone());
+            // 命令行只能指定默认证书，配置文件里按主机名配置的证书保留
+            let sni = config.tls.take().map(|tls| tls.sni).unwrap_or_default();
+            config.tls = Some(TlsSettings {
+                address: address.unwrap_or_else(default_tls_address),
+                cert: cert.clone(),
+                key: key.clone(),
+                sni,
+            });
+        }
+        if let (Some(tls), Some(address)) = (&mut config.tls, &self.tls_address) {
+            tls.address = address.clone();
+        }
+    }
+}
@@ -0,0 +1,900 @@
+use crate::access_log::{AccessEntry, LogFormat, log_access};
+use crate::http::{
+    BodyLength, BodyReceiver, BodyStream, ChunkedDecoder, Handler, LAST_CHUNK, MAX_HEAD_SIZE,
+    Request, Response, TryRecv, encode_chunk,
+};
+use crate::server::{
+    ConnectionPermit, Limiter, Limits, parse_error_response, payload_too_large, request_timeout,
+    respond, too_many_connections,
+};
+use crate::thread_pool::{ExecuteError, ThreadPool};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+use rustls::{ServerConfig, ServerConnection};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+const LISTENER: Token = Token(usize::MAX);
+// 推送式响应体有新数据、工作线程处理完请求时用来唤醒事件循环
+const WAKER: Token = Token(usize::MAX - 1);
+// 写缓冲区积压超过这么多时先不从推送式响应体里取数据
+const MAX_PENDING_WRITE: usize = 64 * 1024;
+// 检查连接超时的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_millis(250);
+
+// 基于 epoll（mio）的非阻塞事件循环：每个线程一个 Poll，
+// 各自从同一个监听 socket 上 accept，连接由所在线程的事件循环收发；
+// handler 在另外 threads 个工作线程上运行，慢的 handler 不会卡住事件循环
+pub fn run(
+    listener: std::net::TcpListener,
+    handler: Arc<dyn Handler>,
+    threads: usize,
+) -> io::Result<()> {
+    run_with_limits(listener, handler, threads, Limiter::default())
+}
+
+// 同 run，各线程共用同一个 Limiter，按 IP 限制的连接数跨线程统计
+pub fn run_with_limits(
+    listener: std::net::TcpListener,
+    handler: Arc<dyn Handler>,
+    threads: usize,
+    limiter: Limiter,
+) -> io::Result<()> {
+    start(listener, handler, threads, limiter, None)
+}
+
+// 同 run_with_limits，连接上先做 TLS 握手，握手和加解密都在事件循环里非阻塞地进行
+pub fn run_tls(
+    listener: std::net::TcpListener,
+    handler: Arc<dyn Handler>,
+    threads: usize,
+    limiter: Limiter,
+    tls: Arc<ServerConfig>,
+) -> io::Result<()> {
+    start(listener, handler, threads, limiter, Some(tls))
+}
+
+fn start(
+    listener: std::net::TcpListener,
+    handler: Arc<dyn Handler>,
+    threads: usize,
+    limiter: Limiter,
+    tls: Option<Arc<ServerConfig>>,
+) -> io::Result<()> {
+    assert!(threads > 0);
+    listener.set_nonblocking(true)?;
+
+    let workers = Arc::new(
+        ThreadPool::builder()
+            .core_size(threads)
+            .thread_name("web-event-worker")
+            .build(),
+    );
+    let mut loops = Vec::with_capacity(threads);
+    for id in 0..threads {
+        let listener = TcpListener::from_std(listener.try_clone()?);
+        let handler = Arc::clone(&handler);
+        let workers = Arc::clone(&workers);
+        let limiter = limiter.clone();
+        let tls = tls.clone();
+        let thread = thread::Builder::new()
+            .name(format!("web-event-loop-{id}"))
+            .spawn(move || EventLoop::new(listener, handler, workers, limiter, tls)?.run())?;
+        loops.push(thread);
+    }
+
+    for thread in loops {
+        thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("event loop panicked")))?;
+    }
+    Ok(())
+}
+
+// 工作线程处理完的请求，交回事件循环发送；id 用来认出 token 已经被新连接复用的情况
+struct Completion {
+    token: Token,
+    id: u64,
+    // 请求体已经丢掉，留着记访问日志
+    request: Request,
+    started: Instant,
+    response: Response,
+    body: Option<BodyReceiver>,
+    keep_alive: bool,
+}
+
+// 放进了写缓冲区、还没写完的响应，写完时记访问日志；
+// start 和 end 是响应体在这条连接累计输出里的位置，推送式响应体发完之前 end 为 None
+struct Sending {
+    request: Request,
+    status: u16,
+    started: Instant,
+    start: usize,
+    end: Option<usize>,
+}
+
+// 推送式响应体有新数据或者结束时，把连接记进 ready 并唤醒事件循环；
+// 工作线程处理完请求时把结果放进 done 并唤醒事件循环
+#[derive(Clone)]
+struct Wakeup {
+    waker: Arc<Waker>,
+    ready: Arc<Mutex<Vec<Token>>>,
+    done: Arc<Mutex<Vec<Completion>>>,
+}
+
+impl Wakeup {
+    fn watch(&self, body: &BodyReceiver, token: Token) {
+        let wakeup = self.clone();
+        body.set_notify(move || {
+            wakeup
+                .ready
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(token);
+            let _ = wakeup.waker.wake();
+        });
+    }
+
+    fn take_ready(&self) -> Vec<Token> {
+        std::mem::take(&mut *self.ready.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    fn complete(&self, completion: Completion) {
+        self.done
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(completion);
+        let _ = self.waker.wake();
+    }
+
+    fn take_done(&self) -> Vec<Completion> {
+        std::mem::take(&mut *self.done.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+// 把请求交给工作线程池，handler 的返回值经 Wakeup 交回事件循环
+struct Dispatcher {
+    handler: Arc<dyn Handler>,
+    workers: Arc<ThreadPool>,
+    wakeup: Wakeup,
+}
+
+impl Dispatcher {
+    fn dispatch(
+        &self,
+        mut request: Request,
+        token: Token,
+        id: u64,
+        started: Instant,
+    ) -> Result<(), ExecuteError> {
+        let handler = Arc::clone(&self.handler);
+        let wakeup = self.wakeup.clone();
+        self.workers.execute(move || {
+            // handler panic 时回 500 并关闭连接，不让连接一直等下去
+            let (mut response, keep_alive) =
+                match panic::catch_unwind(AssertUnwindSafe(|| respond(handler.as_ref(), &request)))
+                {
+                    Ok(response) => (response, request.keep_alive()),
+                    Err(_) => {
+                        warn!(path = %request.path, "handler panicked");
+                        let response = Response::text(500, "Internal Server Error")
+                            .with_header("Connection", "close");
+                        (response, false)
+                    }
+                };
+            // 普通的流式响应体在工作线程上读完，事件循环里只做非阻塞的读写
+            let body = response.stream.as_ref().and_then(BodyStream::take_channel);
+            if body.is_none()
+                && let Err(err) = response.buffer()
+            {
+                warn!("read response body failed: {err}");
+            }
+            request.body = Vec::new();
+            wakeup.complete(Completion {
+                token,
+                id,
+                request,
+                started,
+                response,
+                body,
+                keep_alive,
+            });
+        })
+    }
+}
+
+struct Connection {
+    id: u64,
+    stream: TcpStream,
+    addr: SocketAddr,
+    // HTTPS 连接的 TLS 状态，read_buf 和 write_buf 里始终是明文
+    tls: Option<ServerConnection>,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    // 正在发送的推送式响应体，发完之前不处理后面的请求
+    body: Option<BodyReceiver>,
+    // 有请求在工作线程上处理，处理完之前不处理后面的请求
+    handling: bool,
+    // 响应写完后关闭连接
+    closing: bool,
+    // 对端已经关闭了写方向，手上的请求处理完就关闭
+    eof: bool,
+    // 读缓冲区满了，暂停读，等 process 取走请求腾出地方再读
+    paused: bool,
+    // 在 Poll 上关注的事件，读暂停并且没有要写的数据时什么都不关注，从 Poll 上注销
+    interest: Option<Interest>,
+    // 已经读完请求头，正在等请求体
+    reading_body: bool,
+    // 是否已经处理过请求，用来区分新连接和空闲的长连接
+    served: bool,
+    // 当前这次等待（等请求或者读请求头）开始的时间
+    started: Instant,
+    // 开始等请求体的时间
+    body_started: Instant,
+    // 正在接收的 chunked 请求体，请求头已经从读缓冲区里取走了
+    chunked: Option<(Request, ChunkedDecoder)>,
+    last_read: Instant,
+    last_write: Instant,
+    // 累计放进写缓冲区和已经写出去的字节数，用来算每个响应实际发出去多少
+    queued: usize,
+    sent: usize,
+    sending: VecDeque<Sending>,
+    access_log: Option<LogFormat>,
+    _permit: ConnectionPermit,
+}
+
+impl Connection {
+    // 尽可能多地读，edge-triggered 模式下必须读到 WouldBlock 为止；返回对端是否已关闭。
+    // 读缓冲区最多放一个最大的请求（请求头加请求体），满了就暂停读，
+    // 不然 handler 处理得慢时对端可以一直往里塞数据
+    fn fill(&mut self, limits: &Limits) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        loop {
+            if self.read_buf.len() >= read_limit(limits) {
+                self.paused = true;
+                return Ok(false);
+            }
+            let read = match &mut self.tls {
+                Some(tls) => tls.read_tls(&mut self.stream),
+                None => self.stream.read(&mut chunk),
+            };
+            match read {
+                Ok(0) => return Ok(true),
+                Ok(_) if self.tls.is_some() => {
+                    if self.decrypt()? {
+                        return Ok(true);
+                    }
+                }
+                Ok(read) => self.received(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // 处理收到的 TLS 记录，解密出的明文放进读缓冲区；返回对端是否已经发了 close_notify
+    fn decrypt(&mut self) -> io::Result<bool> {
+        let Some(tls) = &mut self.tls else {
+            return Ok(false);
+        };
+        if let Err(err) = tls.process_new_packets() {
+            // 尽量把 alert 发给对端
+            let _ = tls.write_tls(&mut self.stream);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        let mut plaintext = Vec::new();
+        let closed = match tls.reader().read_to_end(&mut plaintext) {
+            Ok(_) => true,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => false,
+            Err(err) => return Err(err),
+        };
+        if !plaintext.is_empty() {
+            self.received(&plaintext);
+        }
+        Ok(closed)
+    }
+
+    fn received(&mut self, bytes: &[u8]) {
+        let now = Instant::now();
+        // 空闲的长连接上来了新请求，请求头期限从这里开始算
+        if self.read_buf.is_empty() && self.served && !self.reading_body {
+            self.started = now;
+        }
+        self.last_read = now;
+        self.read_buf.extend_from_slice(bytes);
+    }
+
+    // 缓冲区里有完整的请求时交给工作线程处理；同一条连接上一次只处理一个，响应按请求的顺序发送
+    // 对端不读响应、写缓冲区积压时也先不处理，连同暂停读一起把对端挡住
+    fn process(&mut self, dispatcher: &Dispatcher, limits: &Limits, token: Token) {
+        if self.closing
+            || self.handling
+            || self.body.is_some()
+            || self.write_buf.len() >= MAX_PENDING_WRITE
+        {
+            return;
+        }
+        let Some(mut request) = self.next_request(limits) else {
+            return;
+        };
+        request.remote_addr = Some(self.addr);
+        request.secure = self.tls.is_some();
+        self.reading_body = false;
+        self.handling = true;
+        if let Err(err) = dispatcher.dispatch(request, token, self.id, self.started) {
+            warn!("{err}, reject request");
+            self.handling = false;
+            self.reject(
+                Response::text(503, "Service Unavailable").with_header("Connection", "close"),
+            );
+        }
+    }
+
+    // 工作线程处理完请求，把响应放进写缓冲区
+    fn finish(&mut self, completion: Completion, token: Token, wakeup: &Wakeup) {
+        let Completion {
+            request,
+            started,
+            mut response,
+            body,
+            keep_alive,
+            ..
+        } = completion;
+        let (start, end) = match body {
+            Some(body) => {
+                response.remove_header("Content-Length");
+                self.queue(&response.head_bytes());
+                wakeup.watch(&body, token);
+                self.body = Some(body);
+                (self.queued, None)
+            }
+            None => {
+                // worker 已经把流式响应体读出来了
+                let body = response.body.len();
+                self.queue(&response.head_bytes());
+                self.queue(&response.body);
+                (self.queued - body, Some(self.queued))
+            }
+        };
+        if self.access_log.is_some() {
+            self.sending.push_back(Sending {
+                request,
+                status: response.status,
+                started,
+                start,
+                end,
+            });
+        }
+        self.handling = false;
+        self.closing = !keep_alive;
+        self.served = true;
+        self.started = Instant::now();
+    }
+
+    // 从读缓冲区取出下一个完整的请求，还没收全时返回 None；请求有错时回错误响应，也返回 None
+    fn next_request(&mut self, limits: &Limits) -> Option<Request> {
+        if self.chunked.is_none() {
+            let (mut request, head_len) = match Request::parse_head(&self.read_buf) {
+                Ok(parsed) => parsed?,
+                Err(err) => {
+                    self.reject(parse_error_response(err));
+                    return None;
+                }
+            };
+            match request.body_length() {
+                Ok(BodyLength::Fixed(length)) if length > limits.max_body_size => {
+                    self.reject(payload_too_large());
+                    return None;
+                }
+                Ok(BodyLength::Fixed(length)) => {
+                    if self.read_buf.len() < head_len + length {
+                        self.start_body();
+                        return None;
+                    }
+                    request.body = self.read_buf[head_len..head_len + length].to_vec();
+                    self.read_buf.drain(..head_len + length);
+                    return Some(request);
+                }
+                Ok(BodyLength::Chunked) => {
+                    self.read_buf.drain(..head_len);
+                    self.chunked = Some((request, ChunkedDecoder::new(limits.max_body_size)));
+                    self.start_body();
+                }
+                Err(err) => {
+                    self.reject(parse_error_response(err));
+                    return None;
+                }
+            }
+        }
+
+        let (_, decoder) = self.chunked.as_mut()?;
+        match decoder.decode(&self.read_buf) {
+            Ok(used) => {
+                self.read_buf.drain(..used);
+            }
+            Err(err) => {
+                self.chunked = None;
+                self.reject(parse_error_response(err));
+                return None;
+            }
+        }
+        if !decoder.is_done() {
+            return None;
+        }
+        let (mut request, decoder) = self.chunked.take()?;
+        request.body = decoder.into_body();
+        Some(request)
+    }
+
+    fn start_body(&mut self) {
+        if !self.reading_body {
+            self.reading_body = true;
+            self.body_started = Instant::now();
+        }
+    }
+
+    // 把推送式响应体里已有的数据按 chunk 放进写缓冲区，返回是否放进了东西
+    fn pump(&mut self) -> bool {
+        let Some(mut body) = self.body.take() else {
+            return false;
+        };
+        let mut queued = false;
+        while self.write_buf.len() < MAX_PENDING_WRITE {
+            match body.try_recv() {
+                TryRecv::Data(data) => self.queue(&encode_chunk(&data)),
+                TryRecv::Empty => break,
+                TryRecv::Closed => {
+                    self.queue(LAST_CHUNK);
+                    if let Some(sending) = self.sending.back_mut() {
+                        sending.end = Some(self.queued);
+                    }
+                    self.started = Instant::now();
+                    return true;
+                }
+            }
+            queued = true;
+        }
+        self.body = Some(body);
+        queued
+    }
+
+    fn queue(&mut self, bytes: &[u8]) {
+        if self.write_buf.is_empty() {
+            self.last_write = Instant::now();
+        }
+        self.queued += bytes.len();
+        self.write_buf.extend_from_slice(bytes);
+    }
+
+    // 已经全部写出去的响应记访问日志
+    fn log_sent(&mut self) {
+        while self
+            .sending
+            .front()
+            .is_some_and(|sending| sending.end.is_some_and(|end| self.sent >= end))
+        {
+            if let Some(sending) = self.sending.pop_front() {
+                self.log(sending);
+            }
+        }
+    }
+
+    // 连接断开时没写完的响应按实际写出去的部分记
+    fn log(&self, sending: Sending) {
+        let Some(format) = self.access_log else {
+            return;
+        };
+        let end = sending.end.unwrap_or(self.sent).min(self.sent);
+        let entry = AccessEntry::new(
+            &sending.request,
+            sending.status,
+            end.saturating_sub(sending.start),
+            sending.started.elapsed(),
+        );
+        log_access(&entry, format);
+    }
+
+    // 回一个错误响应，写完后关闭连接；也记访问日志，没读到请求行时请求行记成 -
+    fn reject(&mut self, response: Response) {
+        self.queue(&response.head_bytes());
+        self.queue(&response.body);
+        self.closing = true;
+        if self.access_log.is_some() {
+            self.sending.push_back(Sending {
+                request: Request {
+                    remote_addr: Some(self.addr),
+                    ..Request::default()
+                },
+                status: response.status,
+                started: self.started,
+                start: self.queued - response.body.len(),
+                end: Some(self.queued),
+            });
+        }
+    }
+
+    // 检查是否超时：请求没在期限内读完的回 408；写不出去的和空闲的长连接返回 true，直接关闭
+    fn check_timeouts(&mut self, limits: &Limits, now: Instant) -> bool {
+        if !self.write_buf.is_empty() {
+            return now - self.last_write > limits.write_timeout;
+        }
+        // 推送式响应体什么时候有数据、handler 要处理多久都由 handler 决定，不算空闲
+        if self.closing || self.handling || self.body.is_some() {
+            return false;
+        }
+        if self.served && !self.reading_body && self.read_buf.is_empty() {
+            return now - self.started > limits.keep_alive_timeout;
+        }
+
+        let expired = if self.reading_body {
+            now - self.last_read > limits.read_timeout
+                || now - self.body_started > limits.body_timeout
+        } else if self.read_buf.is_empty() {
+            now - self.started > limits.header_timeout
+        } else {
+            now - self.started > limits.header_timeout || now - self.last_read > limits.read_timeout
+        };
+        if expired {
+            self.reject(request_timeout());
+        }
+        false
+    }
+
+    // 尽可能多地写，返回是否全部写完
+    fn flush(&mut self) -> io::Result<bool> {
+        let Some(tls) = &mut self.tls else {
+            return self.flush_plain();
+        };
+        loop {
+            if !self.write_buf.is_empty() {
+                let written = tls.writer().write(&self.write_buf)?;
+                self.sent += written;
+                self.write_buf.drain(..written);
+            }
+            if !tls.wants_write() {
+                return Ok(self.write_buf.is_empty());
+            }
+            match tls.write_tls(&mut self.stream) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(_) => self.last_write = Instant::now(),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn flush_plain(&mut self) -> io::Result<bool> {
+        while !self.write_buf.is_empty() {
+            match self.stream.write(&self.write_buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.last_write = Instant::now();
+                    self.sent += written;
+                    self.write_buf.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    // 处理收全了的请求，写出缓冲区并按需调整关注的事件，返回连接是否可以关闭
+    fn settle(
+        &mut self,
+        registry: &Registry,
+        dispatcher: &Dispatcher,
+        limits: &Limits,
+        token: Token,
+    ) -> io::Result<bool> {
+        let flushed = loop {
+            let backlogged = self.write_buf.len() >= MAX_PENDING_WRITE;
+            self.process(dispatcher, limits, token);
+            // 读缓冲区腾出了地方，接着读暂停时没读的数据
+            while self.paused && self.read_buf.len() < read_limit(limits) {
+                self.paused = false;
+                if self.fill(limits)? {
+                    self.eof = true;
+                }
+                self.process(dispatcher, limits, token);
+            }
+            let mut flushed = self.flush()?;
+            // 写缓冲区清空后，推送式响应体里可能还积压着数据；
+            // 响应体发完了就接着处理流水线上已经收到的请求
+            while flushed && self.pump() {
+                if self.body.is_none() {
+                    self.process(dispatcher, limits, token);
+                }
+                flushed = self.flush()?;
+            }
+            // 写缓冲区积压时没有处理后面的请求，写完了回头再处理一次
+            if !(backlogged && flushed) {
+                break flushed;
+            }
+        };
+        self.log_sent();
+        if flushed && !self.handling && ((self.closing && self.body.is_none()) || self.eof) {
+            if let Some(tls) = &mut self.tls {
+                tls.send_close_notify();
+                let _ = tls.write_tls(&mut self.stream);
+            }
+            return Ok(true);
+        }
+
+        let interest = match (self.paused, flushed) {
+            (false, true) => Some(Interest::READABLE),
+            (false, false) => Some(Interest::READABLE | Interest::WRITABLE),
+            (true, true) => None,
+            (true, false) => Some(Interest::WRITABLE),
+        };
+        if interest != self.interest {
+            match (self.interest, interest) {
+                (_, None) => registry.deregister(&mut self.stream)?,
+                (None, Some(interest)) => registry.register(&mut self.stream, token, interest)?,
+                (Some(_), Some(interest)) => {
+                    registry.reregister(&mut self.stream, token, interest)?
+                }
+            }
+            self.interest = interest;
+        }
+        Ok(false)
+    }
+}
+
+// 读缓冲区的上限：一个请求最大就是请求头加请求体
+fn read_limit(limits: &Limits) -> usize {
+    MAX_HEAD_SIZE.saturating_add(limits.max_body_size)
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        while let Some(sending) = self.sending.pop_front() {
+            self.log(sending);
+        }
+    }
+}
+
+struct EventLoop {
+    poll: Poll,
+    listener: TcpListener,
+    dispatcher: Dispatcher,
+    limiter: Limiter,
+    tls: Option<Arc<ServerConfig>>,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
+    next_id: u64,
+    last_sweep: Instant,
+}
+
+impl EventLoop {
+    fn new(
+        mut listener: TcpListener,
+        handler: Arc<dyn Handler>,
+        workers: Arc<ThreadPool>,
+        limiter: Limiter,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> io::Result<EventLoop> {
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        let wakeup = Wakeup {
+            waker: Arc::new(Waker::new(poll.registry(), WAKER)?),
+            ready: Arc::new(Mutex::new(Vec::new())),
+            done: Arc::new(Mutex::new(Vec::new())),
+        };
+        Ok(EventLoop {
+            poll,
+            listener,
+            dispatcher: Dispatcher {
+                handler,
+                workers,
+                wakeup,
+            },
+            limiter,
+            tls,
+            connections: HashMap::new(),
+            next_token: 0,
+            next_id: 0,
+            last_sweep: Instant::now(),
+        })
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            if let Err(err) = self.poll.poll(&mut events, Some(SWEEP_INTERVAL)) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(),
+                    WAKER => {
+                        for completion in self.dispatcher.wakeup.take_done() {
+                            self.complete(completion);
+                        }
+                        for token in self.dispatcher.wakeup.take_ready() {
+                            self.drive(token);
+                        }
+                    }
+                    token => self.drive(token),
+                }
+            }
+            if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+                self.sweep();
+            }
+        }
+    }
+
+    // 定期检查所有连接的超时
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        self.last_sweep = now;
+        let registry = self.poll.registry();
+        let limits = &self.limiter.limits;
+        let dispatcher = &self.dispatcher;
+        self.connections.retain(|&token, conn| {
+            if !conn.check_timeouts(limits, now)
+                && matches!(conn.settle(registry, dispatcher, limits, token), Ok(false))
+            {
+                return true;
+            }
+            debug!(addr = %conn.addr, "connection closed by timeout");
+            let _ = registry.deregister(&mut conn.stream);
+            false
+        });
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let (mut stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    warn!("accept failed: {err}");
+                    return;
+                }
+            };
+
+            let permit = match self.limiter.acquire(addr.ip()) {
+                Some(permit) => permit,
+                None => {
+                    // 非阻塞 socket，尽力写一次，写不完也不等；HTTPS 连接没握手没法回，直接关闭
+                    if self.tls.is_none() {
+                        let response = too_many_connections();
+                        let status = response.status;
+                        let body = response.body.len();
+                        let bytes = response.to_bytes();
+                        let written = stream.write(&bytes).unwrap_or(0);
+                        let request = Request {
+                            remote_addr: Some(addr),
+                            ..Request::default()
+                        };
+                        let body = written.saturating_sub(bytes.len() - body);
+                        self.limiter
+                            .log_access(&request, status, body, Instant::now());
+                    }
+                    debug!(%addr, "too many connections from the same ip");
+                    continue;
+                }
+            };
+
+            let tls = match self
+                .tls
+                .as_ref()
+                .map(|tls| ServerConnection::new(Arc::clone(tls)))
+            {
+                Some(Ok(conn)) => Some(conn),
+                Some(Err(err)) => {
+                    warn!("tls setup failed: {err}");
+                    continue;
+                }
+                None => None,
+            };
+
+            let token = Token(self.next_token);
+            self.next_token = (self.next_token + 1) % WAKER.0;
+            if let Err(err) = self
+                .poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)
+            {
+                warn!("register connection failed: {err}");
+                continue;
+            }
+            debug!(%addr, "accepted connection");
+            let now = Instant::now();
+            self.next_id += 1;
+            self.connections.insert(
+                token,
+                Connection {
+                    id: self.next_id,
+                    stream,
+                    addr,
+                    tls,
+                    read_buf: Vec::new(),
+                    write_buf: Vec::new(),
+                    body: None,
+                    handling: false,
+                    closing: false,
+                    eof: false,
+                    paused: false,
+                    interest: Some(Interest::READABLE),
+                    reading_body: false,
+                    served: false,
+                    started: now,
+                    body_started: now,
+                    chunked: None,
+                    last_read: now,
+                    last_write: now,
+                    queued: 0,
+                    sent: 0,
+                    sending: VecDeque::new(),
+                    access_log: self.limiter.access_log,
+                    _permit: permit,
+                },
+            );
+        }
+    }
+
+    // 连接上有事件时，读、处理、写一气呵成，必要时关注可写事件
+    fn drive(&mut self, token: Token) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        let result = (|| -> io::Result<bool> {
+            if conn.fill(&self.limiter.limits)? {
+                conn.eof = true;
+            }
+            conn.settle(
+                self.poll.registry(),
+                &self.dispatcher,
+                &self.limiter.limits,
+                token,
+            )
+        })();
+        self.close_unless(token, result);
+    }
+
+    // 工作线程处理完请求，连接还在的话发送响应，然后接着处理后面的请求
+    fn complete(&mut self, completion: Completion) {
+        let token = completion.token;
+        let Some(conn) = self
+            .connections
+            .get_mut(&token)
+            .filter(|conn| conn.id == completion.id)
+        else {
+            return;
+        };
+        conn.finish(completion, token, &self.dispatcher.wakeup);
+        let result = conn.settle(
+            self.poll.registry(),
+            &self.dispatcher,
+            &self.limiter.limits,
+            token,
+        );
+        self.close_unless(token, result);
+    }
+
+    fn close_unless(&mut self, token: Token, result: io::Result<bool>) {
+        if !matches!(result, Ok(false))
+            && let Some(mut conn) = self.connections.remove(&token)
+        {
+            let _ = self.poll.registry().deregister(&mut conn.stream);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::http::{Request, Response};
+use crate::middleware::Middleware;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::{fmt, fs, io};
+
+// 查看缓存命中情况的路径
+pub const CACHE_STATS_PATH: &str = "/stats/cache";
+
+struct Entry {
+    contents: Arc<Vec<u8>>,
+    modified: SystemTime,
+    // 最近一次使用的序号，越大越新
+    used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<PathBuf, Entry>,
+    // 使用序号 -> 路径，最小的是最久没用的
+    order: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.order.remove(&entry.used);
+            entry.used = self.clock;
+            self.order.insert(self.clock, path.to_path_buf());
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.used);
+            self.bytes -= entry.contents.len();
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let Some((_, path)) = self.order.pop_first() else {
+            return false;
+        };
+        if let Some(entry) = self.entries.remove(&path) {
+            self.bytes -= entry.contents.len();
+        }
+        self.evictions += 1;
+        true
+    }
+}
+
+// 静态文件内容的 LRU 缓存，总大小不超过 capacity 字节；
+// 每次读取都会检查修改时间和大小，文件变了就重新读
+pub struct FileCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+// 缓存某一时刻的状态快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hits: {}", self.hits)?;
+        writeln!(f, "misses: {}", self.misses)?;
+        writeln!(f, "evictions: {}", self.evictions)?;
+        writeln!(f, "entries: {}", self.entries)?;
+        writeln!(f, "bytes: {}", self.bytes)?;
+        writeln!(f, "capacity: {}", self.capacity)
+    }
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> FileCache {
+        FileCache {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // 读文件内容，缓存里的还是最新的就直接返回；比 capacity 大的文件不缓存
+    pub fn read(&self, path: &Path) -> io::Result<Arc<Vec<u8>>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        {
+            let mut lru = self.lock();
+            let fresh = lru.entries.get(path).map(|entry| {
+                entry.modified == modified && entry.contents.len() as u64 == metadata.len()
+            });
+            match fresh {
+                Some(true) => {
+                    lru.hits += 1;
+                    lru.touch(path);
+                    return Ok(Arc::clone(&lru.entries[path].contents));
+                }
+                Some(false) => lru.remove(path),
+                None => {}
+            }
+            lru.misses += 1;
+        }
+
+        // 读文件时不持有锁
+        let contents = Arc::new(fs::read(path)?);
+        if contents.len() > self.capacity {
+            return Ok(contents);
+        }
+        let mut lru = self.lock();
+        lru.remove(path);
+        while lru.bytes + contents.len() > self.capacity && lru.evict_oldest() {}
+        lru.clock += 1;
+        let used = lru.clock;
+        lru.bytes += contents.len();
+        lru.order.insert(used, path.to_path_buf());
+        lru.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                contents: Arc::clone(&contents),
+                modified,
+                used,
+            },
+        );
+        Ok(contents)
+    }
+
+    pub fn clear(&self) {
+        let mut lru = self.lock();
+        lru.entries.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lock();
+        CacheStats {
+            hits: lru.hits,
+            misses: lru.misses,
+            evictions: lru.evictions,
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+            capacity: self.capacity,
+        }
+    }
+}
+
+// GET /stats/cache 返回缓存状态，做成中间件挂在 Chain 上，同步和异步模式都能用
+pub struct CacheStatsRoute {
+    cache: Arc<FileCache>,
+}
+
+impl CacheStatsRoute {
+    pub fn new(cache: Arc<FileCache>) -> CacheStatsRoute {
+        CacheStatsRoute { cache }
+    }
+}
+
+impl Middleware for CacheStatsRoute {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        (request.method == "GET" && request.path == CACHE_STATS_PATH)
+            .then(|| Response::text(200, self.cache.stats().to_string()))
+    }
+}
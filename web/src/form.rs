@@ -0,0 +1,526 @@
+use crate::http::{BodyStream, Request, Response};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{env, error, fmt, process};
+
+// 每次从请求体读多少
+const READ_SIZE: usize = 8 * 1024;
+// 一个 part 的头部最大长度
+const MAX_PART_HEAD: usize = 8 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct FormLimits {
+    // 字段数（含文件）
+    pub max_fields: usize,
+    // 单个普通字段的值
+    pub max_field_size: usize,
+    pub max_files: usize,
+    // 单个文件
+    pub max_file_size: u64,
+    // 上传的文件先写到这个目录
+    pub temp_dir: PathBuf,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_fields: 100,
+            max_field_size: 64 * 1024,
+            max_files: 10,
+            max_file_size: 10 * 1024 * 1024,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    UnsupportedContentType(String),
+    Malformed(&'static str),
+    TooLarge(String),
+    TooManyFields,
+    Missing(String),
+    InvalidValue { name: String, value: String },
+    Io(io::Error),
+}
+
+impl FormError {
+    // 对应的响应状态码
+    pub fn status(&self) -> u16 {
+        match self {
+            FormError::UnsupportedContentType(_) => 415,
+            FormError::TooLarge(_) | FormError::TooManyFields => 413,
+            FormError::Io(_) => 500,
+            _ => 400,
+        }
+    }
+
+    pub fn to_response(&self) -> Response {
+        Response::text(self.status(), self.to_string())
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedContentType(content_type) => {
+                write!(f, "unsupported form content type: {content_type}")
+            }
+            FormError::Malformed(reason) => write!(f, "malformed form: {reason}"),
+            FormError::TooLarge(what) => write!(f, "{what} too large"),
+            FormError::TooManyFields => write!(f, "too many form fields"),
+            FormError::Missing(name) => write!(f, "missing form field {name}"),
+            FormError::InvalidValue { name, value } => {
+                write!(f, "invalid value for form field {name}: {value:?}")
+            }
+            FormError::Io(err) => write!(f, "failed to save upload: {err}"),
+        }
+    }
+}
+
+impl error::Error for FormError {}
+
+impl From<io::Error> for FormError {
+    fn from(err: io::Error) -> Self {
+        FormError::Io(err)
+    }
+}
+
+// 上传的文件，保存在临时目录里，drop 时删除，要留下来就调用 persist
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub field: String,
+    // 客户端给的文件名，只保留最后一段，去掉了目录
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub path: PathBuf,
+    pub size: u64,
+    persisted: bool,
+}
+
+impl UploadedFile {
+    // 移动到 target，之后不再自动删除
+    pub fn persist(mut self, target: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let target = target.as_ref();
+        if fs::rename(&self.path, target).is_err() {
+            // 跨文件系统时 rename 会失败，改为复制
+            fs::copy(&self.path, target)?;
+            fs::remove_file(&self.path)?;
+        }
+        self.persisted = true;
+        Ok(target.to_path_buf())
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// 解析好的表单：普通字段按出现顺序保存，同名字段可以有多个
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    // 按 Content-Type 解析请求体：application/x-www-form-urlencoded 或 multipart/form-data。
+    // 请求体默认由服务端按 max_body_size 读完，上传的文件也受它限制；要上传更大的文件，
+    // handler 的 streams_body 对上传的路径返回 true，请求体就从 request.stream 边读边解析，
+    // 只受 FormLimits 限制（只有阻塞模式支持，事件循环和异步模式下仍然受 max_body_size 限制）
+    pub fn parse(request: &Request, limits: &FormLimits) -> Result<Form, FormError> {
+        let content_type = request.header("Content-Type").unwrap_or_default();
+        let (mime, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+        let stream = request.stream.as_ref().and_then(BodyStream::take);
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "application/x-www-form-urlencoded" => {
+                let body = match stream {
+                    // 字段都很小，读进内存，最多读到所有字段都到上限为止
+                    Some(stream) => {
+                        let max = limits.max_fields.saturating_mul(limits.max_field_size);
+                        let mut body = Vec::new();
+                        stream.take(max as u64 + 1).read_to_end(&mut body)?;
+                        if body.len() > max {
+                            return Err(FormError::TooLarge("form".into()));
+                        }
+                        Cow::Owned(body)
+                    }
+                    None => Cow::Borrowed(&request.body[..]),
+                };
+                let fields = parse_urlencoded(&body)?;
+                if fields.len() > limits.max_fields {
+                    return Err(FormError::TooManyFields);
+                }
+                Ok(Form {
+                    fields,
+                    files: Vec::new(),
+                })
+            }
+            "multipart/form-data" => {
+                let boundary = parameters(params)
+                    .into_iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+                    .map(|(_, value)| value)
+                    .ok_or(FormError::Malformed("missing multipart boundary"))?;
+                match stream {
+                    Some(stream) => parse_multipart(stream, &boundary, limits),
+                    None => parse_multipart(&request.body[..], &boundary, limits),
+                }
+            }
+            _ => Err(FormError::UnsupportedContentType(content_type.to_string())),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    // 必填字段，转换成 T
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        self.optional(name)?
+            .ok_or_else(|| FormError::Missing(name.to_string()))
+    }
+
+    // 可选字段，没有或者为空时返回 None
+    pub fn optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, FormError> {
+        match self.get(name).map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| FormError::InvalidValue {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }),
+        }
+    }
+
+    // 复选框：没提交时是 false
+    pub fn flag(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|value| {
+            matches!(
+                value.trim().to_ascii_lowercase().as_str(),
+                "on" | "true" | "1" | "yes"
+            )
+        })
+    }
+
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.field == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    // 取走上传的文件，由调用方决定怎么处理
+    pub fn take_files(&mut self) -> Vec<UploadedFile> {
+        std::mem::take(&mut self.files)
+    }
+}
+
+// 解析 a=1&b=x+y%21，+ 是空格，非法的 %xx 原样保留
+pub fn parse_urlencoded(input: &[u8]) -> Result<Vec<(String, String)>, FormError> {
+    input
+        .split(|&byte| byte == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = match pair.iter().position(|&byte| byte == b'=') {
+                Some(index) => (&pair[..index], &pair[index + 1..]),
+                None => (pair, &[][..]),
+            };
+            Ok((decode_component(name)?, decode_component(value)?))
+        })
+        .collect()
+}
+
+fn decode_component(input: &[u8]) -> Result<String, FormError> {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut index = 0;
+    while index < input.len() {
+        match input[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < input.len() => {
+                match (hex(input[index + 1]), hex(input[index + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        index += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| FormError::Malformed("form value is not utf-8"))
+}
+
+// 解析 ; name=value; name="quoted \"value\"" 形式的参数
+fn parameters(input: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+        let name: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if name.is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            while chars.next_if(|c| *c != ';').is_some() {}
+        } else {
+            value = chars.by_ref().take_while(|c| *c != ';').collect();
+        }
+        params.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    params
+}
+
+// 在请求体上找分隔符的扫描器，缓冲区只保留还没处理的部分，大文件不会整个放进内存
+struct Scanner<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Scanner<R> {
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buf[len..]) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buf.truncate(len + *read.as_ref().unwrap_or(&0));
+        self.eof = read? == 0;
+        Ok(!self.eof)
+    }
+
+    // 读一行（不含 \r\n）
+    fn read_line(&mut self, max: usize) -> Result<Vec<u8>, FormError> {
+        loop {
+            if let Some(index) = find(&self.buf, b"\r\n") {
+                let line = self.buf[..index].to_vec();
+                self.buf.drain(..index + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > max {
+                return Err(FormError::TooLarge("multipart header".into()));
+            }
+            if !self.fill()? {
+                return Err(FormError::Malformed("unexpected end of multipart body"));
+            }
+        }
+    }
+
+    // 把分隔符之前的内容交给 sink，分隔符本身丢掉
+    fn read_until(
+        &mut self,
+        delimiter: &[u8],
+        mut sink: impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        loop {
+            if let Some(index) = find(&self.buf, delimiter) {
+                sink(&self.buf[..index])?;
+                self.buf.drain(..index + delimiter.len());
+                return Ok(());
+            }
+            // 末尾可能是分隔符的前半段，先留着
+            let keep = delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let emit = self.buf.len() - keep;
+                sink(&self.buf[..emit])?;
+                self.buf.drain(..emit);
+            }
+            if !self.fill()? {
+                return Err(FormError::Malformed("unexpected end of multipart body"));
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// 一个 part 的头
+struct PartHead {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+fn read_part_head<R: Read>(scanner: &mut Scanner<R>) -> Result<PartHead, FormError> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+    let mut size = 0;
+    loop {
+        let line = scanner.read_line(MAX_PART_HEAD)?;
+        size += line.len();
+        if size > MAX_PART_HEAD {
+            return Err(FormError::TooLarge("multipart header".into()));
+        }
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8(line)
+            .map_err(|_| FormError::Malformed("multipart header is not utf-8"))?;
+        let (header, value) = line
+            .split_once(':')
+            .ok_or(FormError::Malformed("invalid multipart header"))?;
+        if header.trim().eq_ignore_ascii_case("Content-Disposition") {
+            let (disposition, params) = value.split_once(';').unwrap_or((value, ""));
+            if !disposition.trim().eq_ignore_ascii_case("form-data") {
+                return Err(FormError::Malformed("part is not form-data"));
+            }
+            for (key, value) in parameters(params) {
+                if key.eq_ignore_ascii_case("name") {
+                    name = Some(value);
+                } else if key.eq_ignore_ascii_case("filename") {
+                    filename = Some(value);
+                }
+            }
+        } else if header.trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    Ok(PartHead {
+        name: name.ok_or(FormError::Malformed("part without a name"))?,
+        // 有的浏览器会带上完整路径，只留文件名
+        filename: filename
+            .map(|filename| {
+                filename
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .filter(|filename| !filename.is_empty()),
+        content_type,
+    })
+}
+
+fn temp_path(dir: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("web-upload-{}-{id}", process::id()))
+}
+
+// 边读边解析 multipart/form-data，文件内容直接写进临时文件
+pub fn parse_multipart<R: Read>(
+    reader: R,
+    boundary: &str,
+    limits: &FormLimits,
+) -> Result<Form, FormError> {
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(FormError::Malformed("invalid multipart boundary"));
+    }
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+    // 第一个分隔符前面没有 \r\n，补上之后所有分隔符都一样处理
+    let mut scanner = Scanner {
+        reader,
+        buf: b"\r\n".to_vec(),
+        eof: false,
+    };
+    scanner.read_until(&delimiter, |_| Ok(()))?;
+
+    let mut form = Form::default();
+    loop {
+        // 分隔符后面是 -- 表示结束，否则是 \r\n（前面可能有空白）
+        let line = scanner.read_line(MAX_PART_HEAD)?;
+        if line.starts_with(b"--") {
+            return Ok(form);
+        }
+        if !line.iter().all(|byte| byte.is_ascii_whitespace()) {
+            return Err(FormError::Malformed("invalid multipart delimiter"));
+        }
+
+        if form.fields.len() + form.files.len() >= limits.max_fields {
+            return Err(FormError::TooManyFields);
+        }
+        let head = read_part_head(&mut scanner)?;
+        match head.filename {
+            Some(filename) => {
+                if form.files.len() >= limits.max_files {
+                    return Err(FormError::TooManyFields);
+                }
+                let path = temp_path(&limits.temp_dir);
+                let mut writer = BufWriter::new(File::create_new(&path)?);
+                // 先登记，出错时 drop 会把临时文件删掉
+                let mut file = UploadedFile {
+                    field: head.name,
+                    filename: Some(filename),
+                    content_type: head.content_type,
+                    path,
+                    size: 0,
+                    persisted: false,
+                };
+                scanner.read_until(&delimiter, |chunk| {
+                    file.size += chunk.len() as u64;
+                    if file.size > limits.max_file_size {
+                        return Err(FormError::TooLarge(format!("file {}", file.field)));
+                    }
+                    Ok(writer.write_all(chunk)?)
+                })?;
+                writer.flush()?;
+                form.files.push(file);
+            }
+            None => {
+                let mut value = Vec::new();
+                scanner.read_until(&delimiter, |chunk| {
+                    if value.len() + chunk.len() > limits.max_field_size {
+                        return Err(FormError::TooLarge(format!("field {}", head.name)));
+                    }
+                    value.extend_from_slice(chunk);
+                    Ok(())
+                })?;
+                let value = String::from_utf8(value)
+                    .map_err(|_| FormError::Malformed("form value is not utf-8"))?;
+                form.fields.push((head.name, value));
+            }
+        }
+    }
+}
@@ -0,0 +1,1061 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+// 请求头部分的最大长度
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+// 推送式响应体最多积压多少字节没发出去，超过时 send 阻塞
+pub const CHANNEL_CAPACITY: usize = 64 * 1024;
+// chunked 编码的结束标记
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub remote_addr: Option<SocketAddr>,
+    // 请求是从 HTTPS 连接上来的，由服务端设置
+    pub secure: bool,
+    // handler 要边读边用请求体时（见 Handler::streams_body）服务端不读进 body，从这里读
+    pub stream: Option<BodyStream>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    BadRequestLine,
+    BadHeader,
+    HeadTooLarge,
+    BadContentLength,
+    BadPath,
+    BadChunk,
+    BodyTooLarge,
+    UnsupportedTransferEncoding,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadRequestLine => write!(f, "malformed request line"),
+            ParseError::BadHeader => write!(f, "malformed header"),
+            ParseError::HeadTooLarge => write!(f, "request head too large"),
+            ParseError::BadContentLength => write!(f, "invalid content-length"),
+            ParseError::BadPath => write!(f, "invalid request path"),
+            ParseError::BadChunk => write!(f, "malformed chunked body"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::UnsupportedTransferEncoding => write!(f, "unsupported transfer-encoding"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl Request {
+    // 请求头名字不区分大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    // 同名的头只保留一个，中间件用它给后面的 handler 传信息
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    // 不带请求体的副本，请求交给异步 handler 之后还要用到请求信息时用它
+    pub fn without_body(&self) -> Request {
+        Request {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            query: self.query.clone(),
+            version: self.version.clone(),
+            headers: self.headers.clone(),
+            body: Vec::new(),
+            remote_addr: self.remote_addr,
+            secure: self.secure,
+            stream: None,
+        }
+    }
+
+    pub fn request_line(&self) -> String {
+        let target = match &self.query {
+            Some(query) => format!("{}?{query}", self.path),
+            None => self.path.clone(),
+        };
+        format!("{} {target} {}", self.method, self.version)
+    }
+
+    // HTTP/1.1 默认长连接，HTTP/1.0 需要显式 keep-alive
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(str::to_ascii_lowercase);
+        match connection.as_deref() {
+            Some("close") => false,
+            Some("keep-alive") => true,
+            _ => self.version == "HTTP/1.1",
+        }
+    }
+
+    pub fn content_length(&self) -> Result<usize, ParseError> {
+        match self.header("Content-Length") {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::BadContentLength),
+            None => Ok(0),
+        }
+    }
+
+    // 请求体怎么读：只认 chunked 一种传输编码；同时带 Content-Length 和 Transfer-Encoding、
+    // 多个不一致的 Content-Length 都当作错误，不去猜哪个算数，防止和前面的代理理解不一致（请求走私）
+    pub fn body_length(&self) -> Result<BodyLength, ParseError> {
+        let values = |name: &'static str| {
+            self.headers
+                .iter()
+                .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        };
+        let mut encodings = values("Transfer-Encoding");
+        let Some(encoding) = encodings.next() else {
+            let mut lengths = values("Content-Length");
+            let first = lengths.next();
+            if lengths.any(|length| Some(length) != first) {
+                return Err(ParseError::BadContentLength);
+            }
+            return self.content_length().map(BodyLength::Fixed);
+        };
+        if self.header("Content-Length").is_some() {
+            return Err(ParseError::BadContentLength);
+        }
+        if !encoding.eq_ignore_ascii_case("chunked") || encodings.next().is_some() {
+            return Err(ParseError::UnsupportedTransferEncoding);
+        }
+        Ok(BodyLength::Chunked)
+    }
+
+    // 从缓冲区解析请求头，数据还不完整时返回 Ok(None)，
+    // 成功时返回请求（不含请求体）和请求头占用的字节数
+    pub fn parse_head(buf: &[u8]) -> Result<Option<(Request, usize)>, ParseError> {
+        let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") else {
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(ParseError::HeadTooLarge);
+            }
+            return Ok(None);
+        };
+        if end > MAX_HEAD_SIZE {
+            return Err(ParseError::HeadTooLarge);
+        }
+
+        let head = String::from_utf8_lossy(&buf[..end]);
+        let mut lines = head.split("\r\n");
+        let mut request = Request::parse_request_line(lines.next().unwrap_or_default())?;
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        Ok(Some((request, end + 4)))
+    }
+
+    fn parse_request_line(line: &str) -> Result<Request, ParseError> {
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::BadRequestLine);
+        };
+        if !version.starts_with("HTTP/") {
+            return Err(ParseError::BadRequestLine);
+        }
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (normalize_path(path)?, Some(query.to_string())),
+            None => (normalize_path(target)?, None),
+        };
+        Ok(Request {
+            method: method.to_string(),
+            path,
+            query,
+            version: version.to_string(),
+            ..Request::default()
+        })
+    }
+
+    // 阻塞读取一个完整请求，连接在请求开始前关闭时返回 Ok(None)
+    pub fn read_from<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+        let Some(mut request) = Request::read_head(reader)? else {
+            return Ok(None);
+        };
+        let length = request.content_length()?;
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(Some(request))
+    }
+
+    // 只读请求头，请求体留在 reader 里，方便调用方先检查 Content-Length
+    pub fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+        let mut head = Vec::new();
+        loop {
+            let limit = (MAX_HEAD_SIZE + 1).saturating_sub(head.len()) as u64;
+            let read = reader.by_ref().take(limit).read_until(b'\n', &mut head)?;
+            if head.len() > MAX_HEAD_SIZE {
+                return Err(ParseError::HeadTooLarge.into());
+            }
+            if read == 0 {
+                if head.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            // 忽略请求之间多余的空行
+            if head == b"\r\n" {
+                head.clear();
+                continue;
+            }
+            if !head.ends_with(b"\r\n\r\n") {
+                continue;
+            }
+            if let Some((request, _)) = Request::parse_head(&head)? {
+                return Ok(Some(request));
+            }
+        }
+    }
+}
+
+// 规范化请求路径：合并重复的 / 和 . 段，含 .. 的路径直接拒绝。
+// 在解析请求时做一次，中间件按前缀匹配和静态文件看到的是同一个路径，
+// 否则 //admin、/./admin 这样的写法能绕过只保护 /admin 的认证
+pub fn normalize_path(path: &str) -> Result<String, ParseError> {
+    // OPTIONS * 之类不是以 / 开头的目标原样保留
+    if !path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(ParseError::BadPath),
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    // 保留末尾的 /，/admin/. 也当作 /admin/
+    if !segments.is_empty() && (path.ends_with('/') || path.ends_with("/.")) {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+// 请求体的长度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Fixed(usize),
+    Chunked,
+}
+
+// chunk 大小行和 trailer 每行的最大长度
+const MAX_CHUNK_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+// 增量解码 chunked 编码的请求体，三种服务端共用：收到多少喂多少，没收完的行留在内部。
+// 行必须以 CRLF 结尾，trailer 读完就丢掉
+pub struct ChunkedDecoder {
+    state: ChunkState,
+    line: Vec<u8>,
+    body: Vec<u8>,
+    max: usize,
+    trailer: usize,
+}
+
+impl ChunkedDecoder {
+    // 解码后的请求体超过 max 时返回 BodyTooLarge
+    pub fn new(max: usize) -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            line: Vec::new(),
+            body: Vec::new(),
+            max,
+            trailer: 0,
+        }
+    }
+
+    // 返回用掉的字节数，请求体结束后剩下的是下一个请求，不会用掉
+    pub fn decode(&mut self, buf: &[u8]) -> Result<usize, ParseError> {
+        let mut used = 0;
+        while used < buf.len() && !self.is_done() {
+            let rest = &buf[used..];
+            if let ChunkState::Data(remaining) = self.state {
+                let take = remaining.min(rest.len());
+                self.body.extend_from_slice(&rest[..take]);
+                used += take;
+                self.state = match remaining - take {
+                    0 => ChunkState::DataEnd,
+                    remaining => ChunkState::Data(remaining),
+                };
+                continue;
+            }
+
+            let end = rest.iter().position(|&byte| byte == b'\n').map(|at| at + 1);
+            let take = end.unwrap_or(rest.len());
+            if self.line.len() + take > MAX_CHUNK_LINE {
+                return Err(ParseError::BadChunk);
+            }
+            self.line.extend_from_slice(&rest[..take]);
+            used += take;
+            if end.is_some() {
+                let line = std::mem::take(&mut self.line);
+                self.finish_line(&line)?;
+            }
+        }
+        Ok(used)
+    }
+
+    fn finish_line(&mut self, line: &[u8]) -> Result<(), ParseError> {
+        let line = line.strip_suffix(b"\r\n").ok_or(ParseError::BadChunk)?;
+        self.state = match self.state {
+            ChunkState::Size => match chunk_size(line)? {
+                0 => ChunkState::Trailer,
+                size if size > self.max - self.body.len() => {
+                    return Err(ParseError::BodyTooLarge);
+                }
+                size => ChunkState::Data(size),
+            },
+            ChunkState::DataEnd if line.is_empty() => ChunkState::Size,
+            ChunkState::DataEnd => return Err(ParseError::BadChunk),
+            ChunkState::Trailer => {
+                self.trailer += line.len();
+                if self.trailer > MAX_HEAD_SIZE {
+                    return Err(ParseError::HeadTooLarge);
+                }
+                if line.is_empty() {
+                    ChunkState::Done
+                } else {
+                    ChunkState::Trailer
+                }
+            }
+            state => state,
+        };
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    // 取出目前解码出来的数据，边解码边转发时用
+    pub fn take_body(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.body)
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+// chunk 大小是十六进制，后面可以跟 ;扩展，扩展直接忽略
+fn chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let size = line.split(|&byte| byte == b';').next().unwrap_or_default();
+    let size = size.trim_ascii_end();
+    if size.is_empty() || size.len() > 15 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(ParseError::BadChunk);
+    }
+    let size = std::str::from_utf8(size).map_err(|_| ParseError::BadChunk)?;
+    usize::from_str_radix(size, 16).map_err(|_| ParseError::BadChunk)
+}
+
+enum Source {
+    Reader(Box<dyn Read + Send>),
+    Channel(BodyReceiver),
+}
+
+// 流式响应体：先发响应头，响应体边读边发，只能读一次，复制出来的 Response 共用同一个
+#[derive(Clone)]
+pub struct BodyStream(Arc<Mutex<Option<Source>>>);
+
+impl BodyStream {
+    pub fn new(reader: impl Read + Send + 'static) -> BodyStream {
+        BodyStream(Arc::new(Mutex::new(Some(Source::Reader(Box::new(reader))))))
+    }
+
+    pub fn channel(receiver: BodyReceiver) -> BodyStream {
+        BodyStream(Arc::new(Mutex::new(Some(Source::Channel(receiver)))))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Source>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // 取出 reader，已经被取走时返回 None
+    pub fn take(&self) -> Option<Box<dyn Read + Send>> {
+        self.lock().take().map(|source| match source {
+            Source::Reader(reader) => reader,
+            Source::Channel(receiver) => Box::new(receiver),
+        })
+    }
+
+    // 是推送式的响应体时取出接收端，事件循环和异步模式靠它边收边发
+    pub fn take_channel(&self) -> Option<BodyReceiver> {
+        let mut source = self.lock();
+        match source.take() {
+            Some(Source::Channel(receiver)) => Some(receiver),
+            other => {
+                *source = other;
+                None
+            }
+        }
+    }
+}
+
+// 推送式响应体两端共享的状态
+struct Pipe {
+    chunks: VecDeque<Vec<u8>>,
+    queued: usize,
+    senders: usize,
+    receiver_gone: bool,
+    // 有新数据或者发送端全部关闭时调用，用来唤醒事件循环或者异步任务
+    notify: Option<Box<dyn Fn() + Send + Sync>>,
+}
+
+struct Channel {
+    pipe: Mutex<Pipe>,
+    readable: Condvar,
+    writable: Condvar,
+    capacity: usize,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, Pipe> {
+        self.pipe.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn wake(&self, pipe: &Pipe) {
+        self.readable.notify_all();
+        if let Some(notify) = &pipe.notify {
+            notify();
+        }
+    }
+}
+
+// 推送式响应体的通道：handler 把接收端放进响应返回，发送端交给后台线程，
+// 每次 send 的数据作为一个 chunk 发给客户端，发送端全部 drop 后响应结束
+pub fn body_channel(capacity: usize) -> (BodySender, BodyReceiver) {
+    let channel = Arc::new(Channel {
+        pipe: Mutex::new(Pipe {
+            chunks: VecDeque::new(),
+            queued: 0,
+            senders: 1,
+            receiver_gone: false,
+            notify: None,
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+        capacity: capacity.max(1),
+    });
+    (
+        BodySender(Arc::clone(&channel)),
+        BodyReceiver {
+            channel,
+            pending: Vec::new(),
+            offset: 0,
+        },
+    )
+}
+
+pub struct BodySender(Arc<Channel>);
+
+impl BodySender {
+    // 积压超过容量时阻塞等客户端读走；客户端断开后返回 BrokenPipe
+    pub fn send(&self, bytes: impl Into<Vec<u8>>) -> io::Result<()> {
+        let bytes = bytes.into();
+        let mut pipe = self.0.lock();
+        while !pipe.receiver_gone && pipe.queued >= self.0.capacity {
+            pipe = self
+                .0
+                .writable
+                .wait(pipe)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        if pipe.receiver_gone {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "client disconnected",
+            ));
+        }
+        // 空的 chunk 会被当成结束标记，不发
+        if !bytes.is_empty() {
+            pipe.queued += bytes.len();
+            pipe.chunks.push_back(bytes);
+            self.0.wake(&pipe);
+        }
+        Ok(())
+    }
+
+    // 客户端是否已经断开
+    pub fn is_closed(&self) -> bool {
+        self.0.lock().receiver_gone
+    }
+}
+
+impl Clone for BodySender {
+    fn clone(&self) -> BodySender {
+        self.0.lock().senders += 1;
+        BodySender(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for BodySender {
+    fn drop(&mut self) {
+        let mut pipe = self.0.lock();
+        pipe.senders -= 1;
+        if pipe.senders == 0 {
+            self.0.wake(&pipe);
+        }
+    }
+}
+
+impl fmt::Debug for BodySender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodySender")
+    }
+}
+
+pub enum TryRecv {
+    Data(Vec<u8>),
+    Empty,
+    Closed,
+}
+
+pub struct BodyReceiver {
+    channel: Arc<Channel>,
+    // Read 时一块没读完剩下的部分
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl BodyReceiver {
+    fn pop(&self, pipe: &mut Pipe) -> Option<Vec<u8>> {
+        let chunk = pipe.chunks.pop_front()?;
+        pipe.queued -= chunk.len();
+        self.channel.writable.notify_all();
+        Some(chunk)
+    }
+
+    fn leftover(&mut self) -> Option<Vec<u8>> {
+        if self.offset >= self.pending.len() {
+            return None;
+        }
+        let rest = self.pending.split_off(self.offset);
+        self.pending.clear();
+        self.offset = 0;
+        Some(rest)
+    }
+
+    pub fn try_recv(&mut self) -> TryRecv {
+        if let Some(rest) = self.leftover() {
+            return TryRecv::Data(rest);
+        }
+        let mut pipe = self.channel.lock();
+        match self.pop(&mut pipe) {
+            Some(chunk) => TryRecv::Data(chunk),
+            None if pipe.senders == 0 => TryRecv::Closed,
+            None => TryRecv::Empty,
+        }
+    }
+
+    // 有新数据或者发送端全部关闭时调用 notify，会替换掉之前设置的
+    pub fn set_notify(&self, notify: impl Fn() + Send + Sync + 'static) {
+        self.channel.lock().notify = Some(Box::new(notify));
+    }
+
+    // 没有数据时登记 waker，返回 None 表示响应体结束
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        if let Some(rest) = self.leftover() {
+            return Poll::Ready(Some(rest));
+        }
+        let mut pipe = self.channel.lock();
+        if let Some(chunk) = self.pop(&mut pipe) {
+            return Poll::Ready(Some(chunk));
+        }
+        if pipe.senders == 0 {
+            return Poll::Ready(None);
+        }
+        let waker = cx.waker().clone();
+        pipe.notify = Some(Box::new(move || waker.wake_by_ref()));
+        Poll::Pending
+    }
+
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+// 阻塞地读，等到有数据或者发送端全部关闭
+impl Read for BodyReceiver {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.offset >= self.pending.len() {
+            let mut pipe = self.channel.lock();
+            let chunk = loop {
+                if let Some(chunk) = self.pop(&mut pipe) {
+                    break chunk;
+                }
+                if pipe.senders == 0 {
+                    return Ok(0);
+                }
+                pipe = self
+                    .channel
+                    .readable
+                    .wait(pipe)
+                    .unwrap_or_else(|err| err.into_inner());
+            };
+            self.pending = chunk;
+            self.offset = 0;
+        }
+        let read = buf.len().min(self.pending.len() - self.offset);
+        buf[..read].copy_from_slice(&self.pending[self.offset..self.offset + read]);
+        self.offset += read;
+        Ok(read)
+    }
+}
+
+impl Drop for BodyReceiver {
+    fn drop(&mut self) {
+        let mut pipe = self.channel.lock();
+        pipe.receiver_gone = true;
+        pipe.chunks.clear();
+        pipe.queued = 0;
+        pipe.notify = None;
+        self.channel.writable.notify_all();
+    }
+}
+
+// 按 chunked 编码包装一块数据
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &BodyStream) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for BodyStream {}
+
+// 响应体，clone 时共享同一份数据，静态文件缓存命中时直接用缓存里的内容，不用复制
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Body(Arc<Vec<u8>>);
+
+impl Body {
+    // 没有别的引用时直接取出，否则复制一份
+    pub fn into_vec(self) -> Vec<u8> {
+        Arc::unwrap_or_clone(self.0)
+    }
+}
+
+impl Deref for Body {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl From<Arc<Vec<u8>>> for Body {
+    fn from(bytes: Arc<Vec<u8>>) -> Body {
+        Body(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body(Arc::new(bytes))
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::from(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::from(text.as_bytes())
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Body {
+        Body::from(bytes.to_vec())
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for Body {
+    fn from(bytes: &[u8; N]) -> Body {
+        Body::from(bytes.to_vec())
+    }
+}
+
+impl PartialEq<[u8]> for Body {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0.as_slice() == other
+    }
+}
+
+impl PartialEq<&[u8]> for Body {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.0.as_slice() == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Body {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.0.as_ref() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&[u8; N]> for Body {
+    fn eq(&self, other: &&[u8; N]) -> bool {
+        self.0.as_slice() == other.as_slice()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+    // 不为空时忽略 body，响应体从这里读
+    pub stream: Option<BodyStream>,
+    // 没有消息体（HEAD 的响应、1xx、204、304）：只发响应头，Content-Length 头原样发出，
+    // 不按 body 补上
+    pub no_body: bool,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::default(),
+            stream: None,
+            no_body: false,
+        }
+    }
+
+    pub fn html(status: u16, body: impl Into<Body>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn text(status: u16, body: impl Into<Body>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.set_header(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    // 流式响应体：设置了 Content-Length 头时原样发送，否则用 chunked 编码
+    pub fn with_stream(mut self, reader: impl Read + Send + 'static) -> Response {
+        self.body = Body::default();
+        self.stream = Some(BodyStream::new(reader));
+        self
+    }
+
+    // 推送式的流式响应：先返回响应，之后在别的线程里通过 BodySender 发送响应体，用 chunked 编码
+    pub fn channel(status: u16) -> (Response, BodySender) {
+        let (sender, receiver) = body_channel(CHANNEL_CAPACITY);
+        let mut response = Response::new(status);
+        response.stream = Some(BodyStream::channel(receiver));
+        (response, sender)
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // 把流式响应体读到 body 里，事件循环发送前先这样缓冲
+    pub fn buffer(&mut self) -> io::Result<()> {
+        let Some(stream) = self.stream.take() else {
+            return Ok(());
+        };
+        self.remove_header("Content-Length");
+        let Some(mut reader) = stream.take() else {
+            return Ok(());
+        };
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        self.body = Body::from(body);
+        Ok(())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    // 同名的头只保留一个
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.remove_header(name);
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    // 往 Vary 里追加一个请求头名，已经有了或者是 * 时不变
+    pub fn add_vary(&mut self, name: &str) {
+        match self.header("Vary") {
+            None => self.set_header("Vary", name),
+            Some(vary)
+                if vary.trim() == "*"
+                    || vary
+                        .split(',')
+                        .any(|item| item.trim().eq_ignore_ascii_case(name)) => {}
+            Some(vary) => {
+                let vary = format!("{vary}, {name}");
+                self.set_header("Vary", &vary);
+            }
+        }
+    }
+
+    // 序列化出响应头部分，Content-Length 按 body 实际长度生成；
+    // 流式响应体没有给出 Content-Length 时用 Transfer-Encoding: chunked
+    pub fn head_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+        for (name, value) in &self.headers {
+            if (name.eq_ignore_ascii_case("Content-Length") && !self.no_body)
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if self.no_body {
+            head.push_str("\r\n");
+            return head.into_bytes();
+        }
+        match (&self.stream, self.header("Content-Length")) {
+            (Some(_), Some(length)) => head.push_str(&format!("Content-Length: {length}\r\n\r\n")),
+            (Some(_), None) => head.push_str("Transfer-Encoding: chunked\r\n\r\n"),
+            (None, _) => head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len())),
+        }
+        head.into_bytes()
+    }
+
+    // 流式响应体会先整个读出来，读流会消耗掉响应体，所以拿走整个响应
+    pub fn to_bytes(mut self) -> Vec<u8> {
+        let _ = self.buffer();
+        let mut bytes = self.head_bytes();
+        if !self.no_body {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if self.no_body {
+            writer.write_all(&self.head_bytes())?;
+            return writer.flush();
+        }
+        let Some(stream) = &self.stream else {
+            writer.write_all(&self.head_bytes())?;
+            writer.write_all(&self.body)?;
+            return writer.flush();
+        };
+        let mut reader = stream
+            .take()
+            .ok_or_else(|| io::Error::other("response body stream already consumed"))?;
+        writer.write_all(&self.head_bytes())?;
+        match self.header("Content-Length") {
+            Some(length) => {
+                let length: u64 = length
+                    .trim()
+                    .parse()
+                    .map_err(|_| ParseError::BadContentLength)?;
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied < length {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response body truncated",
+                    ));
+                }
+            }
+            None => write_chunked(&mut reader, writer)?,
+        }
+        writer.flush()
+    }
+}
+
+// 按 chunked 编码转发 reader 的内容，每块写完就 flush，让客户端尽快收到
+fn write_chunked<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&encode_chunk(&buf[..read]))?;
+        writer.flush()?;
+    }
+    writer.write_all(LAST_CHUNK)
+}
+
+// 解码 chunked 编码的消息体，trailer 直接丢掉。用 ChunkedDecoder 解码，对方不可信时
+// （上游、压测目标）行长、chunk 大小和 trailer 也都有上限；读到一点就返回一点
+pub struct ChunkedReader<R> {
+    inner: R,
+    decoder: ChunkedDecoder,
+    // 已经解码、还没读走的数据
+    decoded: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            decoder: ChunkedDecoder::new(usize::MAX),
+            decoded: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.offset == self.decoded.len() {
+            if self.decoder.is_done() {
+                return Ok(0);
+            }
+            let data = self.inner.fill_buf()?;
+            if data.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "chunked body truncated",
+                ));
+            }
+            let used = self.decoder.decode(data)?;
+            self.inner.consume(used);
+            self.decoded = self.decoder.take_body();
+            self.offset = 0;
+        }
+        let read = buf.len().min(self.decoded.len() - self.offset);
+        buf[..read].copy_from_slice(&self.decoded[self.offset..self.offset + read]);
+        self.offset += read;
+        Ok(read)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+// 所有分发模式共用的处理函数接口
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request) -> Response;
+
+    // 读完请求头后调用，返回 true 时阻塞模式的服务端不先读请求体、也不按 max_body_size 限制，
+    // 而是放进 request.stream 交给 handler 边读边用，比如反向代理直接转发给上游
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
@@ -1,8 +1,82 @@
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use std::{fs, thread};
 
+pub mod access_log;
+pub mod async_server;
+pub mod bench;
+pub mod compression;
+pub mod config;
+pub mod event_loop;
+pub mod file_cache;
+pub mod form;
+pub mod http;
+pub mod logger;
+pub mod middleware;
+pub mod proxy;
+pub mod server;
+pub mod sse;
+pub mod static_files;
+pub mod thread_pool;
+pub mod tls;
+pub mod vhost;
+
+use async_server::AsyncHandler;
+use file_cache::FileCache;
+use http::{Handler, Request, Response};
+use server::{Limiter, reject_connection};
+use sse::Event;
+use thread_pool::{StatsHandle, ThreadPool};
+
+// 默认的文档根目录，hello.html 和 404.html 都放在这里
+pub const DOC_ROOT: &str = "public";
+
+// /sleep/progress 同时推送的事件流最多这么多个，再多的回 503
+pub const MAX_PROGRESS_STREAMS: usize = 32;
+
+// 正在推送的 /sleep/progress 事件流个数
+static PROGRESS_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+// 占用一个事件流名额，推送结束（guard 丢掉）时归还
+struct ProgressSlot;
+
+impl ProgressSlot {
+    fn acquire() -> Option<ProgressSlot> {
+        PROGRESS_STREAMS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |streams| {
+                (streams < MAX_PROGRESS_STREAMS).then_some(streams + 1)
+            })
+            .ok()
+            .map(|_| ProgressSlot)
+    }
+}
+
+impl Drop for ProgressSlot {
+    fn drop(&mut self) {
+        PROGRESS_STREAMS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// 同步模式下推送事件的线程池，名额限制了同时运行的任务数，线程数最多也就这么多
+fn progress_pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        ThreadPool::builder()
+            .core_size(1)
+            .max_size(MAX_PROGRESS_STREAMS)
+            .thread_name("web-progress")
+            .build()
+    })
+}
+
+fn too_many_streams() -> Response {
+    Response::text(503, "Service Unavailable").with_header("Retry-After", "1")
+}
+
 pub fn handle_connection(mut stream: TcpStream) {
     let buf_reader = BufReader::new(&mut stream);
     let request_line = buf_reader.lines().next().unwrap().unwrap();
@@ -10,14 +84,14 @@ pub fn handle_connection(mut stream: TcpStream) {
 
     if request_line == "GET / HTTP/1.1" {
         let status_line = "HTTP/1.1 200 OK";
-        let contents = fs::read_to_string("hello.html").unwrap();
+        let contents = fs::read_to_string("public/hello.html").unwrap();
         let length = format!("Content-Length:{}\r\n\r\n", contents.len());
 
         let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n\n{contents}");
         stream.write_all(response.as_bytes()).unwrap();
     } else {
         let status_line = "HTTP/1.1 404 NOT FOUND";
-        let contents = fs::read_to_string("404.html").unwrap();
+        let contents = fs::read_to_string("public/404.html").unwrap();
         let length = format!("Content-Length:{}\r\n\r\n", contents.len());
 
         let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n\n{contents}");
@@ -30,9 +104,9 @@ pub fn handle_connection2(mut stream: TcpStream) {
     let request_line = buf_reader.lines().next().unwrap().unwrap();
 
     let (status_line, filename) = if request_line == "GET / HTTP/1.1" {
-        ("HTTP/1.1 200 OK", "hello.html")
+        ("HTTP/1.1 200 OK", "public/hello.html")
     } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+        ("HTTP/1.1 404 NOT FOUND", "public/404.html")
     };
 
     let contents = fs::read_to_string(filename).unwrap();
@@ -48,12 +122,12 @@ pub fn handle_connection_slow(mut stream: TcpStream) {
     let request_line = buf_reader.lines().next().unwrap().unwrap();
 
     let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
+        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "public/hello.html"),
         "GET /sleep HTTP/1.1" => {
             thread::sleep(Duration::from_secs(10));
-            ("HTTP/1.1 200 OK", "hello.html")
+            ("HTTP/1.1 200 OK", "public/hello.html")
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+        _ => ("HTTP/1.1 404 NOT FOUND", "public/404.html"),
     };
 
     let contents = fs::read_to_string(filename).unwrap();
@@ -62,3 +136,175 @@ pub fn handle_connection_slow(mut stream: TcpStream) {
     let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n\n{contents}");
     stream.write_all(response.as_bytes()).unwrap();
 }
+
+// 和 handle_connection_slow 相同的路由，改为 Request -> Response 的形式，
+// 各种分发模式（包括事件循环）都通过 http::Handler 调用它
+pub fn route(request: &Request) -> Response {
+    route_in(Path::new(DOC_ROOT), request)
+}
+
+// 带文档根目录的 route：/ 返回 hello.html，其余 GET 请求按路径在文档根目录下找文件，
+// 找不到时返回 404.html
+pub fn route_in(doc_root: &Path, request: &Request) -> Response {
+    route_cached(doc_root, None, request)
+}
+
+// route_in 的带缓存版本，cache 不为空时文件内容从缓存里读
+pub fn route_cached(doc_root: &Path, cache: Option<&FileCache>, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => page(request, 200, &doc_root.join("hello.html"), cache),
+        ("GET", "/sleep") => {
+            thread::sleep(Duration::from_secs(10));
+            page(request, 200, &doc_root.join("hello.html"), cache)
+        }
+        ("GET", "/sleep/progress") => sleep_progress(request),
+        ("GET", path) => match static_files::resolve(doc_root, path) {
+            Some(file) => page(request, 200, &file, cache),
+            None => not_found(request, doc_root, cache),
+        },
+        _ => not_found(request, doc_root, cache),
+    }
+}
+
+// /sleep 的进度推送版本（server-sent events）：每秒发一个 progress 事件，结束时发 done；
+// 事件由 progress_pool 上的任务发送
+fn sleep_progress(request: &Request) -> Response {
+    let Some(slot) = ProgressSlot::acquire() else {
+        return too_many_streams();
+    };
+    let (resumed, total) = progress_range(request);
+    let (response, events) = sse::channel();
+    let sent = progress_pool().execute(move || {
+        let _slot = slot;
+        for elapsed in resumed + 1..=total {
+            thread::sleep(Duration::from_secs(1));
+            // 客户端断开了就不用再等
+            if events.send(&progress_event(elapsed, total)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(&Event::new("done").event("done"));
+    });
+    match sent {
+        Ok(()) => response,
+        Err(_) => too_many_streams(),
+    }
+}
+
+// sleep_progress 的异步版本，事件由异步服务线程池上的任务用定时器发送，不占线程
+fn sleep_progress_async(request: &Request) -> Response {
+    let Some(slot) = ProgressSlot::acquire() else {
+        return too_many_streams();
+    };
+    let (resumed, total) = progress_range(request);
+    let (response, events) = sse::channel();
+    async_server::spawn(async move {
+        let _slot = slot;
+        for elapsed in resumed + 1..=total {
+            async_server::sleep(Duration::from_secs(1)).await;
+            if events.send(&progress_event(elapsed, total)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(&Event::new("done").event("done"));
+    });
+    response
+}
+
+// ?seconds= 指定时长（默认 10，最长 60），浏览器重连时从 Last-Event-ID 接着计；
+// 返回 (已经过的秒数, 总秒数)
+fn progress_range(request: &Request) -> (u64, u64) {
+    let query = request.query.as_deref().unwrap_or_default();
+    let total = form::parse_urlencoded(query.as_bytes())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|(name, _)| name == "seconds")
+        .and_then(|(_, value)| value.parse::<u64>().ok())
+        .unwrap_or(10)
+        .min(60);
+    let resumed = sse::last_event_id(request)
+        .and_then(|id| id.parse::<u64>().ok())
+        .unwrap_or(0)
+        .min(total);
+    (resumed, total)
+}
+
+fn progress_event(elapsed: u64, total: u64) -> Event {
+    Event::new(format!("{{\"elapsed\":{elapsed},\"total\":{total}}}"))
+        .id(elapsed.to_string())
+        .event("progress")
+}
+
+// 文档根目录下没有 404.html 时回纯文本
+fn not_found(request: &Request, doc_root: &Path, cache: Option<&FileCache>) -> Response {
+    let file = doc_root.join("404.html");
+    if file.is_file() {
+        page(request, 404, &file, cache)
+    } else {
+        Response::text(404, "Not Found")
+    }
+}
+
+fn page(request: &Request, status: u16, file: &Path, cache: Option<&FileCache>) -> Response {
+    static_files::serve_file_cached(request, status, file, cache)
+        .unwrap_or_else(|err| Response::text(500, err.to_string()))
+}
+
+// route 的异步版本，/sleep 和 /sleep/progress 用定时器等待，不会占住线程
+pub async fn route_async(request: Request) -> Response {
+    route_async_in(Path::new(DOC_ROOT), request).await
+}
+
+pub async fn route_async_in(doc_root: &Path, request: Request) -> Response {
+    route_async_cached(doc_root, None, request).await
+}
+
+pub async fn route_async_cached(
+    doc_root: &Path,
+    cache: Option<&FileCache>,
+    request: Request,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/sleep") => {
+            async_server::sleep(Duration::from_secs(10)).await;
+            page(&request, 200, &doc_root.join("hello.html"), cache)
+        }
+        ("GET", "/sleep/progress") => sleep_progress_async(&request),
+        _ => route_cached(doc_root, cache, &request),
+    }
+}
+
+// 以 doc_root 为文档根目录的站点，多个站点可以共用一个文件缓存
+pub fn site(doc_root: impl Into<PathBuf>, cache: Option<Arc<FileCache>>) -> impl Handler {
+    let doc_root = doc_root.into();
+    move |request: &Request| route_cached(&doc_root, cache.as_deref(), request)
+}
+
+// site 的异步版本
+pub fn site_async(
+    doc_root: impl Into<PathBuf>,
+    cache: Option<Arc<FileCache>>,
+) -> impl AsyncHandler {
+    let doc_root = Arc::new(doc_root.into());
+    move |request: Request| {
+        let doc_root = Arc::clone(&doc_root);
+        let cache = cache.clone();
+        async move { route_async_cached(&doc_root, cache.as_deref(), request).await }
+    }
+}
+
+// 在 site 的基础上增加 GET /stats，返回线程池状态
+pub fn route_with_stats(site: impl Handler, stats: StatsHandle) -> impl Handler {
+    move |request: &Request| match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/stats") => Response::text(200, stats.stats().to_string()),
+        _ => site.handle(request),
+    }
+}
+
+// 线程池队列满时，直接告诉客户端稍后再试；在 accept 线程上调用，不读请求
+pub fn respond_service_unavailable(stream: TcpStream, limiter: &Limiter) {
+    let response = Response::text(503, "Service Unavailable")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    reject_connection(&stream, &response, limiter);
+}
@@ -0,0 +1,65 @@
+use crate::access_log::ACCESS_TARGET;
+use std::fmt;
+use std::path::Path;
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::time::ChronoLocal;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, format::Writer};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+// 访问日志已经是格式化好的一整行，原样写出，不加时间和级别
+pub struct AccessLogFormatter;
+
+impl<S, N> FormatEvent<S, N> for AccessLogFormatter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+// 和 server、rcli 一样用 tracing-appender 按天滚动写文件：
+// 访问日志只写到 {dir}/access.*.log，其余日志按 RUST_LOG（默认 info）输出到 stderr
+pub fn init_logger(dir: impl AsRef<Path>) -> WorkerGuard {
+    let file_appender = Builder::new()
+        .rotation(Rotation::DAILY) // 每日滚动
+        .filename_prefix("access")
+        .filename_suffix("log")
+        .build(dir.as_ref())
+        .expect("Failed to create appender");
+
+    let (non_blocking, guard) = non_blocking(file_appender);
+    let access_layer = tracing_subscriber::fmt::layer()
+        .event_format(AccessLogFormatter)
+        .with_writer(non_blocking)
+        .with_ansi(false)
+        .with_filter(filter_fn(|metadata| metadata.target() == ACCESS_TARGET));
+
+    let stderr_layer = tracing_subscriber::fmt::layer()
+        .with_timer(ChronoLocal::new("%Y-%m-%d %H:%M:%S".to_string()))
+        .with_writer(std::io::stderr)
+        .with_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        // 访问日志只写文件，不在 stderr 上再打一遍
+        .with_filter(filter_fn(|metadata| metadata.target() != ACCESS_TARGET));
+
+    Registry::default()
+        .with(access_layer)
+        .with(stderr_layer)
+        .init();
+
+    guard
+}
@@ -1,35 +1,218 @@
-mod lib;
-mod thread_pool;
-
-use crate::lib::handle_connection_slow;
-use crate::thread_pool::ThreadPool;
-use futures::{SinkExt, StreamExt};
-use std::io::{BufRead, Write};
+use clap::Parser;
 use std::net::{TcpListener, TcpStream};
-use std::thread;
+use std::sync::Arc;
+use std::{process, thread};
+use tracing::{info, warn};
+use web::compression::{Compression, compress, compress_async};
+use web::config::{Cli, Config, Mode};
+use web::file_cache::{CacheStatsRoute, FileCache};
+use web::http::Handler;
+use web::logger::init_logger;
+use web::middleware::Chain;
+use web::respond_service_unavailable;
+use web::server::{ConnectionPermit, Limiter, admit, serve_admitted, serve_tls_admitted};
+use web::thread_pool::{RejectPolicy, ThreadPool};
+use web::tls::ServerConfig;
+use web::{async_server, event_loop, route_with_stats};
 
-fn handle_stream_by_single_thread(stream: TcpStream) {
-    handle_connection_slow(stream);
+fn serve_stream(
+    stream: TcpStream,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+    tls: Option<&Arc<ServerConfig>>,
+    permit: ConnectionPermit,
+) {
+    match tls {
+        Some(tls) => serve_tls_admitted(stream, tls, handler, limiter, permit),
+        None => serve_admitted(stream, handler, limiter, permit),
+    }
 }
 
-fn handle_stream_by_threads(stream: TcpStream) {
-    thread::spawn(|| {
-        handle_connection_slow(stream);
+fn handle_stream_by_single_thread(
+    stream: TcpStream,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+    tls: Option<&Arc<ServerConfig>>,
+    permit: ConnectionPermit,
+) {
+    serve_stream(stream, handler, limiter, tls, permit);
+}
+
+fn handle_stream_by_threads(
+    stream: TcpStream,
+    handler: &Arc<dyn Handler>,
+    limiter: &Limiter,
+    tls: Option<&Arc<ServerConfig>>,
+    permit: ConnectionPermit,
+) {
+    let handler = Arc::clone(handler);
+    let limiter = limiter.clone();
+    let tls = tls.cloned();
+    thread::spawn(move || {
+        serve_stream(stream, handler.as_ref(), &limiter, tls.as_ref(), permit);
     });
 }
 
-fn handle_stream_by_limit_threads(stream: TcpStream, pool: &mut ThreadPool) {
-    pool.execute(move || {
-        handle_connection_slow(stream);
+fn handle_stream_by_limit_threads(
+    stream: TcpStream,
+    pool: &ThreadPool,
+    handler: &Arc<dyn Handler>,
+    limiter: &Limiter,
+    tls: Option<&Arc<ServerConfig>>,
+    permit: ConnectionPermit,
+) {
+    // 任务被拒绝时 stream 会随任务一起丢掉，先留一份用来回 503
+    let fallback = stream.try_clone();
+    let handler = Arc::clone(handler);
+    let shared = limiter.clone();
+    let https = tls.is_some();
+    let tls = tls.cloned();
+    let result = pool.execute(move || {
+        serve_stream(stream, handler.as_ref(), &shared, tls.as_ref(), permit);
+    });
+
+    if let Err(err) = result {
+        warn!("{err}, reject connection");
+        // HTTPS 连接还没握手，回不了 503，直接关闭
+        if let Ok(stream) = fallback
+            && !https
+        {
+            respond_service_unavailable(stream, limiter);
+        }
+    }
+}
+
+// accept 出错（比如文件描述符用完）只记日志，不退出；
+// 连接名额在这里就占上，排队等工作线程的连接也算数，超过限制的连接不会交给工作线程
+fn accepted<'a>(
+    listener: &'a TcpListener,
+    limiter: &'a Limiter,
+    https: bool,
+) -> impl Iterator<Item = (TcpStream, ConnectionPermit)> + 'a {
+    listener.incoming().filter_map(move |stream| {
+        let stream = stream
+            .inspect_err(|err| warn!("accept failed: {err}"))
+            .ok()?;
+        let permit = admit(&stream, limiter, https)?;
+        Some((stream, permit))
     })
 }
 
+// 按配置套上中间件，开了文件缓存时加上 GET /stats/cache
+fn chain<H>(config: &Config, cache: Option<&Arc<FileCache>>, handler: H) -> Chain<H> {
+    let chain = config.chain(handler);
+    match cache {
+        Some(cache) => chain.with(CacheStatsRoute::new(Arc::clone(cache))),
+        None => chain,
+    }
+}
+
+// 按分发模式在一个监听 socket 上提供服务，tls 不为空时是 HTTPS；两个监听 socket 共用一个文件缓存
+fn serve(
+    config: &Config,
+    cache: Option<Arc<FileCache>>,
+    listener: TcpListener,
+    limiter: Limiter,
+    tls: Option<Arc<ServerConfig>>,
+) {
+    let cache = cache.as_ref();
+    let https = tls.is_some();
+    let wrap = |handler| compress(handler, Compression::default());
+    match config.mode {
+        Mode::Single => {
+            let handler = wrap(chain(config, cache, config.virtual_hosts(cache)));
+            for (stream, permit) in accepted(&listener, &limiter, https) {
+                handle_stream_by_single_thread(stream, &handler, &limiter, tls.as_ref(), permit);
+            }
+        }
+        Mode::Threads => {
+            let handler: Arc<dyn Handler> =
+                Arc::new(wrap(chain(config, cache, config.virtual_hosts(cache))));
+            for (stream, permit) in accepted(&listener, &limiter, https) {
+                handle_stream_by_threads(stream, &handler, &limiter, tls.as_ref(), permit);
+            }
+        }
+        Mode::EventLoop => {
+            let handler = Arc::new(wrap(chain(config, cache, config.virtual_hosts(cache))));
+            let workers = config.workers;
+            match tls {
+                Some(tls) => event_loop::run_tls(listener, handler, workers, limiter, tls),
+                None => event_loop::run_with_limits(listener, handler, workers, limiter),
+            }
+            .unwrap();
+        }
+        Mode::Async => {
+            let handler = Arc::new(compress_async(
+                chain(config, cache, config.virtual_hosts_async(cache)),
+                Compression::default(),
+            ));
+            let workers = config.workers;
+            match tls {
+                Some(tls) => async_server::run_tls(listener, handler, workers, limiter, tls),
+                None => async_server::run_with_limits(listener, handler, workers, limiter),
+            }
+            .unwrap();
+        }
+        Mode::Pool => {
+            let pool = ThreadPool::builder()
+                .core_size(config.workers)
+                .max_size(config.max_workers)
+                .queue_capacity(config.queue_capacity)
+                .reject_policy(RejectPolicy::Reject)
+                .build();
+            let handler = compress(
+                chain(
+                    config,
+                    cache,
+                    route_with_stats(config.virtual_hosts(cache), pool.stats_handle()),
+                ),
+                Compression::default(),
+            );
+            let handler: Arc<dyn Handler> = Arc::new(handler);
+            for (stream, permit) in accepted(&listener, &limiter, https) {
+                handle_stream_by_limit_threads(
+                    stream,
+                    &pool,
+                    &handler,
+                    &limiter,
+                    tls.as_ref(),
+                    permit,
+                );
+            }
+        }
+    }
+}
+
 fn main() {
-    let listener = TcpListener::bind("0.0.0.0:7878").unwrap();
-    let mut pool = ThreadPool::new(4);
+    // 配置来自 web.toml（或 --config 指定的文件），命令行参数优先
+    let config = match Cli::parse().load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    let _guard = init_logger(&config.log_dir);
 
-    for stream in listener.incoming() {
-        // handle_stream_by_threads(stream.unwrap());
-        handle_stream_by_limit_threads(stream.unwrap(), &mut pool);
+    let listener = TcpListener::bind(&config.address).unwrap();
+    info!("listening on {}, mode {:?}", config.address, config.mode);
+    // 访问日志由服务端在响应写完后记录，字节数是实际发出去的（压缩后的）大小
+    let limiter = Limiter::new(config.limits.to_limits()).with_access_log(config.access_log_format);
+    let cache = config.file_cache();
+
+    // 配置了证书和私钥时，另外在 tls.address 上提供 HTTPS
+    if let Some(settings) = &config.tls {
+        let tls = settings.builder().build().unwrap();
+        let tls_listener = TcpListener::bind(&settings.address).unwrap();
+        info!(
+            "listening on {} (https), mode {:?}",
+            settings.address, config.mode
+        );
+        let config = config.clone();
+        let limiter = limiter.clone();
+        let cache = cache.clone();
+        thread::spawn(move || serve(&config, cache, tls_listener, limiter, Some(tls)));
     }
+
+    serve(&config, cache, listener, limiter, None);
 }
@@ -0,0 +1,449 @@
+use crate::async_server::{self, AsyncHandler};
+use crate::http::{Handler, Request, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// 中间件：before 在 handler 之前按添加顺序调用，可以改写请求，返回 Some 时短路，
+// 后面的中间件和 handler 都不再调用；after 在得到响应后按相反顺序调用，
+// 只有 before 已经放行的中间件才会调用 after
+pub trait Middleware: Send + Sync + 'static {
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    // started 是 Chain 收到请求的时间
+    fn after(&self, _request: &Request, _response: &mut Response, _started: Instant) {}
+
+    // 同 Handler::streams_body，before 会自己读 request.stream 时返回 true
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+
+    // before 会做阻塞的 I/O（比如转发给上游）时返回 true，
+    // 异步模式下这样的请求的 before 放到阻塞线程池上调用，不占用 executor 的线程
+    fn blocks(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+// 套在 handler（一般是 site 这样的路由）外面的一串中间件，同步和异步 handler 都可以用
+pub struct Chain<H> {
+    middlewares: Vec<Arc<dyn Middleware>>,
+    handler: Arc<H>,
+}
+
+impl<H> Chain<H> {
+    pub fn new(handler: H) -> Chain<H> {
+        Chain {
+            middlewares: Vec::new(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    // 先添加的在外层
+    pub fn with(mut self, middleware: impl Middleware) -> Chain<H> {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+}
+
+// 依次调用 before，返回短路的响应和放行的中间件个数
+fn enter(middlewares: &[Arc<dyn Middleware>], request: &mut Request) -> (Option<Response>, usize) {
+    for (passed, middleware) in middlewares.iter().enumerate() {
+        if let Some(response) = middleware.before(request) {
+            return (Some(response), passed);
+        }
+    }
+    (None, middlewares.len())
+}
+
+fn leave(
+    middlewares: &[Arc<dyn Middleware>],
+    request: &Request,
+    response: &mut Response,
+    started: Instant,
+) {
+    for middleware in middlewares.iter().rev() {
+        middleware.after(request, response, started);
+    }
+}
+
+impl<H: Handler> Handler for Chain<H> {
+    fn handle(&self, request: &Request) -> Response {
+        if self.middlewares.is_empty() {
+            return self.handler.handle(request);
+        }
+        let started = Instant::now();
+        // before 可能改写请求，复制一份
+        let mut request = request.clone();
+        let (response, passed) = enter(&self.middlewares, &mut request);
+        let mut response = response.unwrap_or_else(|| self.handler.handle(&request));
+        leave(
+            &self.middlewares[..passed],
+            &request,
+            &mut response,
+            started,
+        );
+        response
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.middlewares
+            .iter()
+            .any(|middleware| middleware.streams_body(request))
+            || self.handler.streams_body(request)
+    }
+}
+
+impl<H: AsyncHandler> AsyncHandler for Chain<H> {
+    fn handle(&self, mut request: Request) -> BoxFuture<'static, Response> {
+        let started = Instant::now();
+        let blocking = self
+            .middlewares
+            .iter()
+            .any(|middleware| middleware.blocks(&request));
+        if blocking {
+            // 有中间件要做阻塞的 I/O，整串 before 放到阻塞线程池上调用
+            let middlewares = self.middlewares.clone();
+            let handler = Arc::clone(&self.handler);
+            return Box::pin(async move {
+                let (response, passed, request, middlewares) =
+                    async_server::spawn_blocking(move || {
+                        let (response, passed) = enter(&middlewares, &mut request);
+                        (response, passed, request, middlewares)
+                    })
+                    .await;
+                let head = request.without_body();
+                let mut response = match response {
+                    Some(response) => response,
+                    None => handler.handle(request).await,
+                };
+                leave(&middlewares[..passed], &head, &mut response, started);
+                response
+            });
+        }
+        let (response, passed) = enter(&self.middlewares, &mut request);
+        let middlewares = self.middlewares[..passed].to_vec();
+        let head = request.without_body();
+        let response = match response {
+            Some(response) => Box::pin(async move { response }),
+            None => self.handler.handle(request),
+        };
+        Box::pin(async move {
+            let mut response = response.await;
+            leave(&middlewares, &head, &mut response, started);
+            response
+        })
+    }
+}
+
+// 只对 prefix 下的路径生效的中间件
+pub struct Scoped<M> {
+    prefix: String,
+    middleware: M,
+}
+
+pub fn scoped<M: Middleware>(prefix: &str, middleware: M) -> Scoped<M> {
+    Scoped {
+        prefix: prefix.trim_end_matches('/').to_string(),
+        middleware,
+    }
+}
+
+impl<M> Scoped<M> {
+    fn matches(&self, path: &str) -> bool {
+        path_matches(&self.prefix, path)
+    }
+}
+
+// 按路径段匹配前缀（末尾不带 /），/admin 匹配 /admin 和 /admin/x，不匹配 /administrator
+pub fn path_matches(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+impl<M: Middleware> Middleware for Scoped<M> {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        if self.matches(&request.path) {
+            self.middleware.before(request)
+        } else {
+            None
+        }
+    }
+
+    fn after(&self, request: &Request, response: &mut Response, started: Instant) {
+        if self.matches(&request.path) {
+            self.middleware.after(request, response, started);
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.matches(&request.path) && self.middleware.streams_body(request)
+    }
+
+    fn blocks(&self, request: &Request) -> bool {
+        self.matches(&request.path) && self.middleware.blocks(request)
+    }
+}
+
+// 给每个请求一个 X-Request-Id：客户端带了合法的就沿用，否则生成一个；
+// 写回请求头让 handler 能拿到，同时加到响应头里
+pub struct RequestId {
+    seed: u64,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        RequestId {
+            seed: nanos ^ (u64::from(std::process::id()) << 32),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    fn next_id(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("{:016x}-{count:08x}", self.seed)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(&byte))
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        match request.header(REQUEST_ID_HEADER) {
+            Some(id) if valid_request_id(id) => {}
+            _ => request.set_header(REQUEST_ID_HEADER, &self.next_id()),
+        }
+        None
+    }
+
+    fn after(&self, request: &Request, response: &mut Response, _started: Instant) {
+        if let Some(id) = request.header(REQUEST_ID_HEADER) {
+            response.set_header(REQUEST_ID_HEADER, id);
+        }
+    }
+}
+
+// 响应头里带上处理耗时：X-Response-Time 给人看，Server-Timing 给浏览器开发者工具看
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn after(&self, _request: &Request, response: &mut Response, started: Instant) {
+        let millis = started.elapsed().as_secs_f64() * 1000.0;
+        response.set_header("X-Response-Time", &format!("{millis:.3}ms"));
+        response.set_header("Server-Timing", &format!("app;dur={millis:.3}"));
+    }
+}
+
+// 跨域：预检请求（OPTIONS + Access-Control-Request-Method）直接在 before 里应答，
+// 其余带 Origin 的请求在 after 里加上 Access-Control-Allow-Origin
+pub struct Cors {
+    allow_origins: Vec<String>,
+    allow_methods: Vec<String>,
+    allow_headers: Vec<String>,
+    allow_credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    // 默认允许任意来源的 GET、HEAD、POST
+    pub fn new() -> Cors {
+        Cors {
+            allow_origins: Vec::new(),
+            allow_methods: vec!["GET".into(), "HEAD".into(), "POST".into()],
+            allow_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+
+    // 调用后只允许列出的来源，* 表示任意来源
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        self.allow_origins.push(origin.to_string());
+        self
+    }
+
+    pub fn allow_methods<I, S>(mut self, methods: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allow_methods = methods.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // 不设置时，预检请求要求的头都允许
+    pub fn allow_headers<I, S>(mut self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allow_headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        self.allow_credentials = allow;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // 允许时返回 Access-Control-Allow-Origin 的值；带凭据时不能用 *，要回显来源
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        let any = self.allow_origins.is_empty() || self.allow_origins.iter().any(|o| o == "*");
+        if any {
+            Some(if self.allow_credentials { origin } else { "*" }.to_string())
+        } else {
+            self.allow_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then(|| origin.to_string())
+        }
+    }
+
+    fn set_origin(&self, response: &mut Response, allowed: &str) {
+        response.set_header("Access-Control-Allow-Origin", allowed);
+        if allowed != "*" {
+            response.add_vary("Origin");
+        }
+        if self.allow_credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str) -> Response {
+        let Some(allowed) = self.allowed_origin(origin) else {
+            return Response::text(403, "CORS origin not allowed");
+        };
+        let mut response = Response::new(204);
+        self.set_origin(&mut response, &allowed);
+        response.set_header(
+            "Access-Control-Allow-Methods",
+            &self.allow_methods.join(", "),
+        );
+        let headers = match request.header("Access-Control-Request-Headers") {
+            Some(requested) if self.allow_headers.is_empty() => requested.to_string(),
+            _ => self.allow_headers.join(", "),
+        };
+        if !headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", &headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        response.add_vary("Access-Control-Request-Method");
+        response.add_vary("Access-Control-Request-Headers");
+        response
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let origin = request.header("Origin")?;
+        if request.method == "OPTIONS" && request.header("Access-Control-Request-Method").is_some()
+        {
+            return Some(self.preflight(request, origin));
+        }
+        None
+    }
+
+    fn after(&self, request: &Request, response: &mut Response, _started: Instant) {
+        if let Some(origin) = request.header("Origin")
+            && let Some(allowed) = self.allowed_origin(origin)
+        {
+            self.set_origin(response, &allowed);
+        }
+    }
+}
+
+// HTTP Basic 认证，用户名密码不对时回 401
+pub struct BasicAuth {
+    realm: String,
+    users: HashMap<String, String>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_string(),
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn user(mut self, name: &str, password: &str) -> BasicAuth {
+        self.users.insert(name.to_string(), password.to_string());
+        self
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let Some((name, password)) = request.header("Authorization").and_then(basic_credentials)
+        else {
+            return false;
+        };
+        self.users
+            .get(&name)
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+}
+
+// 解析 "Basic base64(name:password)"
+pub fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    Some((name.to_string(), password.to_string()))
+}
+
+// 比较密码时不提前返回，避免按耗时猜出密码
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        if self.authorized(request) {
+            return None;
+        }
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+        Some(Response::text(401, "Unauthorized").with_header("WWW-Authenticate", &challenge))
+    }
+}
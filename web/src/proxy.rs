@@ -0,0 +1,437 @@
+use crate::http::{
+    BodyLength, BodyStream, ChunkedReader, LAST_CHUNK, Request, Response, encode_chunk,
+};
+use crate::middleware::{Middleware, path_matches};
+use crate::server;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// 逐跳头只对一条连接有效，不转发
+const HOP_BY_HOP: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// 一个上游服务，被动健康检查：连续失败 max_fails 次后摘除 fail_timeout 时间
+struct Upstream {
+    addr: String,
+    fails: AtomicUsize,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn new(addr: &str) -> Upstream {
+        let addr = addr.trim_start_matches("http://").trim_end_matches('/');
+        Upstream {
+            addr: addr.to_string(),
+            fails: AtomicUsize::new(0),
+            down_until: Mutex::new(None),
+        }
+    }
+
+    fn healthy(&self, now: Instant) -> bool {
+        let down_until = self
+            .down_until
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        down_until.is_none_or(|until| now >= until)
+    }
+
+    fn succeeded(&self) {
+        self.fails.store(0, Ordering::Relaxed);
+        *self
+            .down_until
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = None;
+    }
+
+    fn failed(&self, max_fails: usize, fail_timeout: Duration) {
+        let fails = self.fails.fetch_add(1, Ordering::Relaxed) + 1;
+        if fails >= max_fails {
+            warn!("upstream {} failed {fails} times, marked down", self.addr);
+            self.fails.store(0, Ordering::Relaxed);
+            *self
+                .down_until
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()) = Some(Instant::now() + fail_timeout);
+        }
+    }
+}
+
+// 把 prefix 下的请求转发到上游，多个上游轮询；作为中间件挂在路由前面，匹配时短路返回上游的响应。
+// 阻塞模式下请求体边读边转发给上游，不受 max_body_size 限制；事件循环和异步模式下请求体
+// 还是由服务端按 max_body_size 读完再转发。上游的响应体边读边发给客户端；
+// 异步模式下和上游的通信都在阻塞线程池上进行
+pub struct Proxy {
+    prefix: String,
+    upstreams: Vec<Upstream>,
+    next: AtomicUsize,
+    strip_prefix: bool,
+    preserve_host: bool,
+    max_fails: usize,
+    fail_timeout: Duration,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl Proxy {
+    // upstreams 形如 127.0.0.1:3000 或 http://localhost:3000
+    pub fn new<I, S>(prefix: &str, upstreams: I) -> Proxy
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Proxy {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstreams: upstreams
+                .into_iter()
+                .map(|addr| Upstream::new(addr.as_ref()))
+                .collect(),
+            next: AtomicUsize::new(0),
+            strip_prefix: false,
+            preserve_host: false,
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(3),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    // 转发时去掉路径里的 prefix：/api/users -> /users
+    pub fn strip_prefix(mut self, strip: bool) -> Proxy {
+        self.strip_prefix = strip;
+        self
+    }
+
+    // 转发客户端原来的 Host，默认改成上游地址
+    pub fn preserve_host(mut self, preserve: bool) -> Proxy {
+        self.preserve_host = preserve;
+        self
+    }
+
+    pub fn max_fails(mut self, max_fails: usize) -> Proxy {
+        self.max_fails = max_fails.max(1);
+        self
+    }
+
+    pub fn fail_timeout(mut self, fail_timeout: Duration) -> Proxy {
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // 上游读写的超时
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.prefix.is_empty() || path_matches(&self.prefix, path)
+    }
+
+    // 按轮询顺序挑健康的上游，连不上就换下一个；全都摘除时回 502
+    pub fn forward(&self, request: &Request) -> Response {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let count = self.upstreams.len();
+        let candidates = (0..count)
+            .map(|offset| &self.upstreams[(start + offset) % count])
+            .filter(|upstream| upstream.healthy(now));
+
+        for upstream in candidates {
+            let stream = match self.connect(&upstream.addr) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("connect upstream {} failed: {err}", upstream.addr);
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    continue;
+                }
+            };
+            return match self.exchange(stream, upstream, request) {
+                Ok(response) => {
+                    upstream.succeeded();
+                    response
+                }
+                // 读客户端的请求体出错不算上游失败，按读请求出错回 408 或 400
+                Err(Failure::Client(err)) => {
+                    warn!(
+                        "read request body for upstream {} failed: {err}",
+                        upstream.addr
+                    );
+                    let mut response = server::error_response(&err);
+                    response.set_header("Connection", "close");
+                    response
+                }
+                Err(Failure::Upstream(err)) => {
+                    warn!("upstream {} failed: {err}", upstream.addr);
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    match err.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                            Response::text(504, "Gateway Timeout")
+                        }
+                        _ => Response::text(502, "Bad Gateway"),
+                    }
+                }
+            };
+        }
+        Response::text(502, "No healthy upstream")
+    }
+
+    fn connect(&self, addr: &str) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "upstream address not resolved");
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn exchange(
+        &self,
+        mut stream: TcpStream,
+        upstream: &Upstream,
+        request: &Request,
+    ) -> Result<Response, Failure> {
+        stream.write_all(&self.request_head(upstream, request))?;
+        match request.stream.as_ref().and_then(BodyStream::take) {
+            Some(body) => forward_body(body, &mut stream, request)?,
+            None => stream.write_all(&request.body)?,
+        }
+        stream.flush()?;
+        Ok(read_response(BufReader::new(stream), &request.method)?)
+    }
+
+    // 改写后的请求头：Host 换成上游地址，追加 X-Forwarded-*，去掉逐跳头
+    fn request_head(&self, upstream: &Upstream, request: &Request) -> Vec<u8> {
+        let mut path = request.path.as_str();
+        if self.strip_prefix {
+            path = &path[self.prefix.len()..];
+        }
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{path}")
+        };
+        let target = match &request.query {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+
+        let mut head = format!("{} {target} HTTP/1.1\r\n", request.method);
+        let dropped = connection_tokens(request.header("Connection"));
+        for (name, value) in &request.headers {
+            if is_hop_by_hop(name, &dropped)
+                || name.eq_ignore_ascii_case("Host")
+                || name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("X-Forwarded-For")
+                || name.eq_ignore_ascii_case("X-Forwarded-Host")
+                || name.eq_ignore_ascii_case("X-Forwarded-Proto")
+            {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+
+        let host = match request.header("Host") {
+            Some(host) if self.preserve_host => host,
+            _ => upstream.addr.as_str(),
+        };
+        head.push_str(&format!("Host: {host}\r\n"));
+        let client = request.remote_addr.map(|addr| addr.ip().to_string());
+        let forwarded_for = match (request.header("X-Forwarded-For"), client) {
+            (Some(previous), Some(client)) => Some(format!("{previous}, {client}")),
+            (previous, client) => client.or(previous.map(str::to_string)),
+        };
+        if let Some(forwarded_for) = forwarded_for {
+            head.push_str(&format!("X-Forwarded-For: {forwarded_for}\r\n"));
+        }
+        if let Some(host) = request.header("Host") {
+            head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+        }
+        // 按客户端实际连上来的方式填写，不信任客户端自己带的值
+        let proto = if request.secure { "https" } else { "http" };
+        head.push_str(&format!("X-Forwarded-Proto: {proto}\r\n"));
+        match (&request.stream, request.body_length()) {
+            // 边读边转发的请求体按原来的方式分帧：chunked 的重新分块，有长度的带上长度
+            (Some(_), Ok(BodyLength::Chunked)) => head.push_str("Transfer-Encoding: chunked\r\n"),
+            (Some(_), Ok(BodyLength::Fixed(length)))
+                if length > 0 || request.header("Content-Length").is_some() =>
+            {
+                head.push_str(&format!("Content-Length: {length}\r\n"));
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                if !request.body.is_empty() || request.header("Content-Length").is_some() {
+                    head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+                }
+            }
+        }
+        // 不复用到上游的连接，响应体没有长度时读到连接关闭为止
+        head.push_str("Connection: close\r\n\r\n");
+        head.into_bytes()
+    }
+}
+
+impl Middleware for Proxy {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        self.matches(&request.path).then(|| self.forward(request))
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.matches(&request.path)
+    }
+
+    fn blocks(&self, request: &Request) -> bool {
+        self.matches(&request.path)
+    }
+}
+
+// 转发出错时区分是读客户端的请求体出错还是和上游通信出错
+enum Failure {
+    Client(io::Error),
+    Upstream(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        Failure::Upstream(err)
+    }
+}
+
+// 把客户端的请求体边读边写给上游，chunked 的请求体重新分块
+fn forward_body(
+    mut body: Box<dyn Read + Send>,
+    upstream: &mut TcpStream,
+    request: &Request,
+) -> Result<(), Failure> {
+    let chunked = matches!(request.body_length(), Ok(BodyLength::Chunked));
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Failure::Client(err)),
+        };
+        if chunked {
+            upstream.write_all(&encode_chunk(&buf[..read]))?;
+        } else {
+            upstream.write_all(&buf[..read])?;
+        }
+    }
+    if chunked {
+        upstream.write_all(LAST_CHUNK)?;
+    }
+    Ok(())
+}
+
+// Connection 头里列出的也是逐跳头
+fn connection_tokens(connection: Option<&str>) -> Vec<String> {
+    connection
+        .map(|value| {
+            value
+                .split(',')
+                .map(|token| token.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_hop_by_hop(name: &str, dropped: &[String]) -> bool {
+    HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name))
+        || dropped.iter().any(|token| token.eq_ignore_ascii_case(name))
+}
+
+// 读上游响应头，响应体包装成流：有 Content-Length 的原样转发，chunked 的先解码再重新分块，
+// 都没有的读到连接关闭
+fn read_response(mut reader: BufReader<TcpStream>, method: &str) -> io::Result<Response> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed upstream response");
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "upstream closed connection",
+        ));
+    }
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut response = Response::new(status);
+    let find = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+    let chunked = find("Transfer-Encoding")
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let content_length = find("Content-Length");
+    let dropped = connection_tokens(find("Connection").as_deref());
+    for (name, value) in &headers {
+        if !is_hop_by_hop(name, &dropped) && !name.eq_ignore_ascii_case("Content-Length") {
+            response.headers.push((name.clone(), value.clone()));
+        }
+    }
+
+    // HEAD、1xx、204、304 没有响应体；HEAD 和 304 的 Content-Length 是实体的长度，照样转发，
+    // 1xx 和 204 不能带 Content-Length
+    if method == "HEAD" || matches!(status, 100..=199 | 204 | 304) {
+        response.no_body = true;
+        if !matches!(status, 100..=199 | 204)
+            && let Some(length) = content_length
+        {
+            let length: u64 = length.parse().map_err(|_| invalid())?;
+            response.set_header("Content-Length", &length.to_string());
+        }
+        return Ok(response);
+    }
+    let body: Box<dyn Read + Send> = if chunked {
+        Box::new(ChunkedReader::new(reader))
+    } else if let Some(length) = content_length {
+        let length: u64 = length.parse().map_err(|_| invalid())?;
+        response.set_header("Content-Length", &length.to_string());
+        Box::new(reader.take(length))
+    } else {
+        Box::new(reader)
+    };
+    Ok(response.with_stream(body))
+}
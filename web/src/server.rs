@@ -0,0 +1,582 @@
+use crate::access_log::{AccessEntry, LogFormat, log_access};
+use crate::http::{BodyLength, BodyStream, ChunkedDecoder, Handler, ParseError, Request, Response};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// 长连接上两个请求之间最多等多久
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 防慢速攻击（slowloris）相关的限制
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    // 单次读 socket 的超时
+    pub read_timeout: Duration,
+    // 单次写 socket 的超时
+    pub write_timeout: Duration,
+    // 从请求第一个字节到请求头读完的期限，新连接从建立时开始计时
+    pub header_timeout: Duration,
+    // 读完请求头之后，请求体要在这个期限内读完
+    pub body_timeout: Duration,
+    pub keep_alive_timeout: Duration,
+    pub max_body_size: usize,
+    // 同一个 IP 同时打开的连接数，0 表示不限
+    pub max_connections_per_ip: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(60),
+            keep_alive_timeout: KEEP_ALIVE_TIMEOUT,
+            max_body_size: 1024 * 1024,
+            max_connections_per_ip: 64,
+        }
+    }
+}
+
+// 各分发模式共用的连接限制，clone 后共享同一份计数；
+// 设置了 access_log 时，连接上每个写完的响应记一条访问日志
+#[derive(Clone, Default)]
+pub struct Limiter {
+    pub limits: Limits,
+    pub access_log: Option<LogFormat>,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits,
+            access_log: None,
+            active: Arc::default(),
+        }
+    }
+
+    pub fn with_access_log(mut self, format: LogFormat) -> Limiter {
+        self.access_log = Some(format);
+        self
+    }
+
+    // bytes 是实际写出的响应体字节数，started 是开始读这个请求的时间
+    pub fn log_access(&self, request: &Request, status: u16, bytes: usize, started: Instant) {
+        if let Some(format) = self.access_log {
+            let entry = AccessEntry::new(request, status, bytes, started.elapsed());
+            log_access(&entry, format);
+        }
+    }
+
+    // 占用一个连接名额，超过 max_connections_per_ip 时返回 None
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = active.entry(ip).or_insert(0);
+        if self.limits.max_connections_per_ip > 0 && *count >= self.limits.max_connections_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionPermit {
+            active: Arc::clone(&self.active),
+            ip,
+        })
+    }
+
+    pub fn connections(&self, ip: IpAddr) -> usize {
+        let active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        active.get(&ip).copied().unwrap_or(0)
+    }
+}
+
+// 连接关闭时归还名额
+pub struct ConnectionPermit {
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+// 阻塞模式下的连接，明文 TCP 或者 TLS，超时设置在底层的 TcpStream 上
+pub trait Transport: Read + Write {
+    fn tcp(&self) -> &TcpStream;
+
+    // 关闭前的收尾，TLS 连接要发 close_notify
+    fn shutdown(&mut self) {}
+
+    fn secure(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Transport for StreamOwned<ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn shutdown(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+
+    fn secure(&self) -> bool {
+        true
+    }
+}
+
+// 每次读之前按剩余时间重新设置 socket 超时，实现整体的读期限
+struct DeadlineStream<S> {
+    stream: S,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl<S: Transport> Read for DeadlineStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut timeout = self.timeout;
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            timeout = timeout.min(remaining);
+        }
+        self.stream.tcp().set_read_timeout(Some(timeout))?;
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Transport> Write for DeadlineStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+pub fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+// 阻塞模式下处理一个连接，使用默认限制
+pub fn serve_connection(stream: TcpStream, handler: &dyn Handler) {
+    serve_connection_limited(stream, handler, &Limiter::default());
+}
+
+// 在 accept 线程上占用连接名额，连接排队等工作线程期间也算数；
+// 超过限制时回 429 并关闭（HTTPS 连接还没握手，回不了，直接关闭），返回 None
+pub fn admit(stream: &TcpStream, limiter: &Limiter, https: bool) -> Option<ConnectionPermit> {
+    let permit = limiter.acquire(stream.peer_addr().ok()?.ip());
+    if permit.is_none() && !https {
+        reject_connection(stream, &too_many_connections(), limiter);
+    }
+    permit
+}
+
+// 在 accept 线程上直接回一个响应并关闭连接，不读请求，写也只等很短的时间
+pub fn reject_connection(stream: &TcpStream, response: &Response, limiter: &Limiter) {
+    let started = Instant::now();
+    let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+    write_error(stream, response, limiter, stream.peer_addr().ok(), started);
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+// 写出服务端自己回的错误响应，也记一条访问日志，没读到请求行时请求行记成 -
+fn write_error<W: Write>(
+    writer: W,
+    response: &Response,
+    limiter: &Limiter,
+    remote_addr: Option<SocketAddr>,
+    started: Instant,
+) {
+    let mut writer = Counted::new(writer);
+    let _ = response.write_to(&mut writer);
+    let request = Request {
+        remote_addr,
+        ..Request::default()
+    };
+    limiter.log_access(
+        &request,
+        response.status,
+        writer.body_bytes(response),
+        started,
+    );
+}
+
+// 阻塞模式下处理一个连接，单线程、每连接一线程和线程池模式共用
+pub fn serve_connection_limited(stream: TcpStream, handler: &dyn Handler, limiter: &Limiter) {
+    if let Some(permit) = admit(&stream, limiter, false) {
+        serve_admitted(stream, handler, limiter, permit);
+    }
+}
+
+// 同 serve_connection_limited，名额已经在 accept 时用 admit 占好了
+pub fn serve_admitted(
+    stream: TcpStream,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+    permit: ConnectionPermit,
+) {
+    serve_transport(stream, handler, limiter, permit);
+}
+
+// 同 serve_connection_limited，握手在读第一个请求时完成，也受请求头期限约束
+pub fn serve_tls_connection(
+    stream: TcpStream,
+    tls: &Arc<ServerConfig>,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+) {
+    if let Some(permit) = admit(&stream, limiter, true) {
+        serve_tls_admitted(stream, tls, handler, limiter, permit);
+    }
+}
+
+// serve_admitted 的 HTTPS 版本
+pub fn serve_tls_admitted(
+    stream: TcpStream,
+    tls: &Arc<ServerConfig>,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+    permit: ConnectionPermit,
+) {
+    match ServerConnection::new(Arc::clone(tls)) {
+        Ok(conn) => serve_transport(StreamOwned::new(conn, stream), handler, limiter, permit),
+        Err(err) => warn!("tls setup failed: {err}"),
+    }
+}
+
+// 连接的读缓冲区，handler 边读边用请求体时和服务端共用
+type SharedReader<S> = Arc<Mutex<BufReader<DeadlineStream<S>>>>;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn serve_transport<S: Transport + Send + 'static>(
+    stream: S,
+    handler: &dyn Handler,
+    limiter: &Limiter,
+    _permit: ConnectionPermit,
+) {
+    let limits = &limiter.limits;
+    let remote_addr = stream.tcp().peer_addr().ok();
+    let secure = stream.secure();
+    // TLS 写的时候也可能要读（握手），先给底层 socket 设上读超时
+    let _ = stream.tcp().set_read_timeout(Some(limits.read_timeout));
+    let _ = stream.tcp().set_write_timeout(Some(limits.write_timeout));
+    let conn: SharedReader<S> = Arc::new(Mutex::new(BufReader::new(DeadlineStream {
+        stream,
+        timeout: limits.read_timeout,
+        deadline: Some(Instant::now() + limits.header_timeout),
+    })));
+    let mut first = true;
+    let connected = Instant::now();
+
+    loop {
+        let mut stream = lock(&conn);
+        // 等下一个请求的第一个字节；长连接空闲超时直接关闭，新连接一直不发请求则回 408
+        if !first {
+            stream.get_mut().deadline = Some(Instant::now() + limits.keep_alive_timeout);
+        }
+        match stream.fill_buf() {
+            Ok([]) => break,
+            Ok(_) => {}
+            Err(err) if first && is_timeout(&err) => {
+                write_error(
+                    stream.get_mut(),
+                    &request_timeout(),
+                    limiter,
+                    remote_addr,
+                    connected,
+                );
+                break;
+            }
+            Err(_) => break,
+        }
+        let started = Instant::now();
+        if !first {
+            stream.get_mut().deadline = Some(started + limits.header_timeout);
+        }
+        first = false;
+
+        let (mut request, streamed) = match read_request(&mut stream, limits, handler) {
+            Ok(Some(read)) => read,
+            Ok(None) => break,
+            Err(response) => {
+                write_error(stream.get_mut(), &response, limiter, remote_addr, started);
+                break;
+            }
+        };
+        drop(stream);
+        request.remote_addr = remote_addr;
+        request.secure = secure;
+        let finished = streamed.map(|length| {
+            let body = StreamedBody::new(Arc::clone(&conn), length);
+            let finished = Arc::clone(&body.finished);
+            request.stream = Some(BodyStream::new(body));
+            finished
+        });
+
+        let mut response = respond(handler, &request);
+        // handler 没把请求体读完，连接上剩下的数据不能当成下一个请求，回完响应就关闭
+        let unfinished = finished.is_some_and(|finished| !finished.load(Ordering::Acquire));
+        if unfinished {
+            response.set_header("Connection", "close");
+        }
+        let mut stream = lock(&conn);
+        stream.get_mut().deadline = None;
+        let mut writer = Counted::new(stream.get_mut());
+        let written = response.write_to(&mut writer);
+        let bytes = writer.body_bytes(&response);
+        limiter.log_access(&request, response.status, bytes, started);
+        if written.is_err() || !request.keep_alive() || unfinished {
+            break;
+        }
+    }
+    lock(&conn).get_mut().stream.shutdown();
+}
+
+// handler 边读边用的请求体，按 Content-Length 或 chunked 编码从连接上读到请求体结束；
+// finished 告诉服务端请求体有没有读完
+struct StreamedBody<S> {
+    conn: SharedReader<S>,
+    framing: Framing,
+    finished: Arc<AtomicBool>,
+}
+
+enum Framing {
+    // 还剩多少字节
+    Fixed(usize),
+    // 解码器和已经解码、还没交给 handler 的数据
+    Chunked(ChunkedDecoder, Vec<u8>),
+}
+
+impl<S: Transport> StreamedBody<S> {
+    fn new(conn: SharedReader<S>, length: BodyLength) -> StreamedBody<S> {
+        let framing = match length {
+            BodyLength::Fixed(length) => Framing::Fixed(length),
+            BodyLength::Chunked => Framing::Chunked(ChunkedDecoder::new(usize::MAX), Vec::new()),
+        };
+        let body = StreamedBody {
+            conn,
+            framing,
+            finished: Arc::default(),
+        };
+        body.finished.store(body.is_done(), Ordering::Release);
+        body
+    }
+
+    fn is_done(&self) -> bool {
+        match &self.framing {
+            Framing::Fixed(remaining) => *remaining == 0,
+            Framing::Chunked(decoder, decoded) => decoder.is_done() && decoded.is_empty(),
+        }
+    }
+}
+
+impl<S: Transport> Read for StreamedBody<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.is_done() {
+            return Ok(0);
+        }
+        let mut conn = lock(&self.conn);
+        let read = match &mut self.framing {
+            Framing::Fixed(remaining) => {
+                let max = buf.len().min(*remaining);
+                let read = conn.read(&mut buf[..max])?;
+                if read == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *remaining -= read;
+                read
+            }
+            Framing::Chunked(decoder, decoded) => {
+                while decoded.is_empty() && !decoder.is_done() {
+                    let data = conn.fill_buf()?;
+                    if data.is_empty() {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let used = decoder.decode(data)?;
+                    conn.consume(used);
+                    *decoded = decoder.take_body();
+                }
+                let read = decoded.len().min(buf.len());
+                buf[..read].copy_from_slice(&decoded[..read]);
+                decoded.drain(..read);
+                read
+            }
+        };
+        drop(conn);
+        if self.is_done() {
+            self.finished.store(true, Ordering::Release);
+        }
+        Ok(read)
+    }
+}
+
+// 数写到连接上的字节数，访问日志按实际发出去的量记
+struct Counted<W> {
+    inner: W,
+    written: usize,
+}
+
+impl<W: Write> Counted<W> {
+    fn new(inner: W) -> Counted<W> {
+        Counted { inner, written: 0 }
+    }
+
+    // 去掉响应头以后的部分，chunked 编码的分块格式也算在内
+    fn body_bytes(&self, response: &Response) -> usize {
+        self.written.saturating_sub(response.head_bytes().len())
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// 读一个请求，出错时返回应该回给客户端的错误响应；
+// handler 要边读边用请求体时不读请求体，连同请求体的长度一起返回
+fn read_request<S: Transport>(
+    reader: &mut BufReader<DeadlineStream<S>>,
+    limits: &Limits,
+    handler: &dyn Handler,
+) -> Result<Option<(Request, Option<BodyLength>)>, Response> {
+    let mut request = match Request::read_head(reader) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(error_response(&err)),
+    };
+
+    let length = request.body_length().map_err(parse_error_response)?;
+    let streamed = handler.streams_body(&request);
+    if let BodyLength::Fixed(length) = length
+        && length > limits.max_body_size
+        && !streamed
+    {
+        return Err(payload_too_large());
+    }
+    // 请求体除了每次读的超时，还有一个总的期限，边读边用的请求体也一样
+    reader.get_mut().deadline = Some(Instant::now() + limits.body_timeout);
+    if streamed {
+        return Ok(Some((request, Some(length))));
+    }
+    request.body = match length {
+        BodyLength::Fixed(length) => {
+            let mut body = vec![0; length];
+            reader
+                .read_exact(&mut body)
+                .map_err(|err| error_response(&err))?;
+            body
+        }
+        BodyLength::Chunked => {
+            read_chunked(reader, limits.max_body_size).map_err(|err| error_response(&err))?
+        }
+    };
+    reader.get_mut().deadline = None;
+    Ok(Some((request, None)))
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, max_body_size: usize) -> io::Result<Vec<u8>> {
+    let mut decoder = ChunkedDecoder::new(max_body_size);
+    while !decoder.is_done() {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let used = decoder.decode(buf)?;
+        reader.consume(used);
+    }
+    Ok(decoder.into_body())
+}
+
+// 读请求出错时对应的响应：超时 408，解析错误按 parse_error_response，
+// 其余情况连接已经不可用，回了也没人收
+pub fn error_response(err: &io::Error) -> Response {
+    if is_timeout(err) {
+        return request_timeout();
+    }
+    match err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ParseError>())
+    {
+        Some(err) => parse_error_response(err.clone()),
+        None => bad_request(err),
+    }
+}
+
+// 请求体过大 413，不支持的传输编码 501，其余格式错误 400
+pub fn parse_error_response(err: ParseError) -> Response {
+    match err {
+        ParseError::BodyTooLarge => payload_too_large(),
+        ParseError::UnsupportedTransferEncoding => not_implemented(),
+        err => bad_request(err),
+    }
+}
+
+// 调用 handler，并按请求补上连接相关的响应头
+pub fn respond(handler: &dyn Handler, request: &Request) -> Response {
+    let mut response = handler.handle(request);
+    if !request.keep_alive() {
+        response.set_header("Connection", "close");
+    }
+    response
+}
+
+pub fn bad_request(err: impl fmt::Display) -> Response {
+    Response::text(400, err.to_string()).with_header("Connection", "close")
+}
+
+pub fn request_timeout() -> Response {
+    Response::text(408, "Request Timeout").with_header("Connection", "close")
+}
+
+pub fn payload_too_large() -> Response {
+    Response::text(413, "Payload Too Large").with_header("Connection", "close")
+}
+
+pub fn not_implemented() -> Response {
+    Response::text(501, "Not Implemented").with_header("Connection", "close")
+}
+
+pub fn too_many_connections() -> Response {
+    Response::text(429, "Too Many Requests")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close")
+}
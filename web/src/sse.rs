@@ -0,0 +1,103 @@
+use crate::http::{BodySender, Request, Response};
+use serde::Serialize;
+use std::io;
+use std::time::Duration;
+
+// server-sent events 的一条消息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    // data 是 value 的 JSON
+    pub fn json<T: Serialize>(value: &T) -> serde_json::Result<Event> {
+        serde_json::to_string(value).map(Event::new)
+    }
+
+    // 浏览器重连时会在 Last-Event-ID 头里带上最后收到的 id
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    // 事件名，前端用 addEventListener(name) 接收，没有时触发 onmessage
+    pub fn event(mut self, name: impl Into<String>) -> Event {
+        self.event = Some(name.into());
+        self
+    }
+
+    // 让浏览器断线后等这么久再重连
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    // 按 text/event-stream 格式编码，多行的 data 拆成多个 data: 行
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // id 和 event 里不能有换行，否则会被当成别的字段
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        let mut out = String::new();
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\n', '\r']) {
+            out.push_str(&format!("data: {line}\n"));
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+// 发送事件的一端，可以交给后台线程；全部 drop 后响应结束
+#[derive(Debug, Clone)]
+pub struct EventSender(BodySender);
+
+impl EventSender {
+    // 客户端断开后返回 BrokenPipe，发送方应该停下来
+    pub fn send(&self, event: &Event) -> io::Result<()> {
+        self.0.send(event.to_bytes())
+    }
+
+    // 注释行，浏览器会忽略，长时间没有事件时用来保活
+    pub fn comment(&self, text: &str) -> io::Result<()> {
+        let text = text.replace(['\r', '\n'], " ");
+        self.0.send(format!(": {text}\n\n"))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+// text/event-stream 响应，handler 返回 Response，用 EventSender 推送事件
+pub fn channel() -> (Response, EventSender) {
+    let (response, sender) = Response::channel(200);
+    let response = response
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        // 让前面的 nginx 之类的代理不要缓冲
+        .with_header("X-Accel-Buffering", "no");
+    (response, EventSender(sender))
+}
+
+// 浏览器重连时带上的最后一个事件 id
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID")
+}
@@ -0,0 +1,88 @@
+use crate::compression::{Encoding, negotiate};
+use crate::file_cache::FileCache;
+use crate::http::{Body, Request, Response};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// 按扩展名猜 Content-Type
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "application/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+// 把请求路径映射到文档根目录下的文件，含 .. 或者隐藏文件的路径、目录和不存在的文件返回 None
+pub fn resolve(doc_root: &Path, request_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(request_path.trim_start_matches('/'));
+    let mut path = doc_root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) if !name.to_string_lossy().starts_with('.') => path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    path.is_file().then_some(path)
+}
+
+// 预压缩文件的路径：hello.html -> hello.html.gz
+pub fn gzip_sibling(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+// 读静态文件生成响应；旁边有预压缩的 .gz 文件并且客户端接受 gzip 时直接发送它，不用再压缩一遍
+pub fn serve_file(request: &Request, status: u16, path: &Path) -> io::Result<Response> {
+    serve_file_cached(request, status, path, None)
+}
+
+// serve_file 的带缓存版本
+pub fn serve_file_cached(
+    request: &Request,
+    status: u16,
+    path: &Path,
+    cache: Option<&FileCache>,
+) -> io::Result<Response> {
+    let read = |path: &Path| match cache {
+        Some(cache) => cache.read(path).map(Body::from),
+        None => fs::read(path).map(Body::from),
+    };
+    let content_type = content_type(path);
+    let sibling = gzip_sibling(path);
+    if !sibling.is_file() {
+        return Ok(Response::new(status)
+            .with_header("Content-Type", content_type)
+            .with_body(read(path)?));
+    }
+
+    let accept_encoding = request.header("Accept-Encoding");
+    let mut response = if negotiate(accept_encoding, &[Encoding::Gzip]) == Encoding::Gzip {
+        Response::new(status)
+            .with_header("Content-Encoding", Encoding::Gzip.as_str())
+            .with_body(read(&sibling)?)
+    } else {
+        Response::new(status).with_body(read(path)?)
+    };
+    response.set_header("Content-Type", content_type);
+    response.add_vary("Accept-Encoding");
+    Ok(response)
+}
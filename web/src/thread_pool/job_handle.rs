@@ -0,0 +1,303 @@
+use super::{ExecuteError, Job, ThreadPool, recover};
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    // 任务执行时 panic
+    Panicked,
+    // 开始执行前被 cancel
+    Cancelled,
+    // 任务没有执行就被丢掉了，比如 DropOldest 策略挤掉了它
+    Dropped,
+    // join_timeout 超时，任务仍在后台继续执行
+    TimedOut,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked => write!(f, "job panicked"),
+            JobError::Cancelled => write!(f, "job was cancelled"),
+            JobError::Dropped => write!(f, "job was dropped before running"),
+            JobError::TimedOut => write!(f, "timed out waiting for job"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+struct Slot<T> {
+    started: bool,
+    cancelled: bool,
+    result: Option<Result<T, JobError>>,
+    waker: Option<Waker>,
+}
+
+struct JobState<T> {
+    slot: Mutex<Slot<T>>,
+    done: Condvar,
+}
+
+impl<T> JobState<T> {
+    fn lock(&self) -> MutexGuard<'_, Slot<T>> {
+        recover(self.slot.lock())
+    }
+
+    fn finish(&self, result: Result<T, JobError>) {
+        let mut slot = self.lock();
+        if slot.result.is_none() {
+            slot.result = Some(result);
+        }
+        let waker = slot.waker.take();
+        drop(slot);
+
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// 跟着任务闭包一起走，闭包没执行就被丢掉时也能通知到 JobHandle
+struct Completer<T> {
+    state: Arc<JobState<T>>,
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.state.finish(Err(JobError::Dropped));
+    }
+}
+
+// spawn 返回的任务句柄，drop 掉句柄不会影响任务执行
+pub struct JobHandle<T> {
+    state: Arc<JobState<T>>,
+}
+
+impl<T> JobHandle<T> {
+    // 阻塞等待任务结束并取回结果
+    pub fn join(self) -> Result<T, JobError> {
+        let slot = self.state.lock();
+        let mut slot = recover(
+            self.state
+                .done
+                .wait_while(slot, |slot| slot.result.is_none()),
+        );
+        slot.result.take().unwrap()
+    }
+
+    // 最多等 timeout，超时返回 JobError::TimedOut
+    pub fn join_timeout(self, timeout: Duration) -> Result<T, JobError> {
+        if !self.wait_timeout(timeout) {
+            return Err(JobError::TimedOut);
+        }
+        self.join()
+    }
+
+    // 等待任务结束，但不取结果；返回任务是否已经结束
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let slot = self.state.lock();
+        let (slot, _) = recover(
+            self.state
+                .done
+                .wait_timeout_while(slot, timeout, |slot| slot.result.is_none()),
+        );
+        slot.result.is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().result.is_some()
+    }
+
+    // 任务还在排队时取消它，已经开始执行的任务无法取消
+    pub fn cancel(&self) -> bool {
+        let mut slot = self.state.lock();
+        if slot.started || slot.result.is_some() {
+            return false;
+        }
+        slot.cancelled = true;
+        drop(slot);
+
+        self.state.finish(Err(JobError::Cancelled));
+        true
+    }
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.state.lock();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    // 和 execute 一样提交任务，但可以通过 JobHandle 拿到返回值
+    pub fn spawn<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let state = Arc::new(JobState {
+            slot: Mutex::new(Slot {
+                started: false,
+                cancelled: false,
+                result: None,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        });
+
+        let completer = Completer {
+            state: Arc::clone(&state),
+        };
+        self.submit(Box::new(move || {
+            {
+                let mut slot = completer.state.lock();
+                if slot.cancelled {
+                    return;
+                }
+                slot.started = true;
+            }
+
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(value) => completer.state.finish(Ok(value)),
+                Err(payload) => {
+                    completer.state.finish(Err(JobError::Panicked));
+                    // 继续往外抛，让 worker 记到 panicked_jobs 里
+                    panic::resume_unwind(payload);
+                }
+            }
+        }))?;
+
+        Ok(JobHandle { state })
+    }
+
+    // 类似 std::thread::scope，scope 内提交的任务可以借用外部变量，
+    // scope 返回前会等所有任务结束；有任务 panic 时 scope 也会 panic
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                all_done: Condvar::new(),
+                panicked: AtomicBool::new(false),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait_all();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.state.panicked.load(Ordering::SeqCst) => {
+                panic!("a scoped job panicked")
+            }
+            Ok(value) => value,
+        }
+    }
+}
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    all_done: Condvar,
+    panicked: AtomicBool,
+}
+
+impl ScopeState {
+    fn wait_all(&self) {
+        let pending = recover(self.pending.lock());
+        let _pending = recover(self.all_done.wait_while(pending, |pending| *pending > 0));
+    }
+}
+
+// 任务结束或被丢掉时减少计数
+struct ScopeGuard {
+    state: Arc<ScopeState>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        let mut pending = recover(self.state.pending.lock());
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.all_done.notify_all();
+        }
+    }
+}
+
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+// scope 里提交的任务。scope 靠 guard 的计数判断任务借用的数据还有没有人用，
+// 所以不管任务是执行完还是没执行就被丢掉（DropOldest 挤掉、Reject 时提交失败），
+// 都必须先丢掉 f（连同它捕获的借用），再丢掉 guard
+struct ScopedJob<F> {
+    f: Option<F>,
+    guard: ScopeGuard,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(mut self) {
+        let f = self.f.take().expect("scoped job runs once");
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            self.guard.state.panicked.store(true, Ordering::SeqCst);
+            drop(self);
+            // 和 execute 一样继续往外抛，让 worker 记到 panicked_jobs 里
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl<F> Drop for ScopedJob<F> {
+    fn drop(&mut self) {
+        // 字段按声明顺序析构，这里显式先丢掉 f，不依赖这一点
+        drop(self.f.take());
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    pub fn spawn<F>(&'scope self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *recover(self.state.pending.lock()) += 1;
+        let job = ScopedJob {
+            f: Some(f),
+            guard: ScopeGuard {
+                state: Arc::clone(&self.state),
+            },
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+
+        // scope 返回前会等待所有任务（包括被丢掉的任务）的 guard 析构，而 ScopedJob 保证
+        // f 先于 guard 析构，所以任务借用的数据一定活得比任务久，可以把生命周期放宽到 'static
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.submit(job)
+    }
+}
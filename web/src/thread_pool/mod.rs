@@ -0,0 +1,478 @@
+use std::fmt;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+mod job_handle;
+mod queue;
+mod stats;
+mod stealing;
+
+pub use job_handle::{JobError, JobHandle, Scope};
+use queue::{JobQueue, PushError, SharedQueue, Take};
+use stats::Metrics;
+pub use stats::{LatencyHistogram, PoolStats};
+use stealing::StealingQueue;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// 队列满时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectPolicy {
+    // 阻塞调用方，直到队列有空位
+    Block,
+    // 直接拒绝，交给调用方处理（比如返回 503）
+    Reject,
+    // 丢掉队列里最早的任务，腾出位置
+    DropOldest,
+    // 在调用方线程上直接执行
+    CallerRuns,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    // 队列已满，且策略为 Reject
+    Full,
+    // 线程池已经关闭
+    ShutDown,
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full => write!(f, "thread pool queue is full"),
+            ExecuteError::ShutDown => write!(f, "thread pool is shut down"),
+        }
+    }
+}
+
+impl std::error::Error for ExecuteError {}
+
+// worker 取任务的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    // 所有 worker 共用一个队列
+    SharedQueue,
+    // 每个 worker 一个队列，空闲时去别人的队列里偷任务
+    WorkStealing,
+}
+
+// worker 取任务的结果
+enum Next {
+    Job(Job),
+    // 空闲超过 keep_alive，且线程数多于 core_size
+    Retire,
+    // 线程池关闭
+    Exit,
+}
+
+// 所有 worker 共享的状态
+struct Shared {
+    queue: Box<dyn JobQueue>,
+    core_size: usize,
+    max_size: usize,
+    keep_alive: Duration,
+    thread_name: String,
+    stack_size: Option<usize>,
+    workers: Mutex<Vec<Worker>>,
+    next_worker_id: AtomicUsize,
+    live_workers: AtomicUsize,
+    metrics: Metrics,
+}
+
+// 锁被 panic 的线程毒化后，队列本身的数据仍然是完整的，直接拿回来继续用
+fn recover<T>(result: Result<T, PoisonError<T>>) -> T {
+    result.unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Shared {
+    fn lock_workers(&self) -> MutexGuard<'_, Vec<Worker>> {
+        recover(self.workers.lock())
+    }
+
+    // 取出一个任务，队列为空时阻塞，每空等 keep_alive 检查一次是否该退出
+    fn next_job(&self, worker_id: usize) -> Next {
+        loop {
+            match self.queue.take(worker_id, self.keep_alive) {
+                Take::Job(job) => return Next::Job(job),
+                Take::Closed => return Next::Exit,
+                Take::Idle if self.try_shrink() => return Next::Retire,
+                Take::Idle => {}
+            }
+        }
+    }
+
+    // 当前状态快照，各项分别读取，彼此之间不保证严格一致
+    fn stats(&self) -> PoolStats {
+        let workers = self.live_workers.load(Ordering::SeqCst);
+        let idle_workers = self.queue.idle().min(workers);
+
+        PoolStats {
+            workers,
+            active_workers: workers - idle_workers,
+            idle_workers,
+            queued_jobs: self.queue.len(),
+            completed_jobs: self.metrics.completed_jobs.load(Ordering::Relaxed),
+            failed_jobs: self.metrics.panicked_jobs.load(Ordering::Relaxed),
+            respawned_workers: self.metrics.respawned_workers.load(Ordering::Relaxed),
+            latency: self.metrics.latency(),
+        }
+    }
+
+    // 线程数多于 core_size 时占用一个退出名额
+    fn try_shrink(&self) -> bool {
+        self.live_workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live > self.core_size).then(|| live - 1)
+            })
+            .is_ok()
+    }
+
+    // 线程数少于 max_size 时占用一个新增名额
+    fn try_grow(&self) -> bool {
+        self.live_workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live < self.max_size).then(|| live + 1)
+            })
+            .is_ok()
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    // 启动 worker 线程并登记到共享的 workers 列表里，调用前需要先占好 live_workers 名额
+    fn spawn(id: usize, shared: &Arc<Shared>) -> io::Result<()> {
+        let mut builder = thread::Builder::new().name(format!("{}-{id}", shared.thread_name));
+        if let Some(stack_size) = shared.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let worker_shared = Arc::clone(shared);
+        let thread = builder.spawn(move || {
+            let mut sentinel = Sentinel {
+                id,
+                shared: &worker_shared,
+                counted: true,
+            };
+            loop {
+                match sentinel.shared.next_job(id) {
+                    Next::Job(job) => {
+                        debug!(worker = id, "got a job; executing");
+                        let started = Instant::now();
+                        let panicked = panic::catch_unwind(AssertUnwindSafe(job)).is_err();
+                        sentinel
+                            .shared
+                            .metrics
+                            .record_job(started.elapsed(), panicked);
+                        if panicked {
+                            warn!(worker = id, "job panicked; continue");
+                        }
+                    }
+                    Next::Retire => {
+                        debug!(worker = id, "idle too long; retiring");
+                        sentinel.counted = false;
+                        break;
+                    }
+                    Next::Exit => {
+                        debug!(worker = id, "disconnected; shutting down");
+                        break;
+                    }
+                }
+            }
+        })?;
+
+        let mut workers = shared.lock_workers();
+        // 顺手清掉已经退出的线程句柄
+        workers.retain(|worker| worker.thread.as_ref().is_some_and(|t| !t.is_finished()));
+        workers.push(Worker {
+            id,
+            thread: Some(thread),
+        });
+        Ok(())
+    }
+}
+
+// 任务 panic 已经被 catch_unwind 接住，这里兜底 worker 自身意外退出的情况：
+// 线程因 panic 展开时补一个同 id 的 worker，保证线程数不会越来越少
+struct Sentinel<'a> {
+    id: usize,
+    shared: &'a Arc<Shared>,
+    // 退休时已经在 try_shrink 里扣过名额
+    counted: bool,
+}
+
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        if !self.counted {
+            return;
+        }
+        if thread::panicking() {
+            error!(worker = self.id, "worker thread died; respawning");
+            self.shared
+                .metrics
+                .respawned_workers
+                .fetch_add(1, Ordering::SeqCst);
+            if Worker::spawn(self.id, self.shared).is_ok() {
+                return;
+            }
+        }
+        self.shared.live_workers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct ThreadPoolBuilder {
+    core_size: usize,
+    max_size: Option<usize>,
+    keep_alive: Duration,
+    capacity: Option<usize>,
+    policy: RejectPolicy,
+    scheduler: Scheduler,
+    thread_name: String,
+    stack_size: Option<usize>,
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            core_size: 4,
+            max_size: None,
+            keep_alive: Duration::from_secs(60),
+            capacity: None,
+            policy: RejectPolicy::Block,
+            scheduler: Scheduler::SharedQueue,
+            thread_name: String::from("web-worker"),
+            stack_size: None,
+        }
+    }
+
+    // 常驻线程数
+    pub fn core_size(mut self, core_size: usize) -> ThreadPoolBuilder {
+        self.core_size = core_size;
+        self
+    }
+
+    // 队列积压时最多扩到的线程数，默认等于 core_size，即不扩容
+    pub fn max_size(mut self, max_size: usize) -> ThreadPoolBuilder {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    // 超出 core_size 的线程空闲多久后退出
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    // 队列最多缓存的任务数，满了以后按 reject_policy 处理
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn reject_policy(mut self, policy: RejectPolicy) -> ThreadPoolBuilder {
+        self.policy = policy;
+        self
+    }
+
+    pub fn scheduler(mut self, scheduler: Scheduler) -> ThreadPoolBuilder {
+        self.scheduler = scheduler;
+        self
+    }
+
+    // 线程名前缀，实际名字为 `{prefix}-{id}`
+    pub fn thread_name(mut self, prefix: &str) -> ThreadPoolBuilder {
+        self.thread_name = prefix.to_string();
+        self
+    }
+
+    pub fn stack_size(mut self, bytes: usize) -> ThreadPoolBuilder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> ThreadPool {
+        let max_size = self.max_size.unwrap_or(self.core_size);
+        assert!(self.core_size > 0);
+        assert!(max_size >= self.core_size);
+        assert!(self.capacity != Some(0));
+
+        let queue: Box<dyn JobQueue> = match self.scheduler {
+            Scheduler::SharedQueue => Box::new(SharedQueue::new(self.capacity)),
+            Scheduler::WorkStealing => Box::new(StealingQueue::new(max_size, self.capacity)),
+        };
+
+        let shared = Arc::new(Shared {
+            queue,
+            core_size: self.core_size,
+            max_size,
+            keep_alive: self.keep_alive,
+            thread_name: self.thread_name,
+            stack_size: self.stack_size,
+            workers: Mutex::new(Vec::with_capacity(max_size)),
+            next_worker_id: AtomicUsize::new(self.core_size),
+            live_workers: AtomicUsize::new(self.core_size),
+            metrics: Metrics::new(),
+        });
+
+        for id in 0..self.core_size {
+            Worker::spawn(id, &shared).expect("failed to spawn worker thread");
+        }
+
+        ThreadPool {
+            shared,
+            policy: self.policy,
+        }
+    }
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        ThreadPoolBuilder::new()
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    policy: RejectPolicy,
+}
+
+impl ThreadPool {
+    // 固定 size 个线程，不限队列长度
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::builder().core_size(size).build()
+    }
+
+    // 固定 size 个线程，队列最多缓存 capacity 个任务，满了以后按 policy 处理
+    pub fn bounded(size: usize, capacity: usize, policy: RejectPolicy) -> ThreadPool {
+        ThreadPool::builder()
+            .core_size(size)
+            .queue_capacity(capacity)
+            .reject_policy(policy)
+            .build()
+    }
+
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(Box::new(f))
+    }
+
+    fn submit(&self, mut job: Job) -> Result<(), ExecuteError> {
+        let queue = &self.shared.queue;
+
+        loop {
+            job = match queue.try_push(job) {
+                Ok(()) => break,
+                Err(PushError::Closed) => return Err(ExecuteError::ShutDown),
+                Err(PushError::Full(job)) => job,
+            };
+
+            // 先尝试扩容，扩不动了再走拒绝策略
+            if self.shared.try_grow() {
+                self.spawn_extra_worker();
+                continue;
+            }
+            match self.policy {
+                RejectPolicy::Block => queue.wait_not_full(),
+                RejectPolicy::Reject => return Err(ExecuteError::Full),
+                RejectPolicy::DropOldest => drop(queue.drop_oldest()),
+                RejectPolicy::CallerRuns => {
+                    job();
+                    return Ok(());
+                }
+            }
+        }
+
+        // 排队的任务比空闲的 worker 多，说明队列开始积压
+        let backlog = queue.len() > queue.idle();
+        if backlog && self.shared.try_grow() {
+            self.spawn_extra_worker();
+        }
+        Ok(())
+    }
+
+    fn spawn_extra_worker(&self) {
+        let id = self.shared.next_worker_id.fetch_add(1, Ordering::SeqCst);
+        if Worker::spawn(id, &self.shared).is_err() {
+            self.shared.live_workers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // 当前排队中的任务数
+    pub fn queued(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    // 执行时 panic 的任务数
+    pub fn panicked_jobs(&self) -> u64 {
+        self.shared.metrics.panicked_jobs.load(Ordering::SeqCst)
+    }
+
+    // 意外退出后被重新拉起的 worker 数
+    pub fn respawned_workers(&self) -> u64 {
+        self.shared.metrics.respawned_workers.load(Ordering::SeqCst)
+    }
+
+    // 当前存活的 worker 数
+    pub fn worker_count(&self) -> usize {
+        self.shared.live_workers.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+
+    // 可以交给任务或其他线程持有的状态读取句柄，不影响线程池的关闭
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StatsHandle {
+    shared: Arc<Shared>,
+}
+
+impl StatsHandle {
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.queue.close();
+
+        // join 期间可能有 worker 被重新拉起，取空为止
+        loop {
+            let workers = mem::take(&mut *self.shared.lock_workers());
+            if workers.is_empty() {
+                break;
+            }
+
+            for mut worker in workers {
+                info!(worker = worker.id, "shutting down worker");
+
+                if let Some(thread) = worker.thread.take() {
+                    let _ = thread.join();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::{Job, recover};
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+pub(super) enum PushError {
+    Full(Job),
+    Closed,
+}
+
+// worker 从队列取任务的结果
+pub(super) enum Take {
+    Job(Job),
+    // 空等了 keep_alive 也没有任务
+    Idle,
+    // 队列已关闭且任务已取完
+    Closed,
+}
+
+// 两种调度方式共用的队列接口，capacity 为 None 时不限长度
+pub(super) trait JobQueue: Send + Sync {
+    fn try_push(&self, job: Job) -> Result<(), PushError>;
+
+    // 队列满时阻塞到有空位或关闭，可能提前返回，调用方需要重试
+    fn wait_not_full(&self);
+
+    // 丢掉一个最早入队的任务
+    fn drop_oldest(&self) -> Option<Job>;
+
+    fn take(&self, worker_id: usize, keep_alive: Duration) -> Take;
+
+    fn len(&self) -> usize;
+
+    // 正在等任务的 worker 数
+    fn idle(&self) -> usize;
+
+    fn close(&self);
+}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    idle: usize,
+    closed: bool,
+}
+
+// 所有 worker 抢同一把锁的共享队列
+pub(super) struct SharedQueue {
+    queue: Mutex<Queue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+}
+
+impl SharedQueue {
+    pub(super) fn new(capacity: Option<usize>) -> SharedQueue {
+        SharedQueue {
+            queue: Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                idle: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        recover(self.queue.lock())
+    }
+
+    fn is_full(&self, queue: &Queue) -> bool {
+        self.capacity
+            .is_some_and(|capacity| queue.jobs.len() >= capacity)
+    }
+}
+
+impl JobQueue for SharedQueue {
+    fn try_push(&self, job: Job) -> Result<(), PushError> {
+        let mut queue = self.lock();
+        if queue.closed {
+            return Err(PushError::Closed);
+        }
+        if self.is_full(&queue) {
+            return Err(PushError::Full(job));
+        }
+        queue.jobs.push_back(job);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn wait_not_full(&self) {
+        let queue = self.lock();
+        let _queue = recover(
+            self.not_full
+                .wait_while(queue, |queue| !queue.closed && self.is_full(queue)),
+        );
+    }
+
+    fn drop_oldest(&self) -> Option<Job> {
+        self.lock().jobs.pop_front()
+    }
+
+    fn take(&self, _worker_id: usize, keep_alive: Duration) -> Take {
+        let mut queue = self.lock();
+        loop {
+            if let Some(job) = queue.jobs.pop_front() {
+                self.not_full.notify_one();
+                return Take::Job(job);
+            }
+            if queue.closed {
+                return Take::Closed;
+            }
+
+            queue.idle += 1;
+            let (guard, wait) = recover(self.not_empty.wait_timeout(queue, keep_alive));
+            queue = guard;
+            queue.idle -= 1;
+
+            if wait.timed_out() && queue.jobs.is_empty() && !queue.closed {
+                return Take::Idle;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lock().jobs.len()
+    }
+
+    fn idle(&self) -> usize {
+        self.lock().idle
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
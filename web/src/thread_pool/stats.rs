@@ -0,0 +1,137 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// 任务耗时直方图的桶上限，最后还有一个不设上限的桶
+const LATENCY_BOUNDS_MS: [u64; 10] = [1, 5, 10, 25, 50, 100, 250, 500, 1_000, 5_000];
+
+// 线程池内部的计数器，worker 线程直接原子累加
+pub(super) struct Metrics {
+    pub(super) completed_jobs: AtomicU64,
+    pub(super) panicked_jobs: AtomicU64,
+    pub(super) respawned_workers: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BOUNDS_MS.len() + 1],
+    latency_total_us: AtomicU64,
+}
+
+impl Metrics {
+    pub(super) fn new() -> Metrics {
+        Metrics {
+            completed_jobs: AtomicU64::new(0),
+            panicked_jobs: AtomicU64::new(0),
+            respawned_workers: AtomicU64::new(0),
+            latency_buckets: Default::default(),
+            latency_total_us: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn record_job(&self, elapsed: Duration, panicked: bool) {
+        if panicked {
+            self.panicked_jobs.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.completed_jobs.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let elapsed_ms = elapsed.as_millis() as u64;
+        let bucket = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|&bound| elapsed_ms < bound)
+            .unwrap_or(LATENCY_BOUNDS_MS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_total_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn latency(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            counts: self
+                .latency_buckets
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            total: Duration::from_micros(self.latency_total_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// 任务执行耗时分布，按固定的桶统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    // 每个桶的 (上限, 任务数)，上限为 None 的是最后一个桶
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let bound = LATENCY_BOUNDS_MS
+                    .get(i)
+                    .map(|&ms| Duration::from_millis(ms));
+                (bound, count)
+            })
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| self.total.div_f64(count as f64))
+    }
+
+    // 近似分位数，返回对应桶的上限，落在最后一个桶时返回 None
+    pub fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let target = ((count as f64) * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bound, bucket) in self.buckets() {
+            seen += bucket;
+            if seen >= target {
+                return bound;
+            }
+        }
+        None
+    }
+}
+
+// 线程池某一时刻的状态快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    pub workers: usize,
+    pub active_workers: usize,
+    pub idle_workers: usize,
+    pub queued_jobs: usize,
+    pub completed_jobs: u64,
+    pub failed_jobs: u64,
+    pub respawned_workers: u64,
+    pub latency: LatencyHistogram,
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "workers: {}", self.workers)?;
+        writeln!(f, "active_workers: {}", self.active_workers)?;
+        writeln!(f, "idle_workers: {}", self.idle_workers)?;
+        writeln!(f, "queued_jobs: {}", self.queued_jobs)?;
+        writeln!(f, "completed_jobs: {}", self.completed_jobs)?;
+        writeln!(f, "failed_jobs: {}", self.failed_jobs)?;
+        writeln!(f, "respawned_workers: {}", self.respawned_workers)?;
+        for (bound, count) in self.latency.buckets() {
+            match bound {
+                Some(bound) => writeln!(f, "latency_lt_{}ms: {count}", bound.as_millis())?,
+                None => writeln!(f, "latency_lt_inf: {count}")?,
+            }
+        }
+        Ok(())
+    }
+}
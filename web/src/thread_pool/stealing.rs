@@ -0,0 +1,175 @@
+use super::queue::{JobQueue, PushError, Take};
+use super::{Job, recover};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+// 每个 worker 一条自己的队列，新任务轮流分到各条队列上，
+// worker 先取自己队列里的任务，取空了再去别的队列里偷，
+// 取任务时只锁一条队列，不会所有 worker 抢同一把锁
+pub(super) struct StealingQueue {
+    // 任务连同入队序号一起放，DropOldest 按序号找全局最早的任务
+    deques: Vec<Mutex<VecDeque<(usize, Job)>>>,
+    // 下一个任务的序号，同时决定放到哪条队列
+    next_deque: AtomicUsize,
+    capacity: Option<usize>,
+    // 已经占了名额（可能还没放进队列）但还没被取走的任务数
+    pending: AtomicUsize,
+    closed: AtomicBool,
+    // 只在没有任务可取、需要睡眠时才用到的锁
+    park: Mutex<()>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    sleepers: AtomicUsize,
+    blocked: AtomicUsize,
+}
+
+impl StealingQueue {
+    pub(super) fn new(deques: usize, capacity: Option<usize>) -> StealingQueue {
+        StealingQueue {
+            deques: (0..deques).map(|_| Mutex::new(VecDeque::new())).collect(),
+            next_deque: AtomicUsize::new(0),
+            capacity,
+            pending: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            park: Mutex::new(()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+        }
+    }
+
+    fn deque(&self, index: usize) -> MutexGuard<'_, VecDeque<(usize, Job)>> {
+        recover(self.deques[index % self.deques.len()].lock())
+    }
+
+    fn park(&self) -> MutexGuard<'_, ()> {
+        recover(self.park.lock())
+    }
+
+    // 先取自己的队列，再从后面的队列依次偷
+    fn find_job(&self, home: usize) -> Option<Job> {
+        (0..self.deques.len())
+            .find_map(|offset| self.deque(home + offset).pop_front())
+            .map(|(_, job)| job)
+    }
+
+    fn reserve(&self) -> bool {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                match self.capacity {
+                    Some(capacity) if pending >= capacity => None,
+                    _ => Some(pending + 1),
+                }
+            })
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _park = self.park();
+            self.not_full.notify_all();
+        }
+    }
+}
+
+impl JobQueue for StealingQueue {
+    fn try_push(&self, job: Job) -> Result<(), PushError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(PushError::Closed);
+        }
+        if !self.reserve() {
+            return Err(PushError::Full(job));
+        }
+
+        let index = self.next_deque.fetch_add(1, Ordering::Relaxed);
+        self.deque(index).push_back((index, job));
+
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _park = self.park();
+            self.not_empty.notify_one();
+        }
+        Ok(())
+    }
+
+    fn wait_not_full(&self) {
+        let park = self.park();
+        self.blocked.fetch_add(1, Ordering::SeqCst);
+        let still_full = match self.capacity {
+            Some(capacity) => self.pending.load(Ordering::SeqCst) >= capacity,
+            None => false,
+        };
+        if still_full && !self.closed.load(Ordering::SeqCst) {
+            let _park = recover(self.not_full.wait(park));
+        }
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // 每条队列的队首是这条队列里最早的，比较各条队首的序号找全局最早的；
+    // 找到之后被 worker 抢先取走了就重新找
+    fn drop_oldest(&self) -> Option<Job> {
+        loop {
+            let (oldest, index) = (0..self.deques.len())
+                .filter_map(|index| self.deque(index).front().map(|(seq, _)| (*seq, index)))
+                .min()?;
+            let mut deque = self.deque(index);
+            if deque.front().is_some_and(|(seq, _)| *seq == oldest) {
+                let (_, job) = deque.pop_front().unwrap();
+                drop(deque);
+                self.release();
+                return Some(job);
+            }
+        }
+    }
+
+    fn take(&self, worker_id: usize, keep_alive: Duration) -> Take {
+        loop {
+            if let Some(job) = self.find_job(worker_id) {
+                self.release();
+                return Take::Job(job);
+            }
+
+            // 名额已占但任务还没放进队列，稍等一下再取
+            if self.pending.load(Ordering::SeqCst) > 0 {
+                thread::yield_now();
+                continue;
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return Take::Closed;
+            }
+
+            // 先登记为睡眠再检查一次，避免和 try_push 的唤醒错过
+            let park = self.park();
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            if self.pending.load(Ordering::SeqCst) > 0 || self.closed.load(Ordering::SeqCst) {
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            let (_park, wait) = recover(self.not_empty.wait_timeout(park, keep_alive));
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+
+            if wait.timed_out() && self.pending.load(Ordering::SeqCst) == 0 {
+                return Take::Idle;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    fn idle(&self) -> usize {
+        self.sleepers.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _park = self.park();
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
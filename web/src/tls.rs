@@ -0,0 +1,146 @@
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub use rustls::ServerConfig;
+
+// 默认通过 ALPN 协商的协议，服务端只实现了 HTTP/1.1
+pub const DEFAULT_ALPN: &[&[u8]] = &[b"http/1.1"];
+
+// 构造 HTTPS 用的 rustls 配置：一个默认证书，可以再按 SNI 的主机名配置别的证书
+pub struct TlsConfigBuilder {
+    default: Option<(PathBuf, PathBuf)>,
+    hosts: Vec<(String, PathBuf, PathBuf)>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsConfigBuilder {
+    pub fn new() -> TlsConfigBuilder {
+        TlsConfigBuilder {
+            default: None,
+            hosts: Vec::new(),
+            alpn_protocols: DEFAULT_ALPN
+                .iter()
+                .map(|protocol| protocol.to_vec())
+                .collect(),
+        }
+    }
+
+    // 客户端没带 SNI 或者主机名没有单独配置时使用的证书
+    pub fn cert(mut self, cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Self {
+        self.default = Some((cert_path.as_ref().into(), key_path.as_ref().into()));
+        self
+    }
+
+    // 按主机名选证书，支持 *.example.com 形式的通配
+    pub fn sni_cert(
+        mut self,
+        host: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Self {
+        self.hosts.push((
+            host.to_ascii_lowercase(),
+            cert_path.as_ref().into(),
+            key_path.as_ref().into(),
+        ));
+        self
+    }
+
+    pub fn alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    pub fn build(self) -> io::Result<Arc<ServerConfig>> {
+        let provider = Arc::new(ring::default_provider());
+        let mut resolver = SniResolver {
+            default: None,
+            hosts: HashMap::new(),
+        };
+        if let Some((cert_path, key_path)) = &self.default {
+            resolver.default = Some(load_certified_key(&provider, cert_path, key_path)?);
+        }
+        for (host, cert_path, key_path) in &self.hosts {
+            let key = load_certified_key(&provider, cert_path, key_path)?;
+            resolver.hosts.insert(host.clone(), key);
+        }
+        if resolver.default.is_none() && resolver.hosts.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no certificate configured",
+            ));
+        }
+
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.alpn_protocols;
+        Ok(Arc::new(config))
+    }
+}
+
+impl Default for TlsConfigBuilder {
+    fn default() -> Self {
+        TlsConfigBuilder::new()
+    }
+}
+
+// 从 PEM 文件加载证书链和私钥
+fn load_certified_key(
+    provider: &CryptoProvider,
+    cert_path: &Path,
+    key_path: &Path,
+) -> io::Result<Arc<CertifiedKey>> {
+    let invalid = |path: &Path, err: rustls::pki_types::pem::Error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {err}", path.display()),
+        )
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|err| invalid(cert_path, err))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(cert_path, err))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no certificate found", cert_path.display()),
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| invalid(key_path, err))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+// 按客户端 SNI 里的主机名选证书，先精确匹配，再匹配通配证书，最后用默认证书
+#[derive(Debug)]
+struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let found = client_hello.server_name().and_then(|name| {
+            let name = name.to_ascii_lowercase();
+            self.hosts.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                self.hosts.get(&format!("*.{parent}"))
+            })
+        });
+        found.or(self.default.as_ref()).cloned()
+    }
+}
@@ -0,0 +1,89 @@
+use crate::async_server::AsyncHandler;
+use crate::http::{Handler, Request, Response};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// 基于名字的虚拟主机：按 Host 头把请求交给对应站点的 handler，
+// 先精确匹配，再匹配 *.example.com 这样的通配名（最长的优先），都不匹配时交给默认站点。
+// H 是 dyn Handler 或 dyn AsyncHandler
+pub struct VirtualHosts<H: ?Sized> {
+    exact: HashMap<String, Arc<H>>,
+    // (".example.com", handler)，按后缀长度从长到短排列
+    wildcards: Vec<(String, Arc<H>)>,
+    default: Arc<H>,
+}
+
+impl<H: ?Sized> VirtualHosts<H> {
+    pub fn new(default: Arc<H>) -> VirtualHosts<H> {
+        VirtualHosts {
+            exact: HashMap::new(),
+            wildcards: Vec::new(),
+            default,
+        }
+    }
+
+    // name 可以是 example.com 或者 *.example.com，后者匹配任意层级的子域名，不匹配 example.com 本身
+    pub fn host(mut self, name: &str, handler: Arc<H>) -> VirtualHosts<H> {
+        let name = normalize(name);
+        match name.strip_prefix('*') {
+            Some(suffix) => {
+                self.wildcards.retain(|(existing, _)| existing != suffix);
+                self.wildcards.push((suffix.to_string(), handler));
+                self.wildcards
+                    .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+            }
+            None => {
+                self.exact.insert(name, handler);
+            }
+        }
+        self
+    }
+
+    // 按 Host 头（可以带端口）选站点
+    pub fn select(&self, host: Option<&str>) -> &Arc<H> {
+        let Some(host) = host.map(host_name) else {
+            return &self.default;
+        };
+        if let Some(handler) = self.exact.get(&host) {
+            return handler;
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+            .map_or(&self.default, |(_, handler)| handler)
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+// 去掉 Host 头里的端口：example.com:8080 -> example.com，[::1]:8080 -> [::1]
+pub fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') && !host[..index].contains(':') => {
+            &host[..index]
+        }
+        Some(index) if host.starts_with('[') && host[..index].ends_with(']') => &host[..index],
+        _ => host,
+    };
+    normalize(name)
+}
+
+impl Handler for VirtualHosts<dyn Handler> {
+    fn handle(&self, request: &Request) -> Response {
+        self.select(request.header("Host")).handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.select(request.header("Host")).streams_body(request)
+    }
+}
+
+impl AsyncHandler for VirtualHosts<dyn AsyncHandler> {
+    fn handle(&self, request: Request) -> BoxFuture<'static, Response> {
+        self.select(request.header("Host")).handle(request)
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::{Local, TimeZone};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::SubscriberExt;
+use web::access_log::{ACCESS_TARGET, AccessEntry, LogFormat};
+use web::http::{Request, Response};
+use web::logger::AccessLogFormatter;
+use web::server::{Limiter, serve_connection_limited};
+use web::{async_server, event_loop};
+
+fn request() -> Request {
+    Request {
+        method: "GET".to_string(),
+        path: "/index.html".to_string(),
+        query: Some("a=1".to_string()),
+        version: "HTTP/1.1".to_string(),
+        headers: vec![
+            ("User-Agent".to_string(), "curl/8.0 \"test\"".to_string()),
+            ("Referer".to_string(), "http://example.com/".to_string()),
+        ],
+        remote_addr: Some("127.0.0.1:5000".parse().unwrap()),
+        ..Request::default()
+    }
+}
+
+fn entry() -> AccessEntry {
+    let mut entry = AccessEntry::new(&request(), 200, 5, Duration::from_micros(1500));
+    entry.time = Local.with_ymd_and_hms(2024, 10, 1, 13, 55, 36).unwrap();
+    entry
+}
+
+#[test]
+fn formats_combined_log_line() {
+    let entry = entry();
+    let zone = entry.time.format("%z");
+    assert_eq!(
+        entry.format(LogFormat::Combined),
+        format!(
+            "127.0.0.1 - - [01/Oct/2024:13:55:36 {zone}] \"GET /index.html?a=1 HTTP/1.1\" 200 5 \
+             \"http://example.com/\" \"curl/8.0 \\\"test\\\"\" 1.500"
+        )
+    );
+
+    let empty = AccessEntry::new(&Request::default(), 204, 0, Duration::ZERO);
+    assert!(empty.combined().starts_with("- - - ["));
+    // 服务端在读到请求行之前回的错误响应，请求行记成 -
+    assert!(empty.combined().contains("] \"-\" 204 - \"-\" \"-\" "));
+}
+
+#[test]
+fn formats_json_log_line() {
+    let line = entry().format(LogFormat::Json);
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["remote_addr"], "127.0.0.1:5000");
+    assert_eq!(value["method"], "GET");
+    assert_eq!(value["path"], "/index.html?a=1");
+    assert_eq!(value["status"], 200);
+    assert_eq!(value["bytes"], 5);
+    assert_eq!(value["duration_ms"], 1.5);
+    assert_eq!(value["user_agent"], "curl/8.0 \"test\"");
+}
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    // 等到日志有 count 行，返回最后一行
+    fn wait_lines(&self, count: usize) -> serde_json::Value {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            let lines: Vec<_> = output.lines().collect();
+            if lines.len() >= count {
+                assert_eq!(lines.len(), count, "{output}");
+                return serde_json::from_str(lines[count - 1]).unwrap();
+            }
+            assert!(Instant::now() < deadline, "{output}");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+// 推送式响应体分两块发出，中间隔 100ms
+fn pushed(_: &Request) -> Response {
+    let (response, sender) = Response::channel(200);
+    thread::spawn(move || {
+        sender.send("hello").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        sender.send("world").unwrap();
+    });
+    response
+}
+
+fn send(addr: SocketAddr, request: &[u8]) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request).unwrap();
+    stream.read_to_end(&mut Vec::new()).unwrap();
+}
+
+#[test]
+fn every_mode_logs_bytes_actually_sent() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .event_format(AccessLogFormatter)
+            .with_writer(move || writer.clone())
+            .with_filter(filter_fn(|metadata| metadata.target() == ACCESS_TARGET)),
+    );
+    // 事件循环和异步模式的日志在它们自己的线程上记，只能设成全局的
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let limiter = Limiter::default().with_access_log(LogFormat::Json);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let blocking = listener.local_addr().unwrap();
+    let server_limiter = limiter.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve_connection_limited(stream.unwrap(), &pushed, &server_limiter);
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let event_loop = listener.local_addr().unwrap();
+    let server_limiter = limiter.clone();
+    thread::spawn(move || {
+        event_loop::run_with_limits(listener, Arc::new(pushed), 1, server_limiter)
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let async_addr = listener.local_addr().unwrap();
+    let handler = |request: Request| {
+        let response = pushed(&request);
+        async move { response }
+    };
+    thread::spawn(move || async_server::run_with_limits(listener, Arc::new(handler), 1, limiter));
+
+    // 响应体的字节数按实际写出去的算：两块 chunk 加上结尾的空 chunk；耗时算到响应体发完
+    for (index, addr) in [blocking, event_loop, async_addr].into_iter().enumerate() {
+        send(
+            addr,
+            b"GET /events?x=1 HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let value = captured.wait_lines(index * 2 + 1);
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["path"], "/events?x=1");
+        assert_eq!(
+            value["bytes"],
+            "5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n".len()
+        );
+        assert!(value["duration_ms"].as_f64().unwrap() >= 100.0, "{value}");
+
+        // 服务端自己回的错误响应也要记
+        send(addr, b"BAD\r\n\r\n");
+        let value = captured.wait_lines(index * 2 + 2);
+        assert_eq!(value["status"], 400);
+        assert_eq!(value["method"], "");
+        assert!(value["bytes"].as_u64().unwrap() > 0, "{value}");
+    }
+}
@@ -0,0 +1,92 @@
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use web::bench::Target;
+
+// 结束时杀掉子进程
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn log_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("web-bench-logs-{}", std::process::id()))
+}
+
+fn start_server(mode: &str) -> (Server, String) {
+    let port = free_port();
+    let address = format!("127.0.0.1:{port}");
+    let child = Command::new(env!("CARGO_BIN_EXE_web"))
+        .args(["--address", &address, "--mode", mode, "--workers", "4"])
+        .arg("--log-dir")
+        .arg(log_dir())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let server = Server(child);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(&address).is_err() {
+        assert!(Instant::now() < deadline, "{mode} server did not start");
+        thread::sleep(Duration::from_millis(20));
+    }
+    (server, format!("http://{address}/"))
+}
+
+fn bench(url: &str, mode: &str) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_web-bench"))
+        .args([url, "-c", "4", "-n", "200", "--mode", mode, "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn parses_target_url() {
+    let target = Target::parse("http://localhost:7878/hello.html").unwrap();
+    assert_eq!(target.host, "localhost");
+    assert_eq!(target.port, 7878);
+    assert_eq!(target.path, "/hello.html");
+    assert_eq!(Target::parse("http://example.com").unwrap().port, 80);
+    assert!(Target::parse("https://example.com/").is_err());
+    assert!(Target::parse("http://:80/").is_err());
+}
+
+#[test]
+fn benchmarks_every_dispatch_mode() {
+    for mode in ["single", "threads", "pool", "event-loop", "async"] {
+        let (_server, url) = start_server(mode);
+
+        let report = bench(&url, "keep-alive");
+        assert_eq!(report["requests"], 200, "{mode}: {report}");
+        assert_eq!(report["errors"], 0, "{mode}: {report}");
+        assert_eq!(report["statuses"]["200"], 200, "{mode}: {report}");
+        assert!(
+            report["connections"].as_u64().unwrap() <= 8,
+            "{mode}: {report}"
+        );
+        assert!(report["latency_ms"]["p99"].as_f64().unwrap() > 0.0);
+
+        let report = bench(&url, "one-shot");
+        assert_eq!(report["requests"], 200, "{mode}: {report}");
+        assert_eq!(report["errors"], 0, "{mode}: {report}");
+        assert_eq!(report["connections"], 200, "{mode}: {report}");
+    }
+    let _ = std::fs::remove_dir_all(log_dir());
+}
@@ -0,0 +1,148 @@
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::Path;
+use std::{env, fs, process};
+use web::compression::{Compression, Encoding, compress, encode, negotiate};
+use web::http::{Handler, Request, Response};
+use web::static_files::{gzip_sibling, serve_file};
+
+const SUPPORTED: &[Encoding] = &[Encoding::Brotli, Encoding::Gzip];
+
+fn request_accepting(accept_encoding: &str) -> Request {
+    Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        version: "HTTP/1.1".to_string(),
+        headers: vec![("Accept-Encoding".to_string(), accept_encoding.to_string())],
+        ..Request::default()
+    }
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decoded).unwrap();
+    decoded
+}
+
+fn unbrotli(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    brotli::Decompressor::new(data, 4096)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+#[test]
+fn negotiate_respects_quality_values() {
+    assert_eq!(negotiate(None, SUPPORTED), Encoding::Identity);
+    assert_eq!(
+        negotiate(Some("gzip, deflate, br"), SUPPORTED),
+        Encoding::Brotli
+    );
+    assert_eq!(negotiate(Some("br;q=0.5, gzip"), SUPPORTED), Encoding::Gzip);
+    assert_eq!(
+        negotiate(Some("GZIP;Q=0.8, identity;q=0.5"), SUPPORTED),
+        Encoding::Gzip
+    );
+    assert_eq!(negotiate(Some("*"), SUPPORTED), Encoding::Brotli);
+    assert_eq!(
+        negotiate(Some("*;q=0.5, br;q=0"), SUPPORTED),
+        Encoding::Gzip
+    );
+    assert_eq!(
+        negotiate(Some("br;q=0, gzip;q=0"), SUPPORTED),
+        Encoding::Identity
+    );
+    assert_eq!(
+        negotiate(Some("identity, gzip;q=0.5"), SUPPORTED),
+        Encoding::Identity
+    );
+    // 不合法的 q 值整项忽略
+    assert_eq!(negotiate(Some("br;q=2, gzip"), SUPPORTED), Encoding::Gzip);
+    assert_eq!(negotiate(Some("deflate"), SUPPORTED), Encoding::Identity);
+}
+
+#[test]
+fn compresses_large_text_responses() {
+    let body = "<p>hello</p>\n".repeat(200);
+    let handler = compress(
+        move |_: &Request| Response::html(200, body.clone()),
+        Compression::default(),
+    );
+
+    let response = handler.handle(&request_accepting("gzip"));
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(
+        gunzip(&response.body),
+        "<p>hello</p>\n".repeat(200).as_bytes()
+    );
+
+    let response = handler.handle(&request_accepting("br, gzip"));
+    assert_eq!(response.header("Content-Encoding"), Some("br"));
+    assert_eq!(
+        unbrotli(&response.body),
+        "<p>hello</p>\n".repeat(200).as_bytes()
+    );
+
+    let response = handler.handle(&Request::default());
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+}
+
+#[test]
+fn skips_small_and_binary_responses() {
+    let compression = Compression::default();
+
+    let mut small = Response::text(200, "short");
+    compression.apply(Some("gzip"), &mut small);
+    assert_eq!(small.header("Content-Encoding"), None);
+    assert_eq!(small.header("Vary"), Some("Accept-Encoding"));
+
+    let mut image = Response::new(200)
+        .with_header("Content-Type", "image/png")
+        .with_body(vec![0; 4096]);
+    compression.apply(Some("gzip"), &mut image);
+    assert_eq!(image.header("Content-Encoding"), None);
+    assert_eq!(image.header("Vary"), None);
+
+    let mut existing = Response::text(200, "a".repeat(4096)).with_header("Vary", "Origin");
+    compression.apply(Some("gzip"), &mut existing);
+    assert_eq!(existing.header("Vary"), Some("Origin, Accept-Encoding"));
+}
+
+#[test]
+fn serves_precompressed_sibling() {
+    let dir = env::temp_dir().join(format!("web-static-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("page.html");
+    fs::write(&path, "plain").unwrap();
+    fs::write(
+        gzip_sibling(&path),
+        encode(Encoding::Gzip, b"precompressed").unwrap(),
+    )
+    .unwrap();
+
+    let response = serve_file(&request_accepting("gzip, br"), 200, &path).unwrap();
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(gunzip(&response.body), b"precompressed");
+
+    // 已经带了 Content-Encoding，压缩层不会再压一次
+    let mut compressed = response.clone();
+    Compression::default().apply(Some("br"), &mut compressed);
+    assert_eq!(compressed, response);
+
+    let response = serve_file(&request_accepting("br"), 200, &path).unwrap();
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.body, b"plain");
+
+    let response = serve_file(&Request::default(), 404, Path::new("public/hello.html")).unwrap();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.header("Vary"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,181 @@
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use web::access_log::LogFormat;
+use web::config::{Cli, Config, ConfigError, Mode};
+use web::static_files::resolve;
+
+#[test]
+fn parses_toml_config() {
+    let config = Config::from_toml(
+        r#"
+        address = "127.0.0.1:8080"
+        mode = "thread-per-conn"
+        workers = 2
+        doc_root = "site"
+        access_log_format = "json"
+
+        [limits]
+        header_timeout_secs = 3
+        max_body_size = 4096
+
+        [tls]
+        cert = "cert.pem"
+        key = "key.pem"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.address, "127.0.0.1:8080");
+    assert_eq!(config.mode, Mode::Threads);
+    assert_eq!(config.workers, 2);
+    // 没写的字段用默认值
+    assert_eq!(config.max_workers, 8);
+    assert_eq!(config.doc_root, PathBuf::from("site"));
+    assert_eq!(config.access_log_format, LogFormat::Json);
+
+    let limits = config.limits.to_limits();
+    assert_eq!(limits.header_timeout, Duration::from_secs(3));
+    assert_eq!(limits.keep_alive_timeout, Duration::from_secs(5));
+    assert_eq!(limits.max_body_size, 4096);
+
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.address, "0.0.0.0:7443");
+    assert_eq!(tls.cert, PathBuf::from("cert.pem"));
+    assert!(tls.sni.is_empty());
+
+    assert!(Config::from_toml("mode = \"fork\"").is_err());
+    assert!(Config::from_toml("wokers = 2").is_err());
+}
+
+#[test]
+fn cli_flags_override_config_file() {
+    let dir = std::env::temp_dir().join(format!("web-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("web.toml");
+    std::fs::write(
+        &file,
+        "mode = \"async\"\nworkers = 2\n[limits]\nmax_body_size = 10\n",
+    )
+    .unwrap();
+
+    let cli = Cli::try_parse_from([
+        "web",
+        "--config",
+        file.to_str().unwrap(),
+        "--workers",
+        "6",
+        "--header-timeout",
+        "1",
+        "--tls-cert",
+        "c.pem",
+        "--tls-key",
+        "k.pem",
+    ])
+    .unwrap();
+    let config = cli.load_config().unwrap();
+    assert_eq!(config.mode, Mode::Async);
+    assert_eq!(config.workers, 6);
+    assert_eq!(config.limits.max_body_size, 10);
+    assert_eq!(config.limits.header_timeout_secs, 1);
+    assert_eq!(config.tls.unwrap().key, PathBuf::from("k.pem"));
+
+    let cli = Cli::try_parse_from(["web", "-m", "thread-per-conn"]).unwrap();
+    assert_eq!(cli.mode, Some(Mode::Threads));
+    // 证书和私钥要一起给
+    assert!(Cli::try_parse_from(["web", "--tls-cert", "c.pem"]).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejects_invalid_config() {
+    let cli = Cli::try_parse_from(["web", "--workers", "8", "--max-workers", "4"]).unwrap();
+    assert!(matches!(cli.load_config(), Err(ConfigError::Invalid(_))));
+    // 其余模式不用 max_workers
+    let cli = Cli::try_parse_from(["web", "-m", "async", "--workers", "8", "--max-workers", "4"])
+        .unwrap();
+    assert_eq!(cli.load_config().unwrap().workers, 8);
+
+    for flag in [
+        "--read-timeout",
+        "--write-timeout",
+        "--header-timeout",
+        "--body-timeout",
+        "--keep-alive-timeout",
+    ] {
+        let cli = Cli::try_parse_from(["web", flag, "0"]).unwrap();
+        assert!(matches!(cli.load_config(), Err(ConfigError::Invalid(_))));
+    }
+
+    let cli = Cli::try_parse_from(["web", "--config", "no-such-file.toml"]).unwrap();
+    assert!(matches!(cli.load_config(), Err(ConfigError::Io(..))));
+}
+
+#[test]
+fn validates_sni_certificates() {
+    let parse = |hosts: &[&str]| {
+        let mut toml = String::from("[tls]\ncert = 'c.pem'\nkey = 'k.pem'\n");
+        for host in hosts {
+            toml.push_str(&format!(
+                "[[tls.sni]]\nhost = '{host}'\ncert = 'c.pem'\nkey = 'k.pem'\n"
+            ));
+        }
+        Config::from_toml(&toml).unwrap()
+    };
+
+    let config = parse(&["a.example.com", "*.example.com"]);
+    config.validate().unwrap();
+    let sni = &config.tls.unwrap().sni;
+    assert_eq!(sni[1].host, "*.example.com");
+    assert_eq!(sni[1].cert, PathBuf::from("c.pem"));
+
+    for hosts in [
+        &["a.example.com", "A.example.com"][..],
+        &[""],
+        &["example.com:443"],
+        &["a.*.com"],
+        &["*."],
+    ] {
+        assert!(
+            matches!(parse(hosts).validate(), Err(ConfigError::Invalid(_))),
+            "{hosts:?}"
+        );
+    }
+
+    // 命令行换默认证书时保留配置文件里按主机名配置的证书
+    let dir = std::env::temp_dir().join(format!("web-config-sni-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("web.toml");
+    std::fs::write(
+        &file,
+        "[tls]\ncert = 'c.pem'\nkey = 'k.pem'\n\
+         [[tls.sni]]\nhost = 'a.example.com'\ncert = 'a.pem'\nkey = 'a-key.pem'\n",
+    )
+    .unwrap();
+    let cli = Cli::try_parse_from([
+        "web",
+        "--config",
+        file.to_str().unwrap(),
+        "--tls-cert",
+        "new.pem",
+        "--tls-key",
+        "new-key.pem",
+    ])
+    .unwrap();
+    let tls = cli.load_config().unwrap().tls.unwrap();
+    assert_eq!(tls.cert, PathBuf::from("new.pem"));
+    assert_eq!(tls.sni[0].host, "a.example.com");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn resolves_files_inside_doc_root() {
+    let root = Path::new("public");
+    assert_eq!(resolve(root, "/hello.html"), Some(root.join("hello.html")));
+    assert_eq!(resolve(root, "/missing.html"), None);
+    assert_eq!(resolve(root, "/../Cargo.toml"), None);
+    assert_eq!(resolve(root, "/./../src/lib.rs"), None);
+    assert_eq!(resolve(root, "/"), None);
+    assert_eq!(resolve(Path::new("."), "/.git/config"), None);
+}
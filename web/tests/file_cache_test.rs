@@ -0,0 +1,111 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{env, process};
+use web::file_cache::{CACHE_STATS_PATH, CacheStatsRoute, FileCache};
+use web::http::{Handler, Request, Response};
+use web::middleware::Chain;
+use web::site;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("web-cache-{name}-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// 改内容后把修改时间往后拨，避免两次写入落在同一个时间戳里
+fn rewrite(path: &PathBuf, contents: &str) {
+    fs::write(path, contents).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(5);
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+}
+
+#[test]
+fn caches_until_file_changes() {
+    let dir = temp_dir("mtime");
+    let file = dir.join("a.txt");
+    fs::write(&file, "first").unwrap();
+
+    let cache = FileCache::new(1024);
+    assert_eq!(*cache.read(&file).unwrap(), b"first");
+    assert_eq!(*cache.read(&file).unwrap(), b"first");
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+    assert_eq!((stats.entries, stats.bytes), (1, 5));
+
+    rewrite(&file, "second!");
+    assert_eq!(*cache.read(&file).unwrap(), b"second!");
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 2));
+    assert_eq!((stats.entries, stats.bytes), (1, 7));
+
+    fs::remove_file(&file).unwrap();
+    assert!(cache.read(&file).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn evicts_least_recently_used_within_budget() {
+    let dir = temp_dir("lru");
+    let path = |name: &str| dir.join(name);
+    for name in ["a", "b", "c"] {
+        fs::write(path(name), [0u8; 40]).unwrap();
+    }
+    fs::write(path("big"), [0u8; 200]).unwrap();
+
+    let cache = FileCache::new(100);
+    cache.read(&path("a")).unwrap();
+    cache.read(&path("b")).unwrap();
+    // 用一次 a，b 变成最久没用的
+    cache.read(&path("a")).unwrap();
+    cache.read(&path("c")).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.entries, stats.bytes, stats.evictions), (2, 80, 1));
+
+    cache.read(&path("a")).unwrap();
+    cache.read(&path("b")).unwrap();
+    assert_eq!(cache.stats().hits, 2);
+    assert_eq!(cache.stats().misses, 4);
+
+    // 比整个缓存还大的文件照常读出来，但不缓存
+    assert_eq!(cache.read(&path("big")).unwrap().len(), 200);
+    assert_eq!(cache.stats().bytes, 80);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn site_serves_from_cache_and_reports_stats() {
+    let cache = Arc::new(FileCache::new(1024 * 1024));
+    let handler = Chain::new(site("public", Some(Arc::clone(&cache))))
+        .with(CacheStatsRoute::new(Arc::clone(&cache)));
+    let get = |path: &str| {
+        handler.handle(&Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            ..Request::default()
+        })
+    };
+
+    let expected = fs::read("public/hello.html").unwrap();
+    let first = get("/").body;
+    assert_eq!(first, expected);
+    // 命中缓存时响应体和缓存共用同一份数据
+    let second = get("/hello.html").body;
+    assert_eq!(second, expected);
+    assert_eq!(first.as_ptr(), second.as_ptr());
+    assert_eq!(get("/missing").status, 404);
+
+    let Response { status, body, .. } = get(CACHE_STATS_PATH);
+    assert_eq!(status, 200);
+    let body = String::from_utf8(body.into_vec()).unwrap();
+    assert!(body.contains("hits: 1\n"), "{body}");
+    assert!(body.contains("misses: 2\n"), "{body}");
+    assert!(body.contains("entries: 2\n"), "{body}");
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::{env, fs, process, thread};
+use web::form::{Form, FormError, FormLimits, parse_multipart};
+use web::http::{Handler, Request, Response};
+use web::server::{Limiter, Limits, serve_connection_limited};
+
+const BOUNDARY: &str = "----web-test-boundary";
+
+fn limits(name: &str) -> FormLimits {
+    let dir = env::temp_dir().join(format!("web-form-{name}-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    FormLimits {
+        temp_dir: dir,
+        ..FormLimits::default()
+    }
+}
+
+fn post(content_type: &str, body: &[u8]) -> Request {
+    Request {
+        method: "POST".to_string(),
+        path: "/upload".to_string(),
+        version: "HTTP/1.1".to_string(),
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body: body.to_vec(),
+        ..Request::default()
+    }
+}
+
+fn multipart_body(file: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "preamble\r\n--{BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+         hello world\r\n--{BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"count\"\r\n\r\n\
+         42\r\n--{BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"upload\"; filename=\"C:\\\\docs\\\\a \\\"b\\\".bin\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+    body
+}
+
+fn files_in(dir: &PathBuf) -> usize {
+    fs::read_dir(dir).unwrap().count()
+}
+
+// 每次只读一个字节，分隔符会被切在两次读取之间
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.split_first() {
+            Some((&byte, rest)) if !buf.is_empty() => {
+                buf[0] = byte;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn parses_urlencoded_fields_with_types() {
+    let request = post(
+        "application/x-www-form-urlencoded; charset=UTF-8",
+        b"name=J%C3%BCrgen+Li&age=30&tag=a&tag=b&subscribe=on&bad=%zz&empty=",
+    );
+    let form = Form::parse(&request, &FormLimits::default()).unwrap();
+
+    assert_eq!(form.get("name"), Some("Jürgen Li"));
+    assert_eq!(form.value::<u32>("age").unwrap(), 30);
+    assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+    assert!(form.flag("subscribe"));
+    assert!(!form.flag("newsletter"));
+    assert_eq!(form.get("bad"), Some("%zz"));
+    assert_eq!(form.optional::<u32>("empty").unwrap(), None);
+    assert!(matches!(
+        form.value::<u32>("missing"),
+        Err(FormError::Missing(_))
+    ));
+    let err = form.value::<u32>("name").unwrap_err();
+    assert!(matches!(err, FormError::InvalidValue { .. }));
+    assert_eq!(err.status(), 400);
+
+    let err = Form::parse(&post("text/plain", b"a=1"), &FormLimits::default()).unwrap_err();
+    assert_eq!(err.status(), 415);
+}
+
+#[test]
+fn streams_multipart_files_to_temp_dir() {
+    let limits = limits("multipart");
+    let contents: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    let body = multipart_body(&contents);
+    let content_type = format!("multipart/form-data; boundary=\"{BOUNDARY}\"");
+
+    let mut form = Form::parse(&post(&content_type, &body), &limits).unwrap();
+    assert_eq!(form.get("title"), Some("hello world"));
+    assert_eq!(form.value::<i64>("count").unwrap(), 42);
+    let file = form.file("upload").unwrap();
+    assert_eq!(file.filename.as_deref(), Some("a \"b\".bin"));
+    assert_eq!(
+        file.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+    assert_eq!(file.size, contents.len() as u64);
+    assert!(file.path.starts_with(&limits.temp_dir));
+    assert_eq!(file.read().unwrap(), contents);
+
+    // 留下来的文件不会被删掉，其余的随 Form 一起清理
+    let kept = limits.temp_dir.join("kept.bin");
+    let file = form.take_files().pop().unwrap();
+    file.persist(&kept).unwrap();
+    assert_eq!(fs::read(&kept).unwrap(), contents);
+    fs::remove_file(&kept).unwrap();
+
+    let form = parse_multipart(Trickle(&body), BOUNDARY, &limits).unwrap();
+    assert_eq!(form.get("title"), Some("hello world"));
+    assert_eq!(form.file("upload").unwrap().read().unwrap(), contents);
+    assert_eq!(files_in(&limits.temp_dir), 1);
+    drop(form);
+    assert_eq!(files_in(&limits.temp_dir), 0);
+    fs::remove_dir_all(&limits.temp_dir).unwrap();
+}
+
+#[test]
+fn rejects_oversized_and_malformed_uploads() {
+    let limits = FormLimits {
+        max_file_size: 1000,
+        max_field_size: 8,
+        ..limits("limits")
+    };
+    let content_type = format!("multipart/form-data; boundary={BOUNDARY}");
+
+    // 文件超限时返回 413，写了一半的临时文件要删掉
+    let body = multipart_body(&[7u8; 1001]);
+    let mut field_limit = limits.clone();
+    field_limit.max_field_size = 64;
+    let err = Form::parse(&post(&content_type, &body), &field_limit).unwrap_err();
+    assert!(matches!(err, FormError::TooLarge(_)), "{err}");
+    assert_eq!(err.status(), 413);
+    assert_eq!(files_in(&limits.temp_dir), 0);
+
+    let err = Form::parse(&post(&content_type, &multipart_body(b"ok")), &limits).unwrap_err();
+    assert_eq!(err.to_string(), "field title too large");
+
+    let truncated = &multipart_body(b"data")[..120];
+    let err = Form::parse(&post(&content_type, truncated), &field_limit).unwrap_err();
+    assert!(matches!(err, FormError::Malformed(_)), "{err}");
+    assert_eq!(files_in(&limits.temp_dir), 0);
+
+    let err = Form::parse(&post("multipart/form-data", b""), &limits).unwrap_err();
+    assert_eq!(err.status(), 400);
+
+    let too_many = FormLimits {
+        max_fields: 1,
+        ..limits.clone()
+    };
+    let err = Form::parse(
+        &post("application/x-www-form-urlencoded", b"a=1&b=2"),
+        &too_many,
+    )
+    .unwrap_err();
+    assert!(matches!(err, FormError::TooManyFields));
+    fs::remove_dir_all(&limits.temp_dir).unwrap();
+}
+
+// 上传的路径边读边解析请求体，回上传文件的大小
+struct Upload(FormLimits);
+
+impl Handler for Upload {
+    fn handle(&self, request: &Request) -> Response {
+        match Form::parse(request, &self.0) {
+            Ok(form) => Response::text(200, form.file("upload").unwrap().size.to_string()),
+            Err(err) => err.to_response(),
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        request.path == "/upload"
+    }
+}
+
+#[test]
+fn streams_uploads_larger_than_max_body_size() {
+    let limits = limits("streamed");
+    let dir = limits.temp_dir.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let handler = Upload(limits);
+        let limiter = Limiter::new(Limits {
+            max_body_size: 1024,
+            ..Limits::default()
+        });
+        for stream in listener.incoming() {
+            serve_connection_limited(stream.unwrap(), &handler, &limiter);
+        }
+    });
+
+    let contents = vec![7; 50_000];
+    let body = multipart_body(&contents);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary={BOUNDARY}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\n50000"), "{response}");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,273 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::executor::block_on;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+use web::async_server::AsyncHandler;
+use web::config::Config;
+use web::http::{Handler, Request, Response};
+use web::middleware::{
+    BasicAuth, Chain, Cors, Middleware, REQUEST_ID_HEADER, RequestId, Timing, basic_credentials,
+    scoped,
+};
+use web::server::serve_connection;
+
+fn get(path: &str, headers: &[(&str, &str)]) -> Request {
+    Request {
+        method: "GET".to_string(),
+        path: path.to_string(),
+        version: "HTTP/1.1".to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        ..Request::default()
+    }
+}
+
+// 把请求里的 X-Request-Id 放进响应体，确认 handler 能拿到
+fn echo_id(request: &Request) -> Response {
+    Response::text(200, request.header(REQUEST_ID_HEADER).unwrap_or("none"))
+}
+
+// 记录调用顺序的中间件
+struct Trace {
+    name: &'static str,
+    stop: bool,
+}
+
+impl Middleware for Trace {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let trace = format!("{}{}>", request.header("X-Trace").unwrap_or(""), self.name);
+        request.set_header("X-Trace", &trace);
+        self.stop.then(|| Response::text(403, "stopped"))
+    }
+
+    fn after(&self, _request: &Request, response: &mut Response, _started: Instant) {
+        let trace = format!("{}<{}", response.header("X-Trace").unwrap_or(""), self.name);
+        response.set_header("X-Trace", &trace);
+    }
+}
+
+#[test]
+fn chain_runs_hooks_in_order_and_short_circuits() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    let handler = move |request: &Request| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Response::text(200, request.header("X-Trace").unwrap_or(""))
+    };
+
+    let chain = Chain::new(handler)
+        .with(Trace {
+            name: "a",
+            stop: false,
+        })
+        .with(Trace {
+            name: "b",
+            stop: false,
+        });
+    let response = chain.handle(&get("/", &[]));
+    assert_eq!(response.body, b"a>b>");
+    assert_eq!(response.header("X-Trace"), Some("<b<a"));
+
+    // b 短路：handler 和 c 都不调用，只有放行了的 a 调用 after
+    let chain = Chain::new(|_: &Request| -> Response { unreachable!() })
+        .with(Trace {
+            name: "a",
+            stop: false,
+        })
+        .with(Trace {
+            name: "b",
+            stop: true,
+        })
+        .with(Trace {
+            name: "c",
+            stop: false,
+        });
+    let response = chain.handle(&get("/", &[]));
+    assert_eq!(response.status, 403);
+    assert_eq!(response.header("X-Trace"), Some("<a"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn request_id_and_timing_headers() {
+    let chain = Chain::new(echo_id).with(RequestId::new()).with(Timing);
+
+    let first = chain.handle(&get("/", &[]));
+    let second = chain.handle(&get("/", &[]));
+    let id = first.header(REQUEST_ID_HEADER).unwrap();
+    assert_eq!(first.body, id.as_bytes());
+    assert_ne!(Some(id), second.header(REQUEST_ID_HEADER));
+    assert!(first.header("X-Response-Time").unwrap().ends_with("ms"));
+    assert!(
+        first
+            .header("Server-Timing")
+            .unwrap()
+            .starts_with("app;dur=")
+    );
+
+    // 客户端带的合法 id 沿用，不合法的换掉
+    let kept = chain.handle(&get("/", &[(REQUEST_ID_HEADER, "abc-123")]));
+    assert_eq!(kept.header(REQUEST_ID_HEADER), Some("abc-123"));
+    let replaced = chain.handle(&get("/", &[(REQUEST_ID_HEADER, "bad id\"")]));
+    assert_ne!(replaced.header(REQUEST_ID_HEADER), Some("bad id\""));
+}
+
+#[test]
+fn cors_answers_preflight_and_tags_responses() {
+    let chain = Chain::new(|_: &Request| Response::text(200, "ok")).with(
+        Cors::new()
+            .allow_origin("http://localhost:3000")
+            .allow_methods(["GET", "PUT"])
+            .max_age(std::time::Duration::from_secs(600)),
+    );
+
+    let mut preflight = get(
+        "/api",
+        &[
+            ("Origin", "http://localhost:3000"),
+            ("Access-Control-Request-Method", "PUT"),
+            ("Access-Control-Request-Headers", "content-type"),
+        ],
+    );
+    preflight.method = "OPTIONS".to_string();
+    let response = chain.handle(&preflight);
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("http://localhost:3000")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Methods"),
+        Some("GET, PUT")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Headers"),
+        Some("content-type")
+    );
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+
+    let response = chain.handle(&get("/api", &[("Origin", "http://localhost:3000")]));
+    assert_eq!(response.body, b"ok");
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("http://localhost:3000")
+    );
+    assert_eq!(response.header("Vary"), Some("Origin"));
+
+    let response = chain.handle(&get("/api", &[("Origin", "http://evil.example")]));
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+    preflight.set_header("Origin", "http://evil.example");
+    assert_eq!(chain.handle(&preflight).status, 403);
+}
+
+#[test]
+fn basic_auth_protects_scoped_paths() {
+    let chain = Chain::new(|request: &Request| Response::text(200, request.path.clone())).with(
+        scoped("/admin/", BasicAuth::new("admin").user("root", "s3cret")),
+    );
+    let authorization = format!("Basic {}", STANDARD.encode("root:s3cret"));
+    let wrong = format!("Basic {}", STANDARD.encode("root:guess"));
+
+    assert_eq!(chain.handle(&get("/", &[])).status, 200);
+    assert_eq!(chain.handle(&get("/administrator", &[])).status, 200);
+
+    let denied = chain.handle(&get("/admin/stats", &[]));
+    assert_eq!(denied.status, 401);
+    assert_eq!(
+        denied.header("WWW-Authenticate"),
+        Some("Basic realm=\"admin\", charset=\"UTF-8\"")
+    );
+    let wrong = chain.handle(&get("/admin", &[("Authorization", &wrong)]));
+    assert_eq!(wrong.status, 401);
+    let allowed = chain.handle(&get("/admin/stats", &[("Authorization", &authorization)]));
+    assert_eq!(allowed.status, 200);
+
+    assert_eq!(
+        basic_credentials(&authorization),
+        Some(("root".to_string(), "s3cret".to_string()))
+    );
+    assert_eq!(basic_credentials("Bearer abc"), None);
+}
+
+#[test]
+fn basic_auth_is_not_bypassed_by_non_canonical_paths() {
+    let chain = Chain::new(|request: &Request| Response::text(200, request.path.clone())).with(
+        scoped("/admin", BasicAuth::new("admin").user("root", "s3cret")),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve_connection(stream.unwrap(), &chain);
+        }
+    });
+    // 返回状态码和响应体
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+        let status = lines.next().unwrap()[9..12].parse::<u16>().unwrap();
+        (status, lines.last().unwrap())
+    };
+
+    assert_eq!(get("//admin/secret.html").0, 401);
+    assert_eq!(get("/./admin/secret.html").0, 401);
+    assert_eq!(get("/admin//./secret.html").0, 401);
+    assert_eq!(get("/public/../admin/secret.html").0, 400);
+    assert_eq!(
+        get("//public/./a.html"),
+        (200, "/public/a.html".to_string())
+    );
+}
+
+#[test]
+fn chain_wraps_async_handlers() {
+    let handler = |request: Request| async move { echo_id(&request) };
+    let chain = Chain::new(handler)
+        .with(RequestId::new())
+        .with(scoped("/private", BasicAuth::new("web")));
+
+    let response = block_on(AsyncHandler::handle(&chain, get("/", &[])));
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.body,
+        response.header(REQUEST_ID_HEADER).unwrap().as_bytes()
+    );
+
+    // 短路的响应也经过外层的 after
+    let response = block_on(AsyncHandler::handle(&chain, get("/private", &[])));
+    assert_eq!(response.status, 401);
+    assert!(response.header(REQUEST_ID_HEADER).is_some());
+}
+
+#[test]
+fn middleware_from_config() {
+    let config = Config::from_toml(
+        r#"
+        [middleware]
+        timing = false
+
+        [middleware.cors]
+        allow_origins = ["*"]
+
+        [middleware.basic_auth]
+        path = "/stats"
+        users = { admin = "secret" }
+        "#,
+    )
+    .unwrap();
+    let chain = config.middleware.chain(echo_id);
+
+    let response = chain.handle(&get("/", &[("Origin", "http://a.example")]));
+    assert!(response.header(REQUEST_ID_HEADER).is_some());
+    assert_eq!(response.header("X-Response-Time"), None);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(chain.handle(&get("/stats", &[])).status, 401);
+}
@@ -0,0 +1,407 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use web::async_server;
+use web::http::{ChunkedReader, Handler, Request, Response};
+use web::middleware::Chain;
+use web::proxy::Proxy;
+use web::server::{Limiter, Limits, serve_connection, serve_connection_limited};
+
+// 在随机端口上起一个上游服务
+fn spawn_upstream(handler: impl Handler) -> SocketAddr {
+    spawn_upstream_on("127.0.0.1:0".parse().unwrap(), handler)
+}
+
+fn spawn_upstream_on(addr: SocketAddr, handler: impl Handler) -> SocketAddr {
+    let listener = TcpListener::bind(addr).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve_connection(stream.unwrap(), &handler);
+        }
+    });
+    addr
+}
+
+// 把收到的请求行和请求头原样写回
+fn echo_request(request: &Request) -> Response {
+    let mut body = format!("{}\n", request.request_line());
+    for (name, value) in &request.headers {
+        body.push_str(&format!("{name}: {value}\n"));
+    }
+    body.push_str(&String::from_utf8_lossy(&request.body));
+    Response::text(200, body)
+}
+
+fn closed_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request {
+    Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        version: "HTTP/1.1".to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        remote_addr: Some("10.0.0.7:5000".parse().unwrap()),
+        ..Request::default()
+    }
+}
+
+fn body(mut response: Response) -> String {
+    response.buffer().unwrap();
+    String::from_utf8(response.body.into_vec()).unwrap()
+}
+
+#[test]
+fn rewrites_headers_and_strips_prefix() {
+    let upstream = spawn_upstream(echo_request);
+    let chain = Chain::new(|_: &Request| Response::text(200, "local"))
+        .with(Proxy::new("/api", [upstream.to_string()]).strip_prefix(true));
+
+    let mut post = request(
+        "POST",
+        "/api/users",
+        &[
+            ("Host", "example.com"),
+            ("X-Forwarded-For", "1.2.3.4"),
+            ("X-Forwarded-Proto", "https"),
+            ("Connection", "keep-alive, X-Secret"),
+            ("X-Secret", "hop"),
+            ("Content-Length", "5"),
+        ],
+    );
+    post.query = Some("page=2".to_string());
+    post.body = b"hello".to_vec();
+    let response = chain.handle(&post);
+    assert_eq!(response.status, 200);
+    assert!(response.is_streaming());
+
+    let echoed = body(response);
+    assert!(
+        echoed.starts_with("POST /users?page=2 HTTP/1.1\n"),
+        "{echoed}"
+    );
+    assert!(echoed.contains(&format!("Host: {upstream}\n")));
+    assert!(echoed.contains("X-Forwarded-For: 1.2.3.4, 10.0.0.7\n"));
+    assert!(echoed.contains("X-Forwarded-Host: example.com\n"));
+    // 客户端自己带的 X-Forwarded-Proto 被覆盖
+    assert!(echoed.contains("X-Forwarded-Proto: http\n"));
+    assert!(!echoed.contains("X-Forwarded-Proto: https"));
+    assert!(echoed.contains("Connection: close\n"));
+    assert!(!echoed.contains("X-Secret"));
+    assert!(echoed.ends_with("hello"));
+
+    // 前缀以外的请求交给本地路由
+    assert_eq!(chain.handle(&request("GET", "/apix", &[])).body, b"local");
+
+    let preserving = Proxy::new("/", [upstream.to_string()]).preserve_host(true);
+    let echoed = body(preserving.forward(&request("GET", "/", &[("Host", "example.com")])));
+    assert!(echoed.contains("Host: example.com\n"));
+
+    let mut secure = request("GET", "/", &[]);
+    secure.secure = true;
+    assert!(body(preserving.forward(&secure)).contains("X-Forwarded-Proto: https\n"));
+}
+
+#[test]
+fn round_robin_skips_failed_upstreams() {
+    let a = spawn_upstream(|_: &Request| Response::text(200, "a"));
+    let b = spawn_upstream(|_: &Request| Response::text(200, "b"));
+    let proxy = Proxy::new("/", [a.to_string(), b.to_string()]);
+    let bodies: Vec<_> = (0..4)
+        .map(|_| body(proxy.forward(&request("GET", "/", &[]))))
+        .collect();
+    assert_eq!(bodies, ["a", "b", "a", "b"]);
+
+    // 连不上的上游换下一个，并被摘除一段时间
+    let dead = closed_port();
+    let proxy = Proxy::new("/", [format!("http://{dead}"), a.to_string()])
+        .fail_timeout(Duration::from_secs(60));
+    for _ in 0..4 {
+        assert_eq!(body(proxy.forward(&request("GET", "/", &[]))), "a");
+    }
+
+    // 全部摘除后回 502，过了 fail_timeout 再试
+    let proxy = Proxy::new("/", [dead.to_string()]).fail_timeout(Duration::from_millis(100));
+    let response = proxy.forward(&request("GET", "/", &[]));
+    assert_eq!(response.status, 502);
+    assert_eq!(body(response), "No healthy upstream");
+    spawn_upstream_on(dead, |_: &Request| Response::text(200, "revived"));
+    assert_eq!(proxy.forward(&request("GET", "/", &[])).status, 502);
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(body(proxy.forward(&request("GET", "/", &[]))), "revived");
+}
+
+#[test]
+fn streams_chunked_upstream_response_to_client() {
+    // 上游用 chunked 分两次发送，第二块延迟发出
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nX-Upstream: yes\r\n\r\n6\r\nfirst \r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        stream.write_all(b"6\r\nsecond\r\n0\r\n\r\n").unwrap();
+    });
+
+    let proxy = Chain::new(|_: &Request| Response::new(404))
+        .with(Proxy::new("/events", [upstream.to_string()]));
+    let server = spawn_upstream(proxy);
+
+    let mut stream = TcpStream::connect(server).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(head.contains("X-Upstream: yes\r\n"));
+    assert!(!head.contains("Content-Length"));
+
+    // 第一块在上游发完之前就能读到
+    let mut chunked = ChunkedReader::new(reader);
+    let mut first = [0; 6];
+    chunked.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"first ");
+    let mut rest = String::new();
+    chunked.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "second");
+}
+
+// 读一个响应：响应头和按 Content-Length 读到的响应体
+fn read_response(reader: &mut BufReader<TcpStream>) -> (String, String) {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn streams_request_body_larger_than_max_body_size() {
+    let upstream = spawn_upstream(echo_request);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let chain = Chain::new(|_: &Request| Response::text(200, "local"))
+            .with(Proxy::new("/api", [upstream.to_string()]));
+        let limiter = Limiter::new(Limits {
+            max_body_size: 16,
+            ..Limits::default()
+        });
+        for stream in listener.incoming() {
+            serve_connection_limited(stream.unwrap(), &chain, &limiter);
+        }
+    });
+
+    let body = "0123456789".repeat(10);
+    let stream = TcpStream::connect(server).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    // 有长度的请求体原样转发，转发完连接还能继续用
+    write!(
+        writer,
+        "POST /api/upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let (head, echoed) = read_response(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(echoed.contains(&format!("Content-Length: {}\n", body.len())));
+    assert!(echoed.ends_with(&body), "{echoed}");
+
+    // chunked 的请求体重新分块转发
+    write!(
+        writer,
+        "POST /api/upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+         32\r\n{}\r\n32\r\n{}\r\n0\r\n\r\n",
+        &body[..50],
+        &body[50..]
+    )
+    .unwrap();
+    let (head, echoed) = read_response(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(echoed.contains("Transfer-Encoding: chunked\n"));
+    assert!(echoed.ends_with(&body), "{echoed}");
+
+    // 不走代理的请求还是按 max_body_size 限制
+    write!(
+        writer,
+        "POST /local HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let (head, _) = read_response(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 413 "), "{head}");
+}
+
+#[test]
+fn closes_connection_when_request_body_is_not_forwarded() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        // 上游全部摘除，请求体一个字节都没读
+        let chain = Chain::new(|_: &Request| Response::text(200, "local"))
+            .with(Proxy::new("/api", [closed_port().to_string()]));
+        for stream in listener.incoming() {
+            serve_connection(stream.unwrap(), &chain);
+        }
+    });
+
+    let mut stream = TcpStream::connect(server).unwrap();
+    stream
+        .write_all(b"POST /api HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let (head, body) = read_response(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 502 "), "{head}");
+    assert!(head.contains("Connection: close\r\n"), "{head}");
+    assert_eq!(body, "No healthy upstream");
+    // 剩下的请求体不会被当成下一个请求
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn async_proxy_does_not_block_the_executor() {
+    let upstream = spawn_upstream(|_: &Request| {
+        thread::sleep(Duration::from_millis(500));
+        Response::text(200, "slow")
+    });
+    let chain = Chain::new(|_: Request| async { Response::text(200, "local") })
+        .with(Proxy::new("/api", [upstream.to_string()]));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || async_server::run(listener, Arc::new(chain), 1));
+
+    let get = move |path: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        read_response(&mut BufReader::new(stream)).1
+    };
+    let proxied = thread::spawn(move || get("/api/x"));
+
+    // 上游还没响应的时候，执行器唯一的线程照样处理本地的请求
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    assert_eq!(get("/local"), "local");
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert_eq!(proxied.join().unwrap(), "slow");
+}
+
+// 每个连接读完请求头后回一个固定的响应
+fn spawn_raw_upstream(response: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    addr
+}
+
+// 经过代理发一个请求，返回客户端收到的全部内容
+fn proxy_exchange(upstream: SocketAddr, request: &str) -> String {
+    let server = spawn_upstream(
+        Chain::new(|_: &Request| Response::new(404)).with(Proxy::new("/", [upstream.to_string()])),
+    );
+    let mut stream = TcpStream::connect(server).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut received = String::new();
+    stream.read_to_string(&mut received).unwrap();
+    received
+}
+
+#[test]
+fn keeps_entity_length_of_bodyless_responses() {
+    // HEAD 的响应带的是实体的长度，不能改成 0
+    let upstream = spawn_raw_upstream("HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n");
+    let received = proxy_exchange(upstream, "HEAD / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{received}");
+    assert!(received.contains("Content-Length: 1234\r\n"), "{received}");
+    assert!(received.ends_with("\r\n\r\n"), "{received}");
+
+    let upstream = spawn_raw_upstream("HTTP/1.1 304 Not Modified\r\nContent-Length: 99\r\n\r\n");
+    let received = proxy_exchange(upstream, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(received.contains("Content-Length: 99\r\n"), "{received}");
+    assert!(received.ends_with("\r\n\r\n"), "{received}");
+
+    // 204 不能带 Content-Length
+    let upstream = spawn_raw_upstream("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n");
+    let received = proxy_exchange(upstream, "DELETE / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(
+        received.starts_with("HTTP/1.1 204 No Content\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("Content-Length"), "{received}");
+    assert!(!received.contains("Transfer-Encoding"), "{received}");
+}
+
+#[test]
+fn buffers_stream_with_known_length() {
+    let response = Response::new(200)
+        .with_header("Content-Length", "5")
+        .with_stream(&b"hello world"[..]);
+    let bytes = response.to_bytes();
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.contains("Content-Length: 11\r\n"), "{text}");
+    assert!(text.ends_with("\r\n\r\nhello world"));
+
+    let mut written = Vec::new();
+    Response::new(200)
+        .with_header("Content-Length", "5")
+        .with_stream(&b"hello world"[..])
+        .write_to(&mut written)
+        .unwrap();
+    assert!(
+        String::from_utf8(written)
+            .unwrap()
+            .ends_with("Content-Length: 5\r\n\r\nhello")
+    );
+}
@@ -0,0 +1,566 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use web::http::{ChunkedDecoder, ChunkedReader, ParseError, Request, Response, normalize_path};
+use web::server::{Limiter, Limits, admit, serve_connection, serve_connection_limited};
+use web::{async_server, event_loop};
+
+fn echo(request: &Request) -> Response {
+    Response::text(200, format!("{} {}", request.method, request.path))
+}
+
+fn echo_body(request: &Request) -> Response {
+    Response::text(200, request.body.clone())
+}
+
+// 读一个带 Content-Length 的响应，返回 (状态行, body)
+fn read_response(reader: &mut BufReader<TcpStream>) -> (String, String) {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (
+        status_line.trim_end().to_string(),
+        String::from_utf8(body).unwrap(),
+    )
+}
+
+fn assert_keep_alive(addr: SocketAddr) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    assert_eq!(
+        read_response(&mut reader),
+        ("HTTP/1.1 200 OK".to_string(), "GET /a".to_string())
+    );
+
+    stream
+        .write_all(b"POST /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc")
+        .unwrap();
+    assert_eq!(read_response(&mut reader).1, "POST /b");
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    read_response(&mut reader);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+fn test_limiter() -> Limiter {
+    Limiter::new(Limits {
+        header_timeout: Duration::from_millis(300),
+        body_timeout: Duration::from_millis(300),
+        max_body_size: 16,
+        max_connections_per_ip: 2,
+        ..Limits::default()
+    })
+}
+
+// 慢速请求头和请求体 408，请求体过大 413，同一 IP 连接过多 429
+fn assert_limits(addr: SocketAddr, limiter: &Limiter) {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let wait_released = || {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while limiter.connections(localhost) > 0 {
+            assert!(Instant::now() < deadline, "connections not released");
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    let started = Instant::now();
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"GET / HTTP/1.1\r\nHost").unwrap();
+    let (status_line, _) = read_response(&mut BufReader::new(slow));
+    assert_eq!(status_line, "HTTP/1.1 408 Request Timeout");
+    assert!(started.elapsed() < Duration::from_secs(3));
+    wait_released();
+
+    // 每次读都没超时，但整个请求体超过了 body_timeout
+    let started = Instant::now();
+    let mut slow_body = TcpStream::connect(addr).unwrap();
+    slow_body
+        .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n")
+        .unwrap();
+    let mut writer = slow_body.try_clone().unwrap();
+    thread::spawn(move || {
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(100));
+            if writer.write_all(b"x").is_err() {
+                break;
+            }
+        }
+    });
+    let (status_line, _) = read_response(&mut BufReader::new(slow_body));
+    assert_eq!(status_line, "HTTP/1.1 408 Request Timeout");
+    assert!(started.elapsed() < Duration::from_millis(900));
+    wait_released();
+
+    let mut large = TcpStream::connect(addr).unwrap();
+    large
+        .write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n")
+        .unwrap();
+    let (status_line, _) = read_response(&mut BufReader::new(large));
+    assert_eq!(status_line, "HTTP/1.1 413 Payload Too Large");
+    wait_released();
+
+    let idle: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let (status_line, _) = read_response(&mut BufReader::new(TcpStream::connect(addr).unwrap()));
+    assert_eq!(status_line, "HTTP/1.1 429 Too Many Requests");
+    drop(idle);
+    wait_released();
+    assert_keep_alive(addr);
+}
+
+// chunked 请求体解码后交给 handler，后面流水线上的请求照常处理；
+// 解码后过大 413，不支持的传输编码 501，长度有歧义或者格式错误 400
+fn assert_chunked(addr: SocketAddr) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream
+        .write_all(
+            b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\nX-Trailer: 1\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nfg",
+        )
+        .unwrap();
+    assert_eq!(read_response(&mut reader).1, "abcde");
+    assert_eq!(read_response(&mut reader).1, "fg");
+
+    let rejected = |request: &[u8]| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        read_response(&mut BufReader::new(stream)).0
+    };
+    assert_eq!(
+        rejected(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n"),
+        "HTTP/1.1 413 Payload Too Large"
+    );
+    assert_eq!(
+        rejected(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+        "HTTP/1.1 501 Not Implemented"
+    );
+    assert_eq!(
+        rejected(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n"),
+        "HTTP/1.1 400 Bad Request"
+    );
+    assert_eq!(
+        rejected(b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"),
+        "HTTP/1.1 400 Bad Request"
+    );
+    assert_eq!(
+        rejected(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+        "HTTP/1.1 400 Bad Request"
+    );
+}
+
+#[test]
+fn decodes_chunked_body_incrementally() {
+    let encoded = b"4\r\nWiki\r\n6 ;a=b\r\npedia \r\n0\r\nTrailer: x\r\n\r\nNEXT";
+    // 一个字节一个字节地喂，结果一样
+    let mut decoder = ChunkedDecoder::new(64);
+    let mut used = 0;
+    for byte in encoded.chunks(1) {
+        used += decoder.decode(byte).unwrap();
+    }
+    assert!(decoder.is_done());
+    assert_eq!(used, encoded.len() - 4);
+    assert_eq!(decoder.into_body(), b"Wikipedia ");
+
+    let decode = |input: &[u8], max: usize| ChunkedDecoder::new(max).decode(input);
+    assert_eq!(decode(b"+4\r\n", 64), Err(ParseError::BadChunk));
+    assert_eq!(decode(b"4\r\nWikiX\r\n", 64), Err(ParseError::BadChunk));
+    assert_eq!(decode(b"4\n", 64), Err(ParseError::BadChunk));
+    assert_eq!(decode(b"5\r\n", 4), Err(ParseError::BodyTooLarge));
+}
+
+#[test]
+fn chunked_reader_bounds_untrusted_input() {
+    let mut body = String::new();
+    ChunkedReader::new(&b"4\r\nWiki\r\n6 ;a=b\r\npedia \r\n0\r\nTrailer: x\r\n\r\nNEXT"[..])
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "Wikipedia ");
+
+    let read = |input: &[u8]| ChunkedReader::new(input).read_to_end(&mut Vec::new());
+    // 没有换行的 chunk 大小行读到上限就报错，不会一直读下去
+    let endless = io::repeat(b'0').take(1 << 20);
+    let err = ChunkedReader::new(BufReader::new(endless))
+        .read_to_end(&mut Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        read(b"fffffffffffffffff\r\n").unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert_eq!(
+        read(b"4\r\nWi").unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[test]
+fn parse_head_waits_for_complete_head() {
+    assert!(
+        Request::parse_head(b"GET / HTTP/1.1\r\nHost")
+            .unwrap()
+            .is_none()
+    );
+
+    let (request, used) = Request::parse_head(b"GET /p?q=1 HTTP/1.1\r\nHost: a\r\n\r\nrest")
+        .unwrap()
+        .unwrap();
+    assert_eq!(used, 32);
+    assert_eq!(request.path, "/p");
+    assert_eq!(request.query.as_deref(), Some("q=1"));
+    assert_eq!(request.header("host"), Some("a"));
+    assert!(request.keep_alive());
+}
+
+#[test]
+fn normalizes_request_path() {
+    assert_eq!(normalize_path("/"), Ok("/".to_string()));
+    assert_eq!(normalize_path("//a//b"), Ok("/a/b".to_string()));
+    assert_eq!(normalize_path("/./a/./"), Ok("/a/".to_string()));
+    assert_eq!(normalize_path("/a/."), Ok("/a/".to_string()));
+    assert_eq!(normalize_path("/."), Ok("/".to_string()));
+    assert_eq!(normalize_path("*"), Ok("*".to_string()));
+    assert_eq!(normalize_path("/a/../b"), Err(ParseError::BadPath));
+    assert_eq!(normalize_path("/.."), Err(ParseError::BadPath));
+
+    let (request, _) = Request::parse_head(b"GET //admin/./x?q=1 HTTP/1.1\r\n\r\n")
+        .unwrap()
+        .unwrap();
+    assert_eq!(request.path, "/admin/x");
+    assert_eq!(request.query.as_deref(), Some("q=1"));
+}
+
+#[test]
+fn blocking_connection_supports_keep_alive() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve_connection(stream.unwrap(), &echo);
+        }
+    });
+
+    assert_keep_alive(addr);
+}
+
+#[test]
+fn event_loop_serves_many_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // 100 个连接都来自本机，不按 IP 限制
+    let limiter = Limiter::new(Limits {
+        max_connections_per_ip: 0,
+        ..Limits::default()
+    });
+    thread::spawn(move || event_loop::run_with_limits(listener, Arc::new(echo), 2, limiter));
+
+    // 先建好一批空闲连接，事件循环不应该被它们占住
+    let idle: Vec<_> = (0..100)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect();
+    assert_keep_alive(addr);
+
+    let mut bad = TcpStream::connect(addr).unwrap();
+    bad.write_all(b"NONSENSE\r\n\r\n").unwrap();
+    let (status_line, _) = read_response(&mut BufReader::new(bad));
+    assert_eq!(status_line, "HTTP/1.1 400 Bad Request");
+    drop(idle);
+}
+
+#[test]
+fn async_server_sleeps_without_blocking_threads() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: Request| async move {
+        if request.path == "/sleep" {
+            async_server::sleep(Duration::from_millis(300)).await;
+        }
+        echo(&request)
+    };
+    thread::spawn(move || async_server::run(listener, Arc::new(handler), 1));
+
+    assert_keep_alive(addr);
+
+    // 单线程的执行器上同时挂起 20 个 /sleep，总耗时应该接近一次 sleep
+    let started = Instant::now();
+    let clients: Vec<_> = (0..20)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream
+                    .write_all(b"GET /sleep HTTP/1.1\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                read_response(&mut BufReader::new(stream)).1
+            })
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap(), "GET /sleep");
+    }
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn async_server_survives_handler_panics() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: Request| async move {
+        if request.path == "/panic" {
+            panic!("handler failed");
+        }
+        echo(&request)
+    };
+    let threads = 2;
+    thread::spawn(move || async_server::run(listener, Arc::new(handler), threads));
+
+    // panic 的次数比执行器的线程多，每次都回 500，之后的请求照常处理
+    for _ in 0..=threads {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /panic HTTP/1.1\r\n\r\n").unwrap();
+        let (status, _) = read_response(&mut BufReader::new(stream));
+        assert_eq!(status, "HTTP/1.1 500 Internal Server Error");
+    }
+    assert_keep_alive(addr);
+}
+
+// 每次 read 都要等一会儿的 reader，模拟慢盘上的文件
+struct SlowReader {
+    remaining: usize,
+}
+
+impl Read for SlowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        thread::sleep(Duration::from_millis(50));
+        let read = buf.len().min(self.remaining).min(1000);
+        buf[..read].fill(b'x');
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+#[test]
+fn async_server_streams_reader_bodies_off_the_executor() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: Request| async move {
+        match request.path.as_str() {
+            "/sized" => Response::new(200)
+                .with_header("Content-Length", "10000")
+                .with_stream(SlowReader { remaining: 10_000 }),
+            "/chunked" => Response::new(200).with_stream(SlowReader { remaining: 10_000 }),
+            _ => echo(&request),
+        }
+    };
+    thread::spawn(move || async_server::run(listener, Arc::new(handler), 1));
+
+    let sized = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /sized HTTP/1.1\r\n\r\n").unwrap();
+        read_response(&mut BufReader::new(stream))
+    });
+    let chunked = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /chunked HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    });
+
+    // 读响应体的时候执行器唯一的线程不被占用，别的请求马上有响应
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    assert_keep_alive(addr);
+    assert!(started.elapsed() < Duration::from_millis(300));
+
+    let (status, body) = sized.join().unwrap();
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, "x".repeat(10_000));
+
+    let response = chunked.join().unwrap();
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    assert!(head.contains("Transfer-Encoding: chunked"));
+    let mut decoder = ChunkedDecoder::new(usize::MAX);
+    decoder.decode(&response[split..]).unwrap();
+    assert!(decoder.is_done());
+    assert_eq!(decoder.into_body(), vec![b'x'; 10_000]);
+}
+
+#[test]
+fn blocking_connection_enforces_limits() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limiter = test_limiter();
+    let server_limiter = limiter.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let limiter = server_limiter.clone();
+            let stream = stream.unwrap();
+            thread::spawn(move || serve_connection_limited(stream, &echo, &limiter));
+        }
+    });
+
+    assert_limits(addr, &limiter);
+}
+
+#[test]
+fn admit_counts_connections_at_accept_time() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limiter = Limiter::new(Limits {
+        max_connections_per_ip: 1,
+        ..Limits::default()
+    });
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    // 第一个连接还没交给工作线程，名额已经占上了
+    let _queued = TcpStream::connect(addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let permit = admit(&stream, &limiter, false).unwrap();
+    assert_eq!(limiter.connections(localhost), 1);
+
+    let rejected = TcpStream::connect(addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    assert!(admit(&stream, &limiter, false).is_none());
+    drop(stream);
+    let (status_line, _) = read_response(&mut BufReader::new(rejected));
+    assert_eq!(status_line, "HTTP/1.1 429 Too Many Requests");
+
+    drop(permit);
+    assert_eq!(limiter.connections(localhost), 0);
+}
+
+#[test]
+fn every_mode_decodes_chunked_bodies() {
+    let chunked_limiter = || {
+        Limiter::new(Limits {
+            max_body_size: 16,
+            ..Limits::default()
+        })
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_limiter = chunked_limiter();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let limiter = server_limiter.clone();
+            thread::spawn(move || serve_connection_limited(stream.unwrap(), &echo_body, &limiter));
+        }
+    });
+    assert_chunked(addr);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_limiter = chunked_limiter();
+    thread::spawn(move || {
+        event_loop::run_with_limits(listener, Arc::new(echo_body), 1, server_limiter)
+    });
+    assert_chunked(addr);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: Request| async move { echo_body(&request) };
+    thread::spawn(move || {
+        async_server::run_with_limits(listener, Arc::new(handler), 1, chunked_limiter())
+    });
+    assert_chunked(addr);
+}
+
+#[test]
+fn event_loop_stops_reading_while_handler_is_busy() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let slow = |request: &Request| {
+        if request.path == "/slow" {
+            thread::sleep(Duration::from_secs(2));
+        }
+        echo(request)
+    };
+    thread::spawn(move || event_loop::run_with_limits(listener, Arc::new(slow), 1, test_limiter()));
+
+    // handler 处理请求期间对端一直发流水线请求、也不读响应：读缓冲区满了就不再读，
+    // 写缓冲区积压了也不再处理，对端很快就写不进去了
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_write_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+    let flood = b"GET /fast HTTP/1.1\r\n\r\n".repeat(4096);
+    let mut written = 0;
+    let blocked = loop {
+        match stream.write(&flood) {
+            Ok(sent) => written += sent,
+            Err(err) => break err,
+        }
+        assert!(written < 256 * 1024 * 1024, "server kept reading");
+    };
+    assert!(
+        matches!(
+            blocked.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+        "{blocked}"
+    );
+
+    // 正在处理的请求照常响应
+    let mut reader = BufReader::new(stream);
+    let (status, body) = read_response(&mut reader);
+    assert!(status.starts_with("HTTP/1.1 200 OK"), "{status}");
+    assert_eq!(body, "GET /slow");
+}
+
+#[test]
+fn event_loop_enforces_limits() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limiter = test_limiter();
+    let server_limiter = limiter.clone();
+    thread::spawn(move || event_loop::run_with_limits(listener, Arc::new(echo), 2, server_limiter));
+
+    assert_limits(addr, &limiter);
+}
+
+#[test]
+fn async_server_enforces_limits() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limiter = test_limiter();
+    let server_limiter = limiter.clone();
+    let handler = |request: Request| async move { echo(&request) };
+    thread::spawn(move || {
+        async_server::run_with_limits(listener, Arc::new(handler), 1, server_limiter)
+    });
+
+    assert_limits(addr, &limiter);
+}
@@ -0,0 +1,274 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use web::http::{ChunkedReader, Request, Response};
+use web::server::serve_connection;
+use web::sse::{self, Event};
+use web::{MAX_PROGRESS_STREAMS, async_server, event_loop, site, site_async};
+
+// /events 先推一个事件，等测试放行后再推第二个；其余路径回普通响应
+fn streaming(gate: Receiver<()>) -> impl Fn(&Request) -> Response + Send + Sync + 'static {
+    let gate = Arc::new(Mutex::new(gate));
+    move |request: &Request| {
+        if request.path != "/events" {
+            return Response::text(200, "plain");
+        }
+        let (response, events) = sse::channel();
+        let gate = Arc::clone(&gate);
+        thread::spawn(move || {
+            events.send(&Event::new("one").event("tick")).unwrap();
+            gate.lock().unwrap().recv().unwrap();
+            events.send(&Event::new("two")).unwrap();
+        });
+        response
+    }
+}
+
+// /sleep/progress 的名额是全局的，用到它的测试依次运行
+static PROGRESS: Mutex<()> = Mutex::new(());
+
+// 读响应头，返回 (状态行, 头)
+fn read_head(reader: &mut impl BufRead) -> (String, Vec<String>) {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            return (status_line.trim_end().to_string(), headers);
+        }
+        headers.push(line.trim_end().to_string());
+    }
+}
+
+// 第一个事件要在第二个产生之前就收到，说明响应是边产生边发的；
+// 流结束后接着处理同一条连接上流水线发来的下一个请求
+fn assert_streams(addr: SocketAddr, gate: &Sender<()>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\nGET /plain HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+
+    let (status_line, headers) = read_head(&mut reader);
+    assert_eq!(status_line, "HTTP/1.1 200 OK");
+    assert!(headers.contains(&"Transfer-Encoding: chunked".to_string()));
+    assert!(headers.contains(&"Content-Type: text/event-stream".to_string()));
+
+    let mut body = ChunkedReader::new(&mut reader);
+    let mut first = Vec::new();
+    let mut buf = [0; 256];
+    while !first.ends_with(b"\n\n") {
+        let read = body.read(&mut buf).unwrap();
+        assert!(read > 0, "stream ended early");
+        first.extend_from_slice(&buf[..read]);
+    }
+    assert_eq!(first, b"event: tick\ndata: one\n\n");
+
+    gate.send(()).unwrap();
+    let mut rest = String::new();
+    body.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "data: two\n\n");
+
+    let (status_line, _) = read_head(&mut reader);
+    assert_eq!(status_line, "HTTP/1.1 200 OK");
+    let mut plain = [0; 5];
+    reader.read_exact(&mut plain).unwrap();
+    assert_eq!(&plain, b"plain");
+}
+
+#[test]
+fn encodes_events() {
+    let event = Event::new("line one\nline two")
+        .id("7")
+        .event("update\n")
+        .retry(Duration::from_secs(3));
+    assert_eq!(
+        String::from_utf8(event.to_bytes()).unwrap(),
+        "id: 7\nevent: update\nretry: 3000\ndata: line one\ndata: line two\n\n"
+    );
+    let json = Event::json(&[1, 2]).unwrap();
+    assert_eq!(json.to_bytes(), b"data: [1,2]\n\n");
+}
+
+#[test]
+fn channel_response_is_chunked_until_senders_drop() {
+    let (response, sender) = Response::channel(200);
+    let producer = thread::spawn(move || {
+        sender.send("hello").unwrap();
+        let second = sender.clone();
+        drop(sender);
+        second.send("world").unwrap();
+    });
+    let mut out = Vec::new();
+    response.write_to(&mut out).unwrap();
+    producer.join().unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"));
+    assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"));
+
+    // 客户端走了，发送端应该知道
+    let (response, sender) = Response::channel(200);
+    drop(response);
+    assert!(sender.is_closed());
+    let err = sender.send("late").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn streams_in_every_mode() {
+    // 阻塞模式
+    let (blocking_gate, gate) = mpsc::channel();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let blocking = listener.local_addr().unwrap();
+    let handler = streaming(gate);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve_connection(stream.unwrap(), &handler);
+        }
+    });
+
+    let (event_loop_gate, gate) = mpsc::channel();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let event_loop = listener.local_addr().unwrap();
+    let handler = Arc::new(streaming(gate));
+    thread::spawn(move || event_loop::run(listener, handler, 1));
+
+    let (async_gate, gate) = mpsc::channel();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let async_addr = listener.local_addr().unwrap();
+    let handler = Arc::new(streaming(gate));
+    let handler = move |request: Request| {
+        let response = handler(&request);
+        async move { response }
+    };
+    thread::spawn(move || async_server::run(listener, Arc::new(handler), 1));
+
+    assert_streams(blocking, &blocking_gate);
+    assert_streams(event_loop, &event_loop_gate);
+    assert_streams(async_addr, &async_gate);
+}
+
+#[test]
+fn event_loop_streams_while_handler_blocks() {
+    // 只有一个事件循环线程，/slow 的 handler 卡住时另一条连接上的事件照样推送
+    let (event_gate, gate) = mpsc::channel();
+    let events = streaming(gate);
+    let (slow_gate, slow) = mpsc::channel::<()>();
+    let slow = Mutex::new(slow);
+    let handler = move |request: &Request| {
+        if request.path == "/slow" {
+            slow.lock().unwrap().recv().unwrap();
+            return Response::text(200, "slow");
+        }
+        events(request)
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || event_loop::run(listener, Arc::new(handler), 1));
+
+    let mut events = TcpStream::connect(addr).unwrap();
+    events
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    events
+        .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(events);
+    read_head(&mut reader);
+    let mut body = ChunkedReader::new(&mut reader);
+    let mut first = [0; 23];
+    body.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"event: tick\ndata: one\n\n");
+
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    event_gate.send(()).unwrap();
+    let mut rest = String::new();
+    body.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "data: two\n\n");
+
+    slow_gate.send(()).unwrap();
+    let mut response = String::new();
+    slow.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nslow"), "{response}");
+}
+
+#[test]
+fn sleep_progress_resumes_from_last_event_id() {
+    let _progress = PROGRESS.lock().unwrap_or_else(|err| err.into_inner());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let event_loop = listener.local_addr().unwrap();
+    let handler = Arc::new(site(web::DOC_ROOT, None));
+    thread::spawn(move || event_loop::run(listener, handler, 1));
+
+    // 异步模式下事件由线程池上的任务发送
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let async_addr = listener.local_addr().unwrap();
+    let handler = Arc::new(site_async(web::DOC_ROOT, None));
+    thread::spawn(move || async_server::run(listener, handler, 1));
+
+    for addr in [event_loop, async_addr] {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /sleep/progress?seconds=2 HTTP/1.1\r\nLast-Event-ID: 1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        read_head(&mut reader);
+        let mut events = String::new();
+        ChunkedReader::new(&mut reader)
+            .read_to_string(&mut events)
+            .unwrap();
+        assert_eq!(
+            events,
+            "id: 2\nevent: progress\ndata: {\"elapsed\":2,\"total\":2}\n\n\
+             event: done\ndata: done\n\n"
+        );
+    }
+}
+
+#[test]
+fn sleep_progress_limits_concurrent_streams() {
+    let _progress = PROGRESS.lock().unwrap_or_else(|err| err.into_inner());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(site(web::DOC_ROOT, None));
+    thread::spawn(move || event_loop::run(listener, handler, 1));
+
+    let open = || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /sleep/progress?seconds=60 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let (status_line, _) = read_head(&mut reader);
+        (status_line, reader)
+    };
+
+    // 名额用完以后回 503，不再为新的事件流占用线程
+    let streams: Vec<_> = (0..MAX_PROGRESS_STREAMS).map(|_| open()).collect();
+    for (status_line, _) in &streams {
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+    }
+    assert_eq!(open().0, "HTTP/1.1 503 Service Unavailable");
+
+    // 客户端断开后，下一次发送失败时归还名额
+    drop(streams);
+    let started = Instant::now();
+    while open().0 != "HTTP/1.1 200 OK" {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,415 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+use web::thread_pool::{ExecuteError, JobError, RejectPolicy, Scheduler, ThreadPool};
+
+// 占住唯一的 worker，直到测试放行
+fn block_single_worker(pool: &ThreadPool) -> Arc<Barrier> {
+    let gate = Arc::new(Barrier::new(2));
+    let (started_tx, started_rx) = mpsc::channel();
+    let worker_gate = Arc::clone(&gate);
+    pool.execute(move || {
+        started_tx.send(()).unwrap();
+        worker_gate.wait();
+    })
+    .unwrap();
+    started_rx.recv().unwrap();
+    gate
+}
+
+#[test]
+fn execute_runs_all_jobs() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    {
+        let pool = ThreadPool::new(4);
+        for _ in 0..32 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 32);
+}
+
+#[test]
+fn reject_policy_returns_full() {
+    let pool = ThreadPool::bounded(1, 1, RejectPolicy::Reject);
+    let gate = block_single_worker(&pool);
+
+    pool.execute(|| {}).unwrap();
+    assert_eq!(pool.execute(|| {}), Err(ExecuteError::Full));
+
+    gate.wait();
+}
+
+#[test]
+fn drop_oldest_policy_evicts_queued_job() {
+    let ran = Arc::new(AtomicUsize::new(0));
+    {
+        let pool = ThreadPool::bounded(1, 1, RejectPolicy::DropOldest);
+        let gate = block_single_worker(&pool);
+
+        let first = Arc::clone(&ran);
+        pool.execute(move || {
+            first.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+        let second = Arc::clone(&ran);
+        pool.execute(move || {
+            second.fetch_add(10, Ordering::SeqCst);
+        })
+        .unwrap();
+        assert_eq!(pool.queued(), 1);
+
+        gate.wait();
+    }
+    assert_eq!(ran.load(Ordering::SeqCst), 10);
+}
+
+#[test]
+fn work_stealing_drop_oldest_evicts_globally_oldest_job() {
+    let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+    {
+        let pool = ThreadPool::builder()
+            .core_size(2)
+            .queue_capacity(3)
+            .reject_policy(RejectPolicy::DropOldest)
+            .scheduler(Scheduler::WorkStealing)
+            .build();
+        // 占住两个 worker
+        let gate = Arc::new(Barrier::new(3));
+        let (started_tx, started_rx) = mpsc::channel();
+        for _ in 0..2 {
+            let gate = Arc::clone(&gate);
+            let started_tx = started_tx.clone();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                gate.wait();
+            })
+            .unwrap();
+        }
+        started_rx.recv().unwrap();
+        started_rx.recv().unwrap();
+
+        // a、c 和 b 分在两条队列里，挤掉的应该是最早的 a
+        for name in ["a", "b", "c", "d"] {
+            let ran = Arc::clone(&ran);
+            pool.execute(move || ran.lock().unwrap().push(name))
+                .unwrap();
+        }
+        assert_eq!(pool.queued(), 3);
+        gate.wait();
+    }
+    let mut ran = ran.lock().unwrap().clone();
+    ran.sort();
+    assert_eq!(ran, ["b", "c", "d"]);
+}
+
+#[test]
+fn caller_runs_policy_executes_on_caller_thread() {
+    let pool = ThreadPool::bounded(1, 1, RejectPolicy::CallerRuns);
+    let gate = block_single_worker(&pool);
+    pool.execute(|| {}).unwrap();
+
+    let caller = thread::current().id();
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || tx.send(thread::current().id()).unwrap())
+        .unwrap();
+    assert_eq!(rx.recv().unwrap(), caller);
+
+    gate.wait();
+}
+
+#[test]
+fn block_policy_waits_for_free_slot() {
+    let pool = Arc::new(ThreadPool::bounded(1, 1, RejectPolicy::Block));
+    let gate = block_single_worker(&pool);
+    pool.execute(|| {}).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let blocked_pool = Arc::clone(&pool);
+    let submitter = thread::spawn(move || {
+        blocked_pool.execute(|| {}).unwrap();
+        tx.send(()).unwrap();
+    });
+
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    gate.wait();
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    submitter.join().unwrap();
+}
+
+fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("condition not met in time");
+}
+
+#[test]
+fn panicking_job_does_not_kill_worker() {
+    let pool = ThreadPool::new(1);
+    pool.execute(|| panic!("job failed")).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || tx.send(()).unwrap()).unwrap();
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert_eq!(pool.panicked_jobs(), 1);
+    assert_eq!(pool.worker_count(), 1);
+    assert_eq!(pool.respawned_workers(), 0);
+}
+
+// panic 的 payload 在 drop 时再次 panic，会让 worker 线程本身退出
+struct PanicOnDrop;
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        panic!("payload dropped");
+    }
+}
+
+#[test]
+fn dead_worker_is_respawned() {
+    let pool = ThreadPool::new(1);
+    pool.execute(|| std::panic::panic_any(PanicOnDrop)).unwrap();
+    wait_until(|| pool.respawned_workers() == 1);
+
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || tx.send(()).unwrap()).unwrap();
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(pool.worker_count(), 1);
+}
+
+#[test]
+fn elastic_pool_grows_under_load_and_shrinks_when_idle() {
+    let pool = ThreadPool::builder()
+        .core_size(1)
+        .max_size(3)
+        .keep_alive(Duration::from_millis(50))
+        .build();
+    let gate = Arc::new(Barrier::new(4));
+    for _ in 0..3 {
+        let gate = Arc::clone(&gate);
+        pool.execute(move || {
+            gate.wait();
+        })
+        .unwrap();
+    }
+
+    // 三个任务同时阻塞在 barrier 上，只有扩到 3 个线程才能放行
+    gate.wait();
+    assert_eq!(pool.worker_count(), 3);
+
+    wait_until(|| pool.worker_count() == 1);
+}
+
+#[test]
+fn workers_are_named_with_prefix() {
+    let pool = ThreadPool::builder()
+        .core_size(1)
+        .stack_size(256 * 1024)
+        .build();
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || {
+        tx.send(thread::current().name().map(String::from)).unwrap();
+    })
+    .unwrap();
+    assert_eq!(rx.recv().unwrap().as_deref(), Some("web-worker-0"));
+}
+
+#[test]
+fn spawn_returns_job_result() {
+    let pool = ThreadPool::new(2);
+    let handle = pool.spawn(|| 6 * 7).unwrap();
+    assert_eq!(handle.join(), Ok(42));
+
+    let handle = pool.spawn(|| -> u32 { panic!("boom") }).unwrap();
+    assert_eq!(handle.join(), Err(JobError::Panicked));
+}
+
+#[test]
+fn join_timeout_and_cancel() {
+    let pool = ThreadPool::new(1);
+    let gate = block_single_worker(&pool);
+
+    let queued = pool.spawn(|| "never runs").unwrap();
+    assert!(!queued.wait_timeout(Duration::from_millis(20)));
+    assert!(queued.cancel());
+    assert_eq!(queued.join(), Err(JobError::Cancelled));
+
+    let slow = pool.spawn(|| "done").unwrap();
+    assert_eq!(
+        pool.spawn(|| ())
+            .unwrap()
+            .join_timeout(Duration::from_millis(20)),
+        Err(JobError::TimedOut)
+    );
+
+    gate.wait();
+    assert_eq!(slow.join_timeout(Duration::from_secs(5)), Ok("done"));
+}
+
+#[test]
+fn job_handle_can_be_awaited() {
+    let pool = ThreadPool::new(1);
+    let handle = pool.spawn(|| String::from("async")).unwrap();
+    assert_eq!(
+        futures::executor::block_on(handle),
+        Ok(String::from("async"))
+    );
+}
+
+#[test]
+fn dropped_job_reports_dropped() {
+    let pool = ThreadPool::bounded(1, 1, RejectPolicy::DropOldest);
+    let gate = block_single_worker(&pool);
+
+    let evicted = pool.spawn(|| 1).unwrap();
+    let kept = pool.spawn(|| 2).unwrap();
+    assert_eq!(evicted.join(), Err(JobError::Dropped));
+
+    gate.wait();
+    assert_eq!(kept.join(), Ok(2));
+}
+
+#[test]
+fn scope_jobs_can_borrow_local_data() {
+    let pool = ThreadPool::new(4);
+    let mut totals = [0u64; 4];
+    let numbers: Vec<u64> = (1..=100).collect();
+
+    pool.scope(|scope| {
+        for (chunk, total) in numbers.chunks(25).zip(totals.iter_mut()) {
+            scope.spawn(move || *total = chunk.iter().sum()).unwrap();
+        }
+    });
+
+    assert_eq!(totals.iter().sum::<u64>(), 5050);
+}
+
+// 析构时往借用的 Vec 里记一笔
+struct Touch<'a>(&'a Mutex<Vec<&'static str>>, &'static str);
+
+impl Drop for Touch<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().push(self.1);
+    }
+}
+
+#[test]
+fn unrun_scoped_jobs_drop_captures_before_scope_returns() {
+    let pool = ThreadPool::bounded(1, 1, RejectPolicy::DropOldest);
+    let gate = block_single_worker(&pool);
+    let dropped = Mutex::new(Vec::new());
+
+    pool.scope(|scope| {
+        let evicted = Touch(&dropped, "evicted");
+        scope.spawn(move || drop(evicted)).unwrap();
+        // 挤掉上一个还没执行的任务
+        let kept = Touch(&dropped, "kept");
+        scope.spawn(move || drop(kept)).unwrap();
+        assert_eq!(*dropped.lock().unwrap(), ["evicted"]);
+        gate.wait();
+    });
+    assert_eq!(*dropped.lock().unwrap(), ["evicted", "kept"]);
+
+    // Reject 时提交失败的任务当场丢掉
+    let pool = ThreadPool::bounded(1, 1, RejectPolicy::Reject);
+    let gate = block_single_worker(&pool);
+    let dropped = Mutex::new(Vec::new());
+    pool.scope(|scope| {
+        let queued = Touch(&dropped, "queued");
+        scope.spawn(move || drop(queued)).unwrap();
+        let rejected = Touch(&dropped, "rejected");
+        assert_eq!(scope.spawn(move || drop(rejected)), Err(ExecuteError::Full));
+        assert_eq!(*dropped.lock().unwrap(), ["rejected"]);
+        gate.wait();
+    });
+    assert_eq!(*dropped.lock().unwrap(), ["rejected", "queued"]);
+}
+
+#[test]
+fn scoped_job_panics_are_counted() {
+    let pool = ThreadPool::new(2);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.scope(|scope| {
+            scope.spawn(|| panic!("boom")).unwrap();
+            scope.spawn(|| {}).unwrap();
+        })
+    }));
+    assert!(result.is_err());
+    wait_until(|| pool.panicked_jobs() == 1);
+    assert_eq!(pool.stats().failed_jobs, 1);
+}
+
+#[test]
+fn work_stealing_scheduler_runs_all_jobs() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    {
+        let pool = ThreadPool::builder()
+            .core_size(4)
+            .scheduler(Scheduler::WorkStealing)
+            .build();
+        let handles: Vec<_> = (0..64u64)
+            .map(|i| {
+                let counter = Arc::clone(&counter);
+                pool.spawn(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    i * 2
+                })
+                .unwrap()
+            })
+            .collect();
+        let total: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(total, 63 * 64);
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 64);
+}
+
+#[test]
+fn work_stealing_scheduler_respects_capacity() {
+    let pool = ThreadPool::builder()
+        .core_size(1)
+        .queue_capacity(1)
+        .reject_policy(RejectPolicy::Reject)
+        .scheduler(Scheduler::WorkStealing)
+        .build();
+    let gate = block_single_worker(&pool);
+
+    pool.execute(|| {}).unwrap();
+    assert_eq!(pool.execute(|| {}), Err(ExecuteError::Full));
+    assert_eq!(pool.queued(), 1);
+
+    gate.wait();
+}
+
+#[test]
+fn stats_track_jobs_and_latency() {
+    let pool = ThreadPool::new(2);
+    pool.spawn(|| thread::sleep(Duration::from_millis(30)))
+        .unwrap()
+        .join()
+        .unwrap();
+    pool.spawn(|| panic!("boom")).unwrap().join().unwrap_err();
+    wait_until(|| pool.stats().failed_jobs == 1);
+
+    let stats = pool.stats();
+    assert_eq!(stats.workers, 2);
+    assert_eq!(stats.completed_jobs, 1);
+    assert_eq!(stats.queued_jobs, 0);
+    assert_eq!(stats.latency.count(), 2);
+    assert!(stats.latency.percentile(1.0).unwrap() >= Duration::from_millis(30));
+
+    let handle = pool.stats_handle();
+    let gate = block_single_worker(&pool);
+    wait_until(|| handle.stats().active_workers == 1);
+    gate.wait();
+}
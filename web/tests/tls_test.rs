@@ -0,0 +1,205 @@
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs, process, thread};
+use web::config::Config;
+use web::http::{Request, Response};
+use web::server::{Limiter, serve_tls_connection};
+use web::tls::{ServerConfig, TlsConfigBuilder};
+use web::{async_server, event_loop};
+
+fn echo(request: &Request) -> Response {
+    Response::text(200, format!("{} {}", request.method, request.path))
+}
+
+struct TestCerts {
+    localhost: CertificateDer<'static>,
+    wildcard: CertificateDer<'static>,
+    config: Arc<ServerConfig>,
+}
+
+// 生成两张自签名证书写到临时目录：默认证书给 localhost，另一张给 *.example.test
+fn test_certs(name: &str) -> TestCerts {
+    let dir = env::temp_dir().join(format!("web-tls-{}-{name}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let write = |file: &str, hosts: &[&str]| -> (CertificateDer<'static>, PathBuf, PathBuf) {
+        let hosts = hosts
+            .iter()
+            .map(|host| host.to_string())
+            .collect::<Vec<_>>();
+        let generated = rcgen::generate_simple_self_signed(hosts).unwrap();
+        let cert_path = dir.join(format!("{file}.crt"));
+        let key_path = dir.join(format!("{file}.key"));
+        fs::write(&cert_path, generated.cert.pem()).unwrap();
+        fs::write(&key_path, generated.key_pair.serialize_pem()).unwrap();
+        (generated.cert.der().clone(), cert_path, key_path)
+    };
+
+    let (localhost, cert, key) = write("localhost", &["localhost"]);
+    let (wildcard, wildcard_cert, wildcard_key) = write("wildcard", &["*.example.test"]);
+    // 和部署时一样从配置文件读证书
+    let settings = Config::from_toml(&format!(
+        "[tls]\ncert = '{}'\nkey = '{}'\n\
+         [[tls.sni]]\nhost = '*.example.test'\ncert = '{}'\nkey = '{}'\n",
+        cert.display(),
+        key.display(),
+        wildcard_cert.display(),
+        wildcard_key.display()
+    ))
+    .unwrap()
+    .tls
+    .unwrap();
+    let config = settings.builder().build().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    TestCerts {
+        localhost,
+        wildcard,
+        config,
+    }
+}
+
+fn client_config(certs: &TestCerts) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(certs.localhost.clone()).unwrap();
+    roots.add(certs.wildcard.clone()).unwrap();
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+type TlsClient = BufReader<StreamOwned<ClientConnection, TcpStream>>;
+
+fn connect(addr: SocketAddr, server_name: &str, config: &Arc<ClientConfig>) -> TlsClient {
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    let conn = ClientConnection::new(Arc::clone(config), name).unwrap();
+    BufReader::new(StreamOwned::new(conn, TcpStream::connect(addr).unwrap()))
+}
+
+fn read_response(reader: &mut TlsClient) -> (String, String) {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (
+        status_line.trim_end().to_string(),
+        String::from_utf8(body).unwrap(),
+    )
+}
+
+// 长连接上发两个请求，检查 ALPN、SNI 选中的证书和 close_notify
+fn assert_https(addr: SocketAddr, certs: &TestCerts) {
+    let config = client_config(certs);
+    let mut client = connect(addr, "localhost", &config);
+
+    client
+        .get_mut()
+        .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    assert_eq!(
+        read_response(&mut client),
+        ("HTTP/1.1 200 OK".to_string(), "GET /a".to_string())
+    );
+    let conn = &client.get_ref().conn;
+    assert_eq!(conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+    assert_eq!(conn.peer_certificates().unwrap()[0], certs.localhost);
+
+    client
+        .get_mut()
+        .write_all(b"POST /b HTTP/1.1\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc")
+        .unwrap();
+    assert_eq!(read_response(&mut client).1, "POST /b");
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    let mut client = connect(addr, "api.example.test", &config);
+    client
+        .get_mut()
+        .write_all(b"GET /c HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut client).1, "GET /c");
+    let peer = client.get_ref().conn.peer_certificates().unwrap()[0].clone();
+    assert_eq!(peer, certs.wildcard);
+}
+
+#[test]
+fn builder_requires_certificate() {
+    assert!(TlsConfigBuilder::new().build().is_err());
+    let missing = Path::new("/nonexistent/web.crt");
+    assert!(
+        TlsConfigBuilder::new()
+            .cert(missing, missing)
+            .build()
+            .is_err()
+    );
+}
+
+#[test]
+fn blocking_connection_serves_https() {
+    let certs = test_certs("blocking");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::clone(&certs.config);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let config = Arc::clone(&config);
+            let stream = stream.unwrap();
+            thread::spawn(move || {
+                serve_tls_connection(stream, &config, &echo, &Limiter::default())
+            });
+        }
+    });
+
+    assert_https(addr, &certs);
+}
+
+#[test]
+fn event_loop_serves_https() {
+    let certs = test_certs("event-loop");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::clone(&certs.config);
+    thread::spawn(move || {
+        event_loop::run_tls(listener, Arc::new(echo), 2, Limiter::default(), config)
+    });
+
+    assert_https(addr, &certs);
+}
+
+#[test]
+fn async_server_serves_https() {
+    let certs = test_certs("async");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::clone(&certs.config);
+    let handler = |request: Request| async move { echo(&request) };
+    thread::spawn(move || {
+        async_server::run_tls(listener, Arc::new(handler), 1, Limiter::default(), config)
+    });
+
+    assert_https(addr, &certs);
+}
@@ -0,0 +1,104 @@
+use futures::executor::block_on;
+use std::sync::Arc;
+use std::{env, fs, process};
+use web::async_server::AsyncHandler;
+use web::config::Config;
+use web::http::{Handler, Request, Response};
+use web::vhost::{VirtualHosts, host_name};
+
+fn get(host: Option<&str>, path: &str) -> Request {
+    Request {
+        method: "GET".to_string(),
+        path: path.to_string(),
+        version: "HTTP/1.1".to_string(),
+        headers: host
+            .map(|host| vec![("Host".to_string(), host.to_string())])
+            .unwrap_or_default(),
+        ..Request::default()
+    }
+}
+
+fn named(name: &'static str) -> Arc<dyn Handler> {
+    Arc::new(move |_: &Request| Response::text(200, name))
+}
+
+#[test]
+fn strips_port_from_host_header() {
+    assert_eq!(host_name("Example.COM:8080"), "example.com");
+    assert_eq!(host_name("example.com."), "example.com");
+    assert_eq!(host_name("[::1]:7878"), "[::1]");
+    assert_eq!(host_name("[::1]"), "[::1]");
+    assert_eq!(host_name("127.0.0.1"), "127.0.0.1");
+}
+
+#[test]
+fn selects_exact_then_wildcard_then_default() {
+    let hosts = VirtualHosts::new(named("default"))
+        .host("example.com", named("apex"))
+        .host("*.example.com", named("any-sub"))
+        .host("*.api.example.com", named("api-sub"))
+        .host("WWW.Example.com", named("www"));
+
+    let body = |host: Option<&str>| hosts.handle(&get(host, "/")).body;
+    assert_eq!(body(Some("example.com:7878")), b"apex");
+    assert_eq!(body(Some("www.example.com")), b"www");
+    assert_eq!(body(Some("blog.example.com")), b"any-sub");
+    assert_eq!(body(Some("a.b.example.com")), b"any-sub");
+    assert_eq!(body(Some("v1.api.example.com")), b"api-sub");
+    assert_eq!(body(Some("notexample.com")), b"default");
+    assert_eq!(body(Some("other.org")), b"default");
+    assert_eq!(body(None), b"default");
+}
+
+#[test]
+fn config_maps_hosts_to_doc_roots() {
+    let root = env::temp_dir().join(format!("web-vhost-{}", process::id()));
+    let blog = root.join("blog");
+    fs::create_dir_all(&blog).unwrap();
+    fs::write(blog.join("hello.html"), "blog home").unwrap();
+    fs::write(blog.join("post.html"), "blog post").unwrap();
+
+    let config = Config::from_toml(&format!(
+        r#"
+        [[vhost]]
+        names = ["blog.local", "*.blog.local"]
+        doc_root = "{}"
+        "#,
+        blog.display()
+    ))
+    .unwrap();
+    config.validate().unwrap();
+
+    let cache = config.file_cache();
+    let hosts = config.virtual_hosts(cache.as_ref());
+    assert_eq!(
+        hosts.handle(&get(Some("blog.local"), "/")).body,
+        b"blog home"
+    );
+    let post = hosts.handle(&get(Some("en.blog.local"), "/post.html"));
+    assert_eq!(post.body, b"blog post");
+    let missing = hosts.handle(&get(Some("blog.local"), "/missing.html"));
+    assert_eq!(
+        (missing.status, missing.body.into_vec()),
+        (404, b"Not Found".to_vec())
+    );
+    // 默认站点用顶层的 doc_root
+    let default = hosts.handle(&get(Some("localhost"), "/"));
+    assert_eq!(default.body, fs::read("public/hello.html").unwrap());
+    assert_eq!(
+        hosts.handle(&get(Some("localhost"), "/post.html")).status,
+        404
+    );
+
+    let hosts = config.virtual_hosts_async(None);
+    let response = block_on(AsyncHandler::handle(&hosts, get(Some("blog.local"), "/")));
+    assert_eq!(response.body, b"blog home");
+
+    assert!(
+        Config::from_toml("[[vhost]]\nnames = []\ndoc_root = \"x\"")
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    fs::remove_dir_all(&root).unwrap();
+}